@@ -0,0 +1,307 @@
+use clap::{Args, Parser, Subcommand};
+use prost_types::FileDescriptorSet;
+use protobuf_gen::config::Config;
+use protobuf_gen::rules::Rules;
+use protobuf_gen::script::Script;
+use protobuf_gen::{render_set, ProtobufString, RenderSetOptions};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Regenerates and formats .proto files from their compiled descriptors.
+#[derive(Parser)]
+#[command(name = "protobuf-gen", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile the inputs and print or write the regenerated .proto files.
+    Render {
+        #[command(flatten)]
+        common: Common,
+        /// Write each file under this directory at its import path instead
+        /// of printing it.
+        #[arg(short, long)]
+        out_dir: Option<PathBuf>,
+        /// With --out-dir, remove the .proto files under it that were not
+        /// rendered.
+        #[arg(long, requires = "out_dir")]
+        remove_stale: bool,
+    },
+    /// Rewrite the inputs in place in canonical form.
+    Fmt {
+        #[command(flatten)]
+        common: Common,
+        /// Don't write anything; print a diff and exit with status 1 if any
+        /// file is not in canonical form.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Run a Rhai script over the compiled inputs and rewrite them in place
+    /// with the result, as long as nothing written in them would be lost.
+    Transform {
+        #[command(flatten)]
+        common: Common,
+        /// The script, which defines functions such as `fn field(cx)` that
+        /// are called for each element of the inputs.
+        #[arg(long)]
+        script: PathBuf,
+        /// Write each file under this directory at its import path instead
+        /// of rewriting the inputs.
+        #[arg(short, long)]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct Common {
+    /// The .proto files to process.
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// Directory to search for imports, before the configured ones; inputs
+    /// must reside in one of them. Defaults to the current directory.
+    #[arg(short = 'I', long = "include")]
+    includes: Vec<PathBuf>,
+    /// Report what would be written without touching any file.
+    #[arg(long)]
+    dry_run: bool,
+    /// Config file to use instead of the protobuf-gen.toml found by walking
+    /// up from the first input.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Rewrite rules file (TOML, or YAML with a .yaml extension) to apply
+    /// before rendering, after those in the config; may be repeated.
+    #[arg(long = "rules")]
+    rules: Vec<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(err) => {
+            eprintln!("protobuf-gen: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+// Returns `Ok(false)` when `fmt --check` found files that are not canonical.
+fn run(cli: Cli) -> io::Result<bool> {
+    match cli.command {
+        Command::Render {
+            common,
+            out_dir: Some(out_dir),
+            remove_stale,
+        } => {
+            let (config, descriptor_set, inputs) = compile(&common)?;
+            let options = RenderSetOptions::new()
+                .format(config.format)
+                .files(inputs.into_iter().map(|(_, name)| name))
+                .only_changed(true)
+                .remove_stale(remove_stale)
+                .dry_run(common.dry_run);
+            let report = render_set(&descriptor_set, &out_dir, &options)?;
+            if common.dry_run {
+                for path in &report.written {
+                    println!("would write {}", path.display());
+                }
+                for path in &report.removed {
+                    println!("would remove {}", path.display());
+                }
+            }
+            Ok(true)
+        }
+        Command::Render {
+            common,
+            out_dir: None,
+            ..
+        } => {
+            let rendered = render(&common, false)?;
+            for (path, _, content) in &rendered {
+                if rendered.len() > 1 {
+                    println!("// {}", path.display());
+                }
+                print!("{}", content);
+            }
+            Ok(true)
+        }
+        Command::Fmt { common, check } => {
+            let mut canonical = true;
+            for (path, _, content) in render(&common, true)? {
+                let original = fs::read_to_string(&path)?;
+                if original == content {
+                    continue;
+                }
+
+                canonical = false;
+                if check {
+                    let path = path.display().to_string();
+                    print!(
+                        "{}",
+                        similar::TextDiff::from_lines(&original, &content)
+                            .unified_diff()
+                            .header(&path, &path)
+                    );
+                } else if common.dry_run {
+                    println!("would reformat {}", path.display());
+                } else {
+                    fs::write(&path, content)?;
+                }
+            }
+            Ok(canonical || !check)
+        }
+        Command::Transform {
+            common,
+            script,
+            out_dir,
+        } => {
+            let script = Script::from_file(&script)?;
+            let (config, mut descriptor_set, inputs) = compile(&common)?;
+            script.run(&mut descriptor_set)?;
+
+            if let Some(out_dir) = out_dir {
+                let options = RenderSetOptions::new()
+                    .format(config.format)
+                    .files(inputs.into_iter().map(|(_, name)| name))
+                    .only_changed(true)
+                    .dry_run(common.dry_run);
+                let report = render_set(&descriptor_set, &out_dir, &options)?;
+                if common.dry_run {
+                    for path in &report.written {
+                        println!("would write {}", path.display());
+                    }
+                }
+                return Ok(true);
+            }
+
+            for (path, _, content) in render_inputs(&config, &descriptor_set, inputs, true)? {
+                if fs::read_to_string(&path)? == content {
+                    continue;
+                }
+                if common.dry_run {
+                    println!("would rewrite {}", path.display());
+                } else {
+                    fs::write(&path, content)?;
+                }
+            }
+            Ok(true)
+        }
+    }
+}
+
+// Compiles all inputs in one protoc invocation and renders each of them,
+// returning `(input path, import path, rendered text)`.
+fn render(common: &Common, lossless: bool) -> io::Result<Vec<(PathBuf, String, String)>> {
+    let (config, descriptor_set, inputs) = compile(common)?;
+    render_inputs(&config, &descriptor_set, inputs, lossless)
+}
+
+// Renders each input from the compiled `descriptor_set`. With `lossless`,
+// which is required before rewriting an input, everything written in an
+// input is rendered back, and the first input for which that is impossible
+// fails.
+fn render_inputs(
+    config: &Config,
+    descriptor_set: &FileDescriptorSet,
+    inputs: Inputs,
+    lossless: bool,
+) -> io::Result<Vec<(PathBuf, String, String)>> {
+    let format = match lossless {
+        true => config.format.clone().lossless(true),
+        false => config.format.clone(),
+    };
+    let mut rendered = Vec::with_capacity(inputs.len());
+    for (path, name) in inputs {
+        let file = descriptor_set
+            .file
+            .iter()
+            .find(|f| f.name() == name)
+            .ok_or_else(|| other(format!("{}: missing from protoc output", name)))?;
+        if lossless {
+            protobuf_gen::ast::check_lossless(file)?;
+        }
+        let content = file.to_protobuf_with(file, &format);
+        rendered.push((path, name, content));
+    }
+    Ok(rendered)
+}
+
+// `(input path, import path)` of each input.
+type Inputs = Vec<(PathBuf, String)>;
+
+// Compiles all inputs in one protoc invocation, returning the config and
+// the descriptor set along with the inputs.
+fn compile(common: &Common) -> io::Result<(Config, FileDescriptorSet, Inputs)> {
+    let mut config = match common.config {
+        Some(ref path) => Config::from_file(path)?,
+        None => Config::discover(&common.files[0])?
+            .map(|(_, config)| config)
+            .unwrap_or_default(),
+    };
+
+    let mut includes = common.includes.clone();
+    includes.append(&mut config.includes);
+    if includes.is_empty() {
+        includes.push(PathBuf::from("."));
+    }
+
+    // protoc only matches inputs to include paths by exact prefix, so use
+    // canonical paths on both sides.
+    let includes = includes
+        .iter()
+        .map(|p| canonicalize(p))
+        .collect::<io::Result<Vec<_>>>()?;
+    let files = common
+        .files
+        .iter()
+        .map(|p| canonicalize(p))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let names = files
+        .iter()
+        .map(|file| import_path(file, &includes))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut descriptor_set = config.read_files(&files, &includes)?;
+    let mut rules = config.rules()?;
+    for path in &common.rules {
+        rules.extend(Rules::from_file(path)?);
+    }
+    rules.apply(&mut descriptor_set)?;
+
+    let inputs = common.files.iter().cloned().zip(names).collect();
+    Ok((config, descriptor_set, inputs))
+}
+
+// The name protoc gives `file`: its path relative to the first include
+// directory containing it, with `/` separators.
+fn import_path(file: &Path, includes: &[PathBuf]) -> io::Result<String> {
+    for include in includes {
+        if let Ok(relative) = file.strip_prefix(include) {
+            let components: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            return Ok(components.join("/"));
+        }
+    }
+
+    Err(other(format!(
+        "{}: file does not reside within any include path",
+        file.display()
+    )))
+}
+
+fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    path.canonicalize()
+        .map_err(|err| other(format!("{}: {}", path.display(), err)))
+}
+
+fn other(msg: String) -> io::Error {
+    io::Error::other(msg)
+}
@@ -0,0 +1,242 @@
+use std::fs;
+use std::process::Command;
+
+const CANONICAL: &str =
+    "syntax = \"proto3\";\n\npackage acme;\n\n\nmessage User {\n  string name = 1;\n}\n";
+const MESSY: &str = "syntax = \"proto3\";\npackage acme;\nmessage User { string name = 1; }\n";
+
+fn protobuf_gen(dir: &std::path::Path, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_protobuf-gen"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn fmt_check_reports_and_fmt_fixes() {
+    let tmp = tempfile::tempdir().unwrap();
+    fs::create_dir(tmp.path().join("protos")).unwrap();
+    let proto = tmp.path().join("protos/user.proto");
+    fs::write(&proto, MESSY).unwrap();
+
+    let output = protobuf_gen(
+        tmp.path(),
+        &["fmt", "--check", "-I", "protos", "protos/user.proto"],
+    );
+    assert_eq!(Some(1), output.status.code());
+    let diff = String::from_utf8(output.stdout).unwrap();
+    assert!(diff.starts_with("--- protos/user.proto\n+++ protos/user.proto\n"));
+    assert!(diff.contains("-message User { string name = 1; }\n"));
+    assert_eq!(MESSY, fs::read_to_string(&proto).unwrap());
+
+    let output = protobuf_gen(
+        tmp.path(),
+        &["fmt", "--dry-run", "-I", "protos", "protos/user.proto"],
+    );
+    assert!(output.status.success());
+    assert_eq!(MESSY, fs::read_to_string(&proto).unwrap());
+
+    let output = protobuf_gen(tmp.path(), &["fmt", "-I", "protos", "protos/user.proto"]);
+    assert!(output.status.success());
+    assert_eq!(CANONICAL, fs::read_to_string(&proto).unwrap());
+
+    let output = protobuf_gen(
+        tmp.path(),
+        &["fmt", "--check", "-I", "protos", "protos/user.proto"],
+    );
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn fmt_keeps_options_and_extensions() {
+    let tmp = tempfile::tempdir().unwrap();
+    let proto = tmp.path().join("user.proto");
+    fs::write(
+        &proto,
+        "syntax = \"proto2\";\npackage acme;\noption go_package = \"example.com/acme\";\nmessage User {\n  optional string name = 1;\n  extensions 100 to 199;\n}\n// Extra fields.\nextend User {\n  optional int32 age = 100; // Years.\n}\n",
+    )
+    .unwrap();
+
+    let output = protobuf_gen(tmp.path(), &["fmt", "user.proto"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        "syntax = \"proto2\";\n\npackage acme;\n\noption go_package = \"example.com/acme\";\n\nmessage User {\n  optional string name = 1;\n  extensions 100 to 199;\n}\n\n// Extra fields.\nextend .acme.User {\n  optional int32 age = 100;  // Years.\n}\n",
+        fs::read_to_string(&proto).unwrap()
+    );
+    let output = protobuf_gen(tmp.path(), &["fmt", "--check", "user.proto"]);
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn fmt_refuses_lossy_rewrites() {
+    let tmp = tempfile::tempdir().unwrap();
+    let proto = tmp.path().join("legacy.proto");
+    let source = "syntax = \"proto2\";\nmessage Legacy {\n  optional group Data = 1 {\n    optional int32 x = 2;\n  }\n}\n";
+    fs::write(&proto, source).unwrap();
+
+    for args in [
+        &["fmt", "legacy.proto"][..],
+        &["fmt", "--check", "legacy.proto"],
+    ] {
+        let output = protobuf_gen(tmp.path(), args);
+        assert_eq!(Some(2), output.status.code());
+        assert_eq!(
+            "protobuf-gen: legacy.proto: group field Legacy.data cannot be rendered\n",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    assert_eq!(source, fs::read_to_string(&proto).unwrap());
+}
+
+#[test]
+fn render_prints_or_writes_to_out_dir() {
+    let tmp = tempfile::tempdir().unwrap();
+    fs::create_dir_all(tmp.path().join("protos/acme")).unwrap();
+    fs::write(tmp.path().join("protos/acme/user.proto"), MESSY).unwrap();
+
+    let output = protobuf_gen(
+        tmp.path(),
+        &["render", "-I", "protos", "protos/acme/user.proto"],
+    );
+    assert!(output.status.success());
+    assert_eq!(CANONICAL, String::from_utf8(output.stdout).unwrap());
+
+    let output = protobuf_gen(
+        tmp.path(),
+        &[
+            "render",
+            "-I",
+            "protos",
+            "-o",
+            "out",
+            "protos/acme/user.proto",
+        ],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        CANONICAL,
+        fs::read_to_string(tmp.path().join("out/acme/user.proto")).unwrap()
+    );
+
+    fs::create_dir_all(tmp.path().join("out/old")).unwrap();
+    fs::write(tmp.path().join("out/old/gone.proto"), MESSY).unwrap();
+    let output = protobuf_gen(
+        tmp.path(),
+        &[
+            "render",
+            "-I",
+            "protos",
+            "-o",
+            "out",
+            "--remove-stale",
+            "protos/acme/user.proto",
+        ],
+    );
+    assert!(output.status.success());
+    assert!(!tmp.path().join("out/old").exists());
+    assert!(tmp.path().join("out/acme/user.proto").exists());
+
+    let output = protobuf_gen(
+        tmp.path(),
+        &["render", "-I", "elsewhere", "protos/acme/user.proto"],
+    );
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[test]
+fn fmt_uses_project_config() {
+    let tmp = tempfile::tempdir().unwrap();
+    fs::create_dir_all(tmp.path().join("protos/acme")).unwrap();
+    fs::write(
+        tmp.path().join("protobuf-gen.toml"),
+        "includes = [\"protos\"]\n",
+    )
+    .unwrap();
+    let proto = tmp.path().join("protos/acme/user.proto");
+    fs::write(&proto, MESSY).unwrap();
+
+    let output = protobuf_gen(tmp.path(), &["fmt", "protos/acme/user.proto"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(CANONICAL, fs::read_to_string(&proto).unwrap());
+}
+
+#[test]
+fn fmt_applies_rules() {
+    let tmp = tempfile::tempdir().unwrap();
+    fs::write(
+        tmp.path().join("protobuf-gen.toml"),
+        "rules = [\"rules.toml\"]\n",
+    )
+    .unwrap();
+    fs::write(
+        tmp.path().join("rules.toml"),
+        "[[rule]]\nfield = \"name\"\noptional = true\n",
+    )
+    .unwrap();
+    fs::write(
+        tmp.path().join("deprecate.yaml"),
+        "rule:\n  - message: User\n    deprecated: true\n",
+    )
+    .unwrap();
+    let proto = tmp.path().join("user.proto");
+    fs::write(&proto, MESSY).unwrap();
+
+    let output = protobuf_gen(
+        tmp.path(),
+        &["fmt", "--rules", "deprecate.yaml", "user.proto"],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        "syntax = \"proto3\";\n\npackage acme;\n\n\nmessage User {\n  optional string name = 1 [deprecated = true];\n}\n",
+        fs::read_to_string(&proto).unwrap()
+    );
+}
+
+#[test]
+fn transform_runs_scripts() {
+    let tmp = tempfile::tempdir().unwrap();
+    fs::write(
+        tmp.path().join("fix.rhai"),
+        "fn field(cx) {\n    if this.type == \"string\" {\n        this.optional = true;\n    }\n}\n",
+    )
+    .unwrap();
+    let proto = tmp.path().join("user.proto");
+    fs::write(&proto, MESSY).unwrap();
+
+    let output = protobuf_gen(
+        tmp.path(),
+        &["transform", "--script", "fix.rhai", "user.proto"],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        "syntax = \"proto3\";\n\npackage acme;\n\n\nmessage User {\n  optional string name = 1;\n}\n",
+        fs::read_to_string(&proto).unwrap()
+    );
+
+    let legacy = tmp.path().join("legacy.proto");
+    let source = "syntax = \"proto2\";\nmessage Legacy {\n  optional group Data = 1 {\n    optional string x = 2;\n  }\n}\n";
+    fs::write(&legacy, source).unwrap();
+    let output = protobuf_gen(
+        tmp.path(),
+        &["transform", "--script", "fix.rhai", "legacy.proto"],
+    );
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!(
+        "protobuf-gen: legacy.proto: group field Legacy.data cannot be rendered\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(source, fs::read_to_string(&legacy).unwrap());
+
+    fs::write(tmp.path().join("fix.rhai"), "fn field() {}\n").unwrap();
+    let output = protobuf_gen(
+        tmp.path(),
+        &["transform", "--script", "fix.rhai", "user.proto"],
+    );
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!(
+        "protobuf-gen: fix.rhai: `field` must take one parameter, the context\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -1,3 +1,5 @@
+use protobuf_gen::builder::make_proto3_optional;
+use protobuf_gen::visit::{visit_file_mut, Context, VisitMut};
 use protobuf_gen::{read_files, ProtobufString};
 use std::path::Path;
 use std::fs;
@@ -32,23 +34,24 @@ fn main() {
 
 fn convert_strings_to_optional(file_descriptor: &prost_types::FileDescriptorProto) -> String {
     let mut modified_descriptor = file_descriptor.clone();
-
-    for message in &mut modified_descriptor.message_type {
-        convert_fields_in_message(message);
-    }
-
-    modified_descriptor.to_protobuf(modified_descriptor.clone())
+    visit_file_mut(&mut StringsToOptional, &mut modified_descriptor);
+    modified_descriptor.to_protobuf(&modified_descriptor)
 }
 
-fn convert_fields_in_message(message: &mut prost_types::DescriptorProto) {
-    for field in &mut message.field {
-        if field.r#type() == prost_types::field_descriptor_proto::Type::String &&
-           field.label() != prost_types::field_descriptor_proto::Label::Repeated {
-            field.proto3_optional = Some(true);
+// Visits every message, including nested ones, and gives its singular string
+// fields presence, each with the synthetic oneof protoc would add. Fields in
+// a oneof already have presence, so they are left alone.
+struct StringsToOptional;
+
+impl VisitMut for StringsToOptional {
+    fn message(&mut self, message: &mut prost_types::DescriptorProto, _: &Context) {
+        for i in 0..message.field.len() {
+            let field = &message.field[i];
+            if field.r#type() == prost_types::field_descriptor_proto::Type::String &&
+               field.label() != prost_types::field_descriptor_proto::Label::Repeated &&
+               field.oneof_index.is_none() {
+                make_proto3_optional(message, i);
+            }
         }
     }
-
-    for nested_message in &mut message.nested_type {
-        convert_fields_in_message(nested_message);
-    }
 }
@@ -0,0 +1,665 @@
+//! The `proto_file!` macro, which writes a `FileDescriptorProto` inline in
+//! Rust, in `.proto` syntax:
+//!
+//! ```
+//! use protobuf_gen_macros::proto_file;
+//!
+//! let file = proto_file! {
+//!     "user.proto",
+//!     syntax = "proto3";
+//!     package acme;
+//!
+//!     message User {
+//!         string name = 1;
+//!         map<string, string> labels = 2;
+//!     }
+//! };
+//! assert_eq!(Some(".acme.User.LabelsEntry"), file.message_type[0].field[1].type_name.as_deref());
+//! ```
+//!
+//! The file name, a string before the first statement, is optional. The
+//! source is checked when the macro is expanded, and expands to a
+//! `protobuf_gen::FileBuilder`, which resolves type names and fills in map
+//! entries, synthetic oneofs and `json_name` like protoc does, so the crate
+//! using the macro depends on `protobuf-gen` as well.
+//!
+//! Type names must resolve to a message or enum defined in the file, field
+//! numbers must be unique within a message, and the first value of a
+//! proto3 enum must be 0; otherwise the macro fails to compile. A file with
+//! imports may also use types it does not define, which, as with the
+//! builder, are taken to be messages.
+//!
+//! Messages, fields, oneofs, maps, enums, services and `reserved`
+//! statements are supported, as are the `json_name`, `default`,
+//! `deprecated` and `packed` field options. Other options, extensions and
+//! groups are not.
+
+extern crate proc_macro;
+
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use std::collections::{HashMap, HashSet};
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::{braced, bracketed, parenthesized, Ident, LitInt, LitStr, Token};
+
+/// Expands `.proto` source into an expression of type
+/// `prost_types::FileDescriptorProto`.
+#[proc_macro]
+pub fn proto_file(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    match syn::parse::<File>(input) {
+        Ok(File(tokens)) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct File(TokenStream);
+
+// The state of parsing a file: the builder calls so far, whether the file
+// is proto3, and what is needed to check it once it is parsed.
+struct Parser {
+    calls: TokenStream,
+    proto3: bool,
+    package: String,
+    imports: bool,
+    // The names of the messages enclosing the definition being parsed.
+    scope: Vec<String>,
+    // The messages and enums defined, by their names within the package.
+    definitions: HashSet<String>,
+    // The type names used, with the scope they are used in.
+    references: Vec<(Vec<String>, String, Span)>,
+    // The field numbers used by each enclosing message, with the fields'
+    // names.
+    numbers: Vec<HashMap<i32, String>>,
+}
+
+// Map keys may be any scalar type except floating point types and bytes.
+const MAP_KEYS: &[&str] = &[
+    "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32", "fixed64", "sfixed32",
+    "sfixed64", "bool", "string",
+];
+
+const SCALARS: &[(&str, &str)] = &[
+    ("double", "Double"),
+    ("float", "Float"),
+    ("int64", "Int64"),
+    ("uint64", "Uint64"),
+    ("int32", "Int32"),
+    ("fixed64", "Fixed64"),
+    ("fixed32", "Fixed32"),
+    ("bool", "Bool"),
+    ("string", "String"),
+    ("bytes", "Bytes"),
+    ("uint32", "Uint32"),
+    ("sfixed32", "Sfixed32"),
+    ("sfixed64", "Sfixed64"),
+    ("sint32", "Sint32"),
+    ("sint64", "Sint64"),
+];
+
+// The largest field number, which `max` stands for in a message's
+// `reserved` range.
+const FIELD_NUMBER_MAX: i32 = 536_870_911;
+
+impl Parse for File {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = match input.peek(LitStr) {
+            true => {
+                let name: LitStr = input.parse()?;
+                input.parse::<Token![,]>()?;
+                Some(name)
+            }
+            false => None,
+        };
+
+        // Like protoc, a file without a `syntax` statement is proto2.
+        let mut parser = Parser {
+            calls: TokenStream::new(),
+            proto3: false,
+            package: String::new(),
+            imports: false,
+            scope: Vec::new(),
+            definitions: HashSet::new(),
+            references: Vec::new(),
+            numbers: Vec::new(),
+        };
+        if peek_keyword(input, "syntax") {
+            input.call(Ident::parse_any)?;
+            input.parse::<Token![=]>()?;
+            let syntax: LitStr = input.parse()?;
+            input.parse::<Token![;]>()?;
+            match syntax.value().as_str() {
+                "proto2" => {}
+                "proto3" => parser.proto3 = true,
+                _ => {
+                    return Err(syn::Error::new(
+                        syntax.span(),
+                        "expected \"proto2\" or \"proto3\"",
+                    ))
+                }
+            }
+        }
+        if !parser.proto3 {
+            parser.calls.extend(quote!(.syntax("proto2")));
+        }
+
+        while !input.is_empty() {
+            if input.peek(Token![;]) {
+                input.parse::<Token![;]>()?;
+                continue;
+            }
+            let keyword = input.call(Ident::parse_any)?;
+            let call = match keyword.to_string().as_str() {
+                "package" => {
+                    let package = full_ident(input)?;
+                    input.parse::<Token![;]>()?;
+                    let call = quote!(.package(#package));
+                    parser.package = package;
+                    call
+                }
+                "import" => {
+                    if peek_keyword(input, "public") || peek_keyword(input, "weak") {
+                        return Err(unsupported(input.span(), "public and weak imports"));
+                    }
+                    let import: LitStr = input.parse()?;
+                    input.parse::<Token![;]>()?;
+                    parser.imports = true;
+                    quote!(.import(#import))
+                }
+                "message" => {
+                    let (name, body) = parser.message(input)?;
+                    quote!(.message(#name, |m| m #body))
+                }
+                "enum" => {
+                    let (name, body) = parser.enumeration(input)?;
+                    quote!(.enumeration(#name, |e| e #body))
+                }
+                "service" => {
+                    let (name, body) = parser.service(input)?;
+                    quote!(.service(#name, |s| s #body))
+                }
+                "syntax" => {
+                    return Err(syn::Error::new(
+                        keyword.span(),
+                        "the syntax statement must come first",
+                    ))
+                }
+                "option" | "extend" => return Err(unsupported(keyword.span(), keyword)),
+                _ => return Err(syn::Error::new(keyword.span(), "expected a definition")),
+            };
+            parser.calls.extend(call);
+        }
+
+        parser.resolve()?;
+        let calls = parser.calls;
+        Ok(File(match name {
+            Some(name) => quote!({
+                ::protobuf_gen::builder::FileBuilder::new(#name) #calls .build()
+            }),
+            None => quote!({
+                let mut file = ::protobuf_gen::builder::FileBuilder::new("") #calls .build();
+                file.name = ::core::option::Option::None;
+                file
+            }),
+        }))
+    }
+}
+
+impl Parser {
+    // Parses a message after the `message` keyword, returning its name and
+    // the calls on its `MessageBuilder`.
+    fn message(&mut self, input: ParseStream) -> syn::Result<(String, TokenStream)> {
+        let name = input.call(Ident::parse_any)?.to_string();
+        let content;
+        braced!(content in input);
+
+        self.define(&name);
+        self.scope.push(name.clone());
+        self.numbers.push(HashMap::new());
+        let body = self.message_body(&content);
+        self.scope.pop();
+        self.numbers.pop();
+        Ok((name, body?))
+    }
+
+    fn message_body(&mut self, content: ParseStream) -> syn::Result<TokenStream> {
+        let mut body = TokenStream::new();
+        while !content.is_empty() {
+            if content.peek(Token![;]) {
+                content.parse::<Token![;]>()?;
+                continue;
+            }
+            let keyword = content.fork().call(Ident::parse_any).ok();
+            let call = match keyword.as_ref().map(Ident::to_string).as_deref() {
+                Some("message") => {
+                    content.call(Ident::parse_any)?;
+                    let (name, nested) = self.message(content)?;
+                    quote!(.message(#name, |m| m #nested))
+                }
+                Some("enum") => {
+                    content.call(Ident::parse_any)?;
+                    let (name, nested) = self.enumeration(content)?;
+                    quote!(.enumeration(#name, |e| e #nested))
+                }
+                Some("oneof") => {
+                    content.call(Ident::parse_any)?;
+                    self.oneof(content)?
+                }
+                Some("reserved") => {
+                    content.call(Ident::parse_any)?;
+                    reserved(content, FIELD_NUMBER_MAX)?
+                }
+                Some("map") if content.peek2(Token![<]) => {
+                    content.call(Ident::parse_any)?;
+                    self.map(content)?
+                }
+                Some(label @ "optional") | Some(label @ "required") | Some(label @ "repeated") => {
+                    let span = content.call(Ident::parse_any)?.span();
+                    if label == "required" && self.proto3 {
+                        return Err(syn::Error::new(span, "proto3 fields cannot be required"));
+                    }
+                    let method = Ident::new(&format!("{}_with", label), span);
+                    let (name, typ, number, options) = self.field(content)?;
+                    quote!(.#method(#name, #typ, #number, |f| f #options))
+                }
+                Some(keyword @ "option")
+                | Some(keyword @ "extensions")
+                | Some(keyword @ "extend")
+                | Some(keyword @ "group") => {
+                    return Err(unsupported(content.span(), keyword));
+                }
+                _ => {
+                    if !self.proto3 {
+                        return Err(syn::Error::new(
+                            content.span(),
+                            "proto2 fields need a label: optional, required or repeated",
+                        ));
+                    }
+                    let (name, typ, number, options) = self.field(content)?;
+                    quote!(.field_with(#name, #typ, #number, |f| f #options))
+                }
+            };
+            body.extend(call);
+        }
+        Ok(body)
+    }
+
+    fn oneof(&mut self, input: ParseStream) -> syn::Result<TokenStream> {
+        let name = input.call(Ident::parse_any)?.to_string();
+        let content;
+        braced!(content in input);
+
+        let mut fields = TokenStream::new();
+        while !content.is_empty() {
+            if content.peek(Token![;]) {
+                content.parse::<Token![;]>()?;
+                continue;
+            }
+            if peek_keyword(&content, "option") {
+                return Err(unsupported(content.span(), "option"));
+            }
+            let (name, typ, number, options) = self.field(&content)?;
+            fields.extend(quote!(.field_with(#name, #typ, #number, |f| f #options)));
+        }
+        Ok(quote!(.oneof(#name, |o| o #fields)))
+    }
+
+    // Parses `type name = number [options];`, returning the name, the
+    // `FieldType`, the number and the calls on its `FieldBuilder`.
+    fn field(
+        &mut self,
+        input: ParseStream,
+    ) -> syn::Result<(String, TokenStream, i32, TokenStream)> {
+        let typ = self.field_type(input)?;
+        let name = input.call(Ident::parse_any)?.to_string();
+        input.parse::<Token![=]>()?;
+        let number = self.number(input, &name)?;
+
+        let mut options = TokenStream::new();
+        if input.peek(syn::token::Bracket) {
+            let content;
+            bracketed!(content in input);
+            let pairs = content.parse_terminated(FieldOption::parse, Token![,])?;
+            for option in pairs {
+                options.extend(option.0);
+            }
+        }
+        input.parse::<Token![;]>()?;
+        Ok((name, typ, number, options))
+    }
+
+    // Parses `map<key, value> name = number;`, after the `map` keyword.
+    fn map(&mut self, input: ParseStream) -> syn::Result<TokenStream> {
+        input.parse::<Token![<]>()?;
+        let span = input.span();
+        let key = type_name(input)?;
+        if !MAP_KEYS.contains(&key.as_str()) {
+            return Err(syn::Error::new(
+                span,
+                "map keys must be an integral type, bool or string",
+            ));
+        }
+        let key = named_type(&key);
+        input.parse::<Token![,]>()?;
+        let value = self.field_type(input)?;
+        input.parse::<Token![>]>()?;
+        let name = input.call(Ident::parse_any)?.to_string();
+        input.parse::<Token![=]>()?;
+        let number = self.number(input, &name)?;
+        if input.peek(syn::token::Bracket) {
+            return Err(unsupported(input.span(), "options on map fields"));
+        }
+        input.parse::<Token![;]>()?;
+        Ok(quote!(.map(#name, #key, #value, #number)))
+    }
+
+    // Parses a type, as an expression of type `FieldType`.
+    fn field_type(&mut self, input: ParseStream) -> syn::Result<TokenStream> {
+        let span = input.span();
+        let name = type_name(input)?;
+        if !SCALARS.iter().any(|&(scalar, _)| scalar == name) {
+            self.references
+                .push((self.scope.clone(), name.clone(), span));
+        }
+        Ok(named_type(&name))
+    }
+
+    // Parses a field number, checking that no other field of the message
+    // being parsed uses it.
+    fn number(&mut self, input: ParseStream, name: &str) -> syn::Result<i32> {
+        let literal = input.parse::<LitInt>()?;
+        let number = literal.base10_parse::<i32>()?;
+        let numbers = self.numbers.last_mut().expect("fields are in a message");
+        if let Some(other) = numbers.insert(number, name.to_owned()) {
+            return Err(syn::Error::new(
+                literal.span(),
+                format!("field number {} is already used by {}", number, other),
+            ));
+        }
+        Ok(number)
+    }
+
+    // Parses an enum after the `enum` keyword, returning its name and the calls
+    // on its `EnumBuilder`.
+    fn enumeration(&mut self, input: ParseStream) -> syn::Result<(String, TokenStream)> {
+        let name = input.call(Ident::parse_any)?.to_string();
+        let content;
+        braced!(content in input);
+        self.define(&name);
+
+        let mut body = TokenStream::new();
+        let mut first = true;
+        while !content.is_empty() {
+            if content.peek(Token![;]) {
+                content.parse::<Token![;]>()?;
+                continue;
+            }
+            let value = content.call(Ident::parse_any)?;
+            let call = match value.to_string().as_str() {
+                "reserved" if !content.peek(Token![=]) => reserved(&content, i32::MAX)?,
+                "option" if !content.peek(Token![=]) => {
+                    return Err(unsupported(value.span(), "option"));
+                }
+                name => {
+                    content.parse::<Token![=]>()?;
+                    let negative = content.parse::<Option<Token![-]>>()?.is_some();
+                    let literal = content.parse::<LitInt>()?;
+                    let number = literal.base10_parse::<i32>()?;
+                    let number = if negative { -number } else { number };
+                    if self.proto3 && first && number != 0 {
+                        return Err(syn::Error::new(
+                            literal.span(),
+                            "the first value of a proto3 enum must be 0",
+                        ));
+                    }
+                    first = false;
+                    if content.peek(syn::token::Bracket) {
+                        return Err(unsupported(content.span(), "options on enum values"));
+                    }
+                    content.parse::<Token![;]>()?;
+                    quote!(.value(#name, #number))
+                }
+            };
+            body.extend(call);
+        }
+        Ok((name, body))
+    }
+
+    // Parses a service after the `service` keyword, returning its name and the
+    // calls on its `ServiceBuilder`.
+    fn service(&mut self, input: ParseStream) -> syn::Result<(String, TokenStream)> {
+        let name = input.call(Ident::parse_any)?.to_string();
+        let content;
+        braced!(content in input);
+
+        let mut body = TokenStream::new();
+        while !content.is_empty() {
+            if content.peek(Token![;]) {
+                content.parse::<Token![;]>()?;
+                continue;
+            }
+            let keyword = content.call(Ident::parse_any)?;
+            if keyword != "rpc" {
+                return Err(match keyword == "option" {
+                    true => unsupported(keyword.span(), "option"),
+                    false => syn::Error::new(keyword.span(), "expected rpc"),
+                });
+            }
+            let name = content.call(Ident::parse_any)?.to_string();
+            let (input, client_streaming) = self.method_type(&content)?;
+            let returns = content.call(Ident::parse_any)?;
+            if returns != "returns" {
+                return Err(syn::Error::new(returns.span(), "expected returns"));
+            }
+            let (output, server_streaming) = self.method_type(&content)?;
+            if content.peek(syn::token::Brace) {
+                let options;
+                braced!(options in content);
+                if !options.is_empty() {
+                    return Err(unsupported(options.span(), "method options"));
+                }
+            } else {
+                content.parse::<Token![;]>()?;
+            }
+            body.extend(match client_streaming || server_streaming {
+                true => quote!(.streaming_method(#name, #input, #client_streaming, #output, #server_streaming)),
+                false => quote!(.method(#name, #input, #output)),
+            });
+        }
+        Ok((name, body))
+    }
+
+    // Parses the parenthesized input or output type of a method, returning it
+    // and whether it is streamed.
+    fn method_type(&mut self, input: ParseStream) -> syn::Result<(String, bool)> {
+        let content;
+        parenthesized!(content in input);
+        // `stream` followed by nothing or a dot is a type name.
+        let streaming = peek_keyword(&content, "stream") && {
+            let fork = content.fork();
+            fork.call(Ident::parse_any)?;
+            !fork.is_empty() && !fork.peek(Token![.])
+        };
+        if streaming {
+            content.call(Ident::parse_any)?;
+        }
+        let span = content.span();
+        let name = type_name(&content)?;
+        self.references
+            .push((self.scope.clone(), name.clone(), span));
+        Ok((name, streaming))
+    }
+
+    // Records a message or enum defined in the current scope.
+    fn define(&mut self, name: &str) {
+        let mut path = self.scope.clone();
+        path.push(name.to_owned());
+        self.definitions.insert(path.join("."));
+    }
+
+    // Checks that the type names used resolve to definitions in the file,
+    // looking in the enclosing scopes from the innermost out, like protoc.
+    // A file with imports may use types defined elsewhere.
+    fn resolve(&self) -> syn::Result<()> {
+        let package: Vec<&str> = match self.package.as_str() {
+            "" => Vec::new(),
+            package => package.split('.').collect(),
+        };
+        let defined = |full_name: &str| {
+            let mut parts = full_name.split('.');
+            package.iter().all(|&part| parts.next() == Some(part))
+                && self
+                    .definitions
+                    .contains(&parts.collect::<Vec<_>>().join("."))
+        };
+        for (scope, name, span) in &self.references {
+            let found = match name.strip_prefix('.') {
+                Some(full_name) => defined(full_name),
+                None => {
+                    let mut outer = package.clone();
+                    outer.extend(scope.iter().map(String::as_str));
+                    (0..=outer.len()).rev().any(|len| {
+                        let mut candidate = outer[..len].join(".");
+                        if len > 0 {
+                            candidate.push('.');
+                        }
+                        candidate.push_str(name);
+                        defined(&candidate)
+                    })
+                }
+            };
+            if !found && !self.imports {
+                return Err(syn::Error::new(
+                    *span,
+                    format!("{} is not defined in this file", name),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A field option in brackets, as the call on the field's `FieldBuilder`.
+struct FieldOption(TokenStream);
+
+impl Parse for FieldOption {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if !input.peek(Ident::peek_any) {
+            return Err(unsupported(input.span(), "custom options"));
+        }
+        let name = input.call(Ident::parse_any)?;
+        input.parse::<Token![=]>()?;
+        let span = input.span();
+        let value = value(input)?;
+        Ok(FieldOption(match name.to_string().as_str() {
+            "json_name" => quote!(.json_name(#value)),
+            "default" => quote!(.default_value(#value)),
+            "deprecated" | "packed" => {
+                let flag = match value.as_str() {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(syn::Error::new(span, "expected true or false")),
+                };
+                quote!(.#name(#flag))
+            }
+            _ => return Err(unsupported(name.span(), format!("the {} option", name))),
+        }))
+    }
+}
+
+// Parses an option value as protoc stores it: the contents of a string,
+// or the text of a number or identifier, with its sign.
+fn value(input: ParseStream) -> syn::Result<String> {
+    if input.peek(LitStr) {
+        return Ok(input.parse::<LitStr>()?.value());
+    }
+    let mut text = String::new();
+    if input.peek(Token![-]) {
+        input.parse::<Token![-]>()?;
+        text.push('-');
+    }
+    match input.peek(Ident::peek_any) {
+        true => text.push_str(&input.call(Ident::parse_any)?.to_string()),
+        false => text.push_str(
+            &input
+                .parse::<syn::Lit>()
+                .map(|lit| quote!(#lit))?
+                .to_string(),
+        ),
+    }
+    Ok(text)
+}
+
+fn named_type(name: &str) -> TokenStream {
+    match SCALARS.iter().find(|&&(scalar, _)| scalar == name) {
+        Some(&(_, variant)) => {
+            let variant = Ident::new(variant, Span::call_site());
+            quote!(::protobuf_gen::builder::FieldType::#variant)
+        }
+        None => quote!(::protobuf_gen::builder::FieldType::Named(#name.to_owned())),
+    }
+}
+
+// Parses a type name, which may be fully qualified with a leading dot.
+fn type_name(input: ParseStream) -> syn::Result<String> {
+    let mut name = String::new();
+    if input.peek(Token![.]) {
+        input.parse::<Token![.]>()?;
+        name.push('.');
+    }
+    name.push_str(&full_ident(input)?);
+    Ok(name)
+}
+
+// Parses dotted identifiers, such as a package name.
+fn full_ident(input: ParseStream) -> syn::Result<String> {
+    let mut name = input.call(Ident::parse_any)?.to_string();
+    while input.peek(Token![.]) {
+        input.parse::<Token![.]>()?;
+        name.push('.');
+        name.push_str(&input.call(Ident::parse_any)?.to_string());
+    }
+    Ok(name)
+}
+
+// Parses a `reserved` statement after the keyword, of either numbers and
+// ranges up to `max`, or names.
+fn reserved(input: ParseStream, max: i32) -> syn::Result<TokenStream> {
+    let mut calls = TokenStream::new();
+    loop {
+        if input.peek(LitStr) {
+            let name: LitStr = input.parse()?;
+            calls.extend(quote!(.reserved_name(#name)));
+        } else {
+            let start = input.parse::<LitInt>()?.base10_parse::<i32>()?;
+            let mut end = start;
+            if peek_keyword(input, "to") {
+                input.call(Ident::parse_any)?;
+                end = match peek_keyword(input, "max") {
+                    true => {
+                        input.call(Ident::parse_any)?;
+                        max
+                    }
+                    false => input.parse::<LitInt>()?.base10_parse::<i32>()?,
+                };
+            }
+            calls.extend(quote!(.reserved_range(#start, #end)));
+        }
+        if input.peek(Token![;]) {
+            input.parse::<Token![;]>()?;
+            return Ok(calls);
+        }
+        input.parse::<Token![,]>()?;
+    }
+}
+
+fn peek_keyword(input: ParseStream, keyword: &str) -> bool {
+    input
+        .fork()
+        .call(Ident::parse_any)
+        .is_ok_and(|ident| ident == keyword)
+}
+
+fn unsupported<T: std::fmt::Display>(span: Span, what: T) -> syn::Error {
+    syn::Error::new(span, format!("{} is not supported by proto_file!", what))
+}
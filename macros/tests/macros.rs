@@ -0,0 +1,145 @@
+use protobuf_gen::{read_sources, ProtobufString};
+use protobuf_gen_macros::proto_file;
+
+// Compiles `source` with protoc, without source info.
+fn compile(name: &str, source: &str) -> prost_types::FileDescriptorProto {
+    let mut file = read_sources(vec![(name, source)], &["."])
+        .file
+        .pop()
+        .unwrap();
+    file.source_code_info = None;
+    file
+}
+
+#[test]
+fn it_expands_like_protoc() {
+    let file = proto_file! {
+        "acme/user.proto",
+        syntax = "proto3";
+
+        package acme.users;
+
+        message User {
+            int64 id = 1;
+            repeated string tags = 2 [packed = false];
+            map<string, Address> addresses = 3;
+            optional Status status = 4;
+            oneof contact {
+                string email = 5;
+                string phone_number = 6 [json_name = "phone"];
+            }
+            .acme.users.User.Address home = 7 [deprecated = true];
+            message Address {
+                string city = 1;
+            }
+            enum Kind {
+                KIND_UNKNOWN = 0;
+                KIND_ADMIN = 1;
+            }
+            Kind kind = 8;
+            reserved 9, 11 to 20, 100 to max;
+            reserved "password";
+        }
+
+        enum Status {
+            STATUS_UNKNOWN = 0;
+            STATUS_ACTIVE = 1;
+            STATUS_DELETED = -1;
+            reserved 5 to max;
+        }
+
+        service Users {
+            rpc GetUser(User) returns (User);
+            rpc WatchUsers(stream User) returns (stream User);
+        }
+    };
+
+    let expected = compile(
+        "acme/user.proto",
+        r#"syntax = "proto3";
+
+package acme.users;
+
+message User {
+  int64 id = 1;
+  repeated string tags = 2 [packed = false];
+  map<string, Address> addresses = 3;
+  optional Status status = 4;
+  oneof contact {
+    string email = 5;
+    string phone_number = 6 [json_name = "phone"];
+  }
+  .acme.users.User.Address home = 7 [deprecated = true];
+  message Address {
+    string city = 1;
+  }
+  enum Kind {
+    KIND_UNKNOWN = 0;
+    KIND_ADMIN = 1;
+  }
+  Kind kind = 8;
+  reserved 9, 11 to 20, 100 to max;
+  reserved "password";
+}
+
+enum Status {
+  STATUS_UNKNOWN = 0;
+  STATUS_ACTIVE = 1;
+  STATUS_DELETED = -1;
+  reserved 5 to max;
+}
+
+service Users {
+  rpc GetUser(User) returns (User);
+  rpc WatchUsers(stream User) returns (stream User);
+}
+"#,
+    );
+    assert_eq!(expected, file);
+
+    // What it renders compiles back to the same descriptor.
+    assert_eq!(file, compile("acme/user.proto", &file.to_protobuf(&file)));
+}
+
+#[test]
+fn it_expands_proto2_without_a_name() {
+    let file = proto_file! {
+        message Point {
+            required int32 x = 1;
+            optional int32 y = 2 [default = -1];
+            optional string label = 3 [default = "origin"];
+        }
+    };
+
+    assert_eq!(None, file.name);
+    assert_eq!(None, file.syntax);
+    let rendered = file.to_protobuf(&file);
+    let mut expected = compile("point.proto", &rendered);
+    expected.name = None;
+    assert_eq!(expected, file);
+    assert!(rendered.contains("  optional int32 y = 2 [default = -1];\n"));
+}
+
+#[test]
+fn it_rejects_invalid_sources() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
+
+#[test]
+fn it_takes_imported_types_as_messages() {
+    let file = proto_file! {
+        syntax = "proto3";
+        import "google/protobuf/timestamp.proto";
+
+        message Event {
+            google.protobuf.Timestamp time = 1;
+        }
+    };
+
+    let field = &file.message_type[0].field[0];
+    assert_eq!(
+        Some(".google.protobuf.Timestamp"),
+        field.type_name.as_deref()
+    );
+}
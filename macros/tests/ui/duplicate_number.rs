@@ -0,0 +1,15 @@
+use protobuf_gen_macros::proto_file;
+
+fn main() {
+    proto_file! {
+        syntax = "proto3";
+
+        message User {
+            string name = 1;
+            oneof contact {
+                string email = 2;
+                string phone = 1;
+            }
+        }
+    };
+}
@@ -0,0 +1,12 @@
+use protobuf_gen_macros::proto_file;
+
+fn main() {
+    proto_file! {
+        syntax = "proto3";
+
+        enum Status {
+            STATUS_ACTIVE = 1;
+            STATUS_UNKNOWN = 0;
+        }
+    };
+}
@@ -0,0 +1,11 @@
+use protobuf_gen_macros::proto_file;
+
+fn main() {
+    proto_file! {
+        syntax = "proto3";
+
+        message User {
+            map<double, string> labels = 1;
+        }
+    };
+}
@@ -0,0 +1,15 @@
+use protobuf_gen_macros::proto_file;
+
+fn main() {
+    proto_file! {
+        syntax = "proto3";
+        package acme;
+
+        message User {
+            Adress home = 1;
+            message Address {
+                string city = 1;
+            }
+        }
+    };
+}
@@ -0,0 +1,1253 @@
+//! A syntax tree of a `.proto` file.
+//!
+//! Rendering goes through this tree: a `FileDescriptorProto` is first turned
+//! into an [`ast::File`](File), with the comments from its `SourceCodeInfo`
+//! attached to the elements they belong to, and the tree is then printed. In
+//! between, the tree can be inspected and changed like any other data, e.g.
+//! to reorder declarations or add comments, or built from scratch. Names and
+//! comments are borrowed from the descriptor, so building the tree copies
+//! hardly any text:
+//!
+//! ```
+//! use protobuf_gen::ast;
+//! use protobuf_gen::GeneratorConfig;
+//!
+//! let descriptor = prost_types::FileDescriptorProto {
+//!     syntax: Some("proto3".to_owned()),
+//!     ..Default::default()
+//! };
+//! let mut file = ast::File::from_descriptor(&descriptor);
+//! file.items.push(ast::Item::Message(ast::Message {
+//!     name: "Empty".into(),
+//!     ..Default::default()
+//! }));
+//! assert_eq!(
+//!     "syntax = \"proto3\";\n\n\nmessage Empty {\n}\n",
+//!     file.print(&GeneratorConfig::default()),
+//! );
+//! ```
+
+use crate::options;
+use crate::path;
+use prost_types::source_code_info::Location;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct File<'a> {
+    /// The comments at the top of the file, which belong to the `syntax`
+    /// statement.
+    pub comments: Comments<'a>,
+    /// `"proto2"` or `"proto3"`; no `syntax` statement is written if unset.
+    pub syntax: Option<Cow<'a, str>>,
+    pub package: Option<Package<'a>>,
+    pub imports: Vec<Import<'a>>,
+    pub options: Vec<OptionStatement<'a>>,
+    pub items: Vec<Item<'a>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Package<'a> {
+    pub comments: Comments<'a>,
+    pub name: Cow<'a, str>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Import<'a> {
+    pub comments: Comments<'a>,
+    pub path: Cow<'a, str>,
+    /// `import public`, making the definitions of the imported file
+    /// available to the files importing this one.
+    pub public: bool,
+    /// `import weak`.
+    pub weak: bool,
+}
+
+/// A top-level declaration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item<'a> {
+    Message(Message<'a>),
+    Enum(Enum<'a>),
+    Service(Service<'a>),
+    Extend(Extend<'a>),
+}
+
+/// The comments attached to an element, without the `//` markers.
+///
+/// As in `SourceCodeInfo`, each line keeps the space following `//`, and a
+/// multi-line comment has a `\n` after every line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Comments<'a> {
+    /// Comments separated from the element, and from each other, by a blank
+    /// line, such as a banner above a group of declarations.
+    pub detached: Vec<Cow<'a, str>>,
+    pub leading: Option<Cow<'a, str>>,
+    pub trailing: Option<Cow<'a, str>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Message<'a> {
+    pub comments: Comments<'a>,
+    pub name: Cow<'a, str>,
+    pub options: Vec<OptionStatement<'a>>,
+    pub items: Vec<MessageItem<'a>>,
+}
+
+/// An element of a message body, written in order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessageItem<'a> {
+    Field(Field<'a>),
+    Oneof(Oneof<'a>),
+    Message(Message<'a>),
+    Enum(Enum<'a>),
+    Extend(Extend<'a>),
+    Extensions(Extensions<'a>),
+    Reserved(Reserved<'a>),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Field<'a> {
+    pub comments: Comments<'a>,
+    pub label: Option<Label>,
+    /// A scalar type, a type name or `map<K, V>`, as written.
+    pub typ: Cow<'a, str>,
+    pub name: Cow<'a, str>,
+    pub number: i32,
+    pub options: Vec<CompactOption<'a>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Label {
+    Optional,
+    Required,
+    Repeated,
+}
+
+impl Label {
+    /// The keyword for the label, as written before the field type.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Label::Optional => "optional",
+            Label::Required => "required",
+            Label::Repeated => "repeated",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Oneof<'a> {
+    pub comments: Comments<'a>,
+    pub name: Cow<'a, str>,
+    pub options: Vec<OptionStatement<'a>>,
+    pub fields: Vec<Field<'a>>,
+}
+
+/// An `extend` block, declaring extensions of the message `extendee`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Extend<'a> {
+    pub comments: Comments<'a>,
+    pub extendee: Cow<'a, str>,
+    pub fields: Vec<Field<'a>>,
+}
+
+/// An `extensions` statement, setting numbers of a message aside for
+/// extensions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Extensions<'a> {
+    pub comments: Comments<'a>,
+    pub ranges: Vec<ReservedRange>,
+    pub options: Vec<CompactOption<'a>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Enum<'a> {
+    pub comments: Comments<'a>,
+    pub name: Cow<'a, str>,
+    pub options: Vec<OptionStatement<'a>>,
+    pub values: Vec<EnumValue<'a>>,
+    pub reserved: Vec<Reserved<'a>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnumValue<'a> {
+    pub comments: Comments<'a>,
+    pub name: Cow<'a, str>,
+    pub number: i32,
+    pub options: Vec<CompactOption<'a>>,
+}
+
+/// A `reserved` statement, of either numbers or names.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reserved<'a> {
+    pub comments: Comments<'a>,
+    pub kind: ReservedKind<'a>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReservedKind<'a> {
+    Ranges(Vec<ReservedRange>),
+    Names(Vec<Cow<'a, str>>),
+}
+
+/// An inclusive range of numbers, up to `max` if `end` is `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReservedRange {
+    pub start: i32,
+    pub end: Option<i32>,
+}
+
+/// An option in the brackets after a field, enum value or extension range,
+/// e.g. `deprecated = true`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompactOption<'a> {
+    /// The option name, with extensions in parentheses, e.g.
+    /// `(acme.rules).min_len`.
+    pub name: Cow<'a, str>,
+    /// The value as written in a `.proto` file: a literal, an identifier,
+    /// or an aggregate in braces.
+    pub value: Cow<'a, str>,
+}
+
+/// An `option` statement in the body of a file or declaration, e.g.
+/// `option java_package = "com.acme";`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OptionStatement<'a> {
+    pub comments: Comments<'a>,
+    /// The number of a standard option in its options message, e.g. `11`
+    /// for `go_package`, or `None` for a custom option, which is kept in
+    /// `uninterpreted_option`.
+    pub number: Option<i32>,
+    pub option: CompactOption<'a>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Service<'a> {
+    pub comments: Comments<'a>,
+    pub name: Cow<'a, str>,
+    pub options: Vec<OptionStatement<'a>>,
+    pub methods: Vec<Method<'a>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Method<'a> {
+    pub comments: Comments<'a>,
+    pub name: Cow<'a, str>,
+    pub input_type: Cow<'a, str>,
+    pub client_streaming: bool,
+    pub output_type: Cow<'a, str>,
+    pub server_streaming: bool,
+    /// Written in a block after the method, rather than a `;`, if any.
+    pub options: Vec<OptionStatement<'a>>,
+}
+
+impl<'a> File<'a> {
+    /// Builds the tree of a file, taking comments from its `SourceCodeInfo`
+    /// if present.
+    pub fn from_descriptor(file: &'a prost_types::FileDescriptorProto) -> File<'a> {
+        File::from_source(file, &mut Source::new(file))
+    }
+
+    /// Builds the tree of a file with everything that
+    /// [`GeneratorConfig::lossless`](crate::GeneratorConfig::lossless)
+    /// keeps.
+    pub fn from_descriptor_lossless(file: &'a prost_types::FileDescriptorProto) -> File<'a> {
+        let mut src = Source::new(file);
+        src.lossless = true;
+        File::from_source(file, &mut src)
+    }
+
+    pub(crate) fn from_source<'s: 'a>(
+        file: &'a prost_types::FileDescriptorProto,
+        src: &mut Source<'s>,
+    ) -> File<'a> {
+        // e.g. "foo", "foo.bar", etc.
+        let package = file.package.as_ref().map(|name| Package {
+            comments: src.comments_at(&[path::FILE_PACKAGE]),
+            name: name.into(),
+        });
+
+        // Names of files imported by this file, along with whether they are
+        // imported `public` or `weak`, given by their indexes in the list.
+        let imports = file
+            .dependency
+            .iter()
+            .enumerate()
+            .map(|(i, name)| match src.lossless {
+                true => Import {
+                    comments: src.comments_at(&[path::FILE_DEPENDENCY, i as i32]),
+                    path: name.into(),
+                    public: file.public_dependency.contains(&(i as i32)),
+                    weak: file.weak_dependency.contains(&(i as i32)),
+                },
+                false => Import {
+                    path: name.into(),
+                    ..Default::default()
+                },
+            })
+            .collect();
+
+        // All top-level definitions in this file, by kind, or in the order
+        // they were written when rendering losslessly.
+        let (mut messages, mut enums, mut services) = (Vec::new(), Vec::new(), Vec::new());
+        src.each(path::FILE_MESSAGE_TYPE, &file.message_type, |src, msg| {
+            messages.push((
+                src.position(),
+                Item::Message(Message::from_source(msg, src)),
+            ))
+        });
+        src.each(path::FILE_ENUM_TYPE, &file.enum_type, |src, enum_type| {
+            enums.push((
+                src.position(),
+                Item::Enum(Enum::from_source(enum_type, src)),
+            ))
+        });
+        src.each(path::FILE_SERVICE, &file.service, |src, service| {
+            services.push((
+                src.position(),
+                Item::Service(Service::from_source(service, src)),
+            ))
+        });
+        let extends = extend_blocks(
+            src,
+            path::FILE_EXTENSION,
+            src.lossless_only(&file.extension),
+        )
+        .into_iter()
+        .map(|(position, extend)| (position, Item::Extend(extend)))
+        .collect();
+
+        let options = file
+            .options
+            .as_ref()
+            .filter(|_| src.lossless)
+            .map_or_else(Vec::new, options::file_options);
+
+        // protoc leaves `syntax` unset for proto2, even when the file says
+        // so; its location tells whether it did.
+        let syntax = match file.syntax {
+            Some(ref syntax) => Some(Cow::Borrowed(syntax.as_str())),
+            None if src.lossless && src.position_at(&[path::FILE_SYNTAX]).is_some() => {
+                Some("proto2".into())
+            }
+            None => None,
+        };
+        let comments = match src.lossless {
+            true => src.comments_at(&[path::FILE_SYNTAX]),
+            false => Comments::default(),
+        };
+
+        File {
+            comments,
+            syntax,
+            package,
+            imports,
+            options: option_statements(src, path::FILE_OPTIONS, options),
+            items: src.ordered(vec![messages, enums, services, extends]),
+        }
+    }
+
+    /// Renders the file as `.proto` source.
+    pub fn print(&self, config: &crate::GeneratorConfig) -> String {
+        let mut gen = crate::Generator::new(config);
+        crate::Print::print(self, &mut gen);
+        gen.finish()
+    }
+
+    /// Renders the file into `out` as the output is produced. See
+    /// [`write_protobuf`](crate::ProtobufString::write_protobuf).
+    pub fn write(
+        &self,
+        config: &crate::GeneratorConfig,
+        out: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        let mut gen = crate::Generator::new(config);
+        gen.sink = Some(crate::Sink::Io(out));
+        crate::Print::print(self, &mut gen);
+        gen.finish_sink()
+    }
+
+    /// Renders the file, along with the location of every element in the
+    /// output. See
+    /// [`to_protobuf_with_source_info`](crate::ProtobufString::to_protobuf_with_source_info).
+    pub fn print_with_source_info(
+        &self,
+        config: &crate::GeneratorConfig,
+    ) -> (String, prost_types::SourceCodeInfo) {
+        let mut gen = crate::Generator::new(config);
+        gen.spans = Some(Default::default());
+        crate::Print::print(self, &mut gen);
+        gen.finish_with_source_info()
+    }
+}
+
+impl<'a> From<&'a prost_types::FileDescriptorProto> for File<'a> {
+    fn from(file: &'a prost_types::FileDescriptorProto) -> File<'a> {
+        File::from_descriptor(file)
+    }
+}
+
+/// Renders with the default [`GeneratorConfig`](crate::GeneratorConfig).
+impl fmt::Display for File<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut gen = crate::Generator::new(&crate::GeneratorConfig::default());
+        gen.sink = Some(crate::Sink::Fmt(f));
+        crate::Print::print(self, &mut gen);
+        gen.finish_sink().map_err(|_| fmt::Error)
+    }
+}
+
+impl<'a> Message<'a> {
+    pub(crate) fn from_source<'s: 'a>(
+        msg: &'a prost_types::DescriptorProto,
+        src: &mut Source<'s>,
+    ) -> Message<'a> {
+        // Map fields are compiled into a nested `FooEntry` message with the
+        // `map_entry` option, which is folded back into `map<K, V>` syntax.
+        let is_map_entry = |t: &prost_types::DescriptorProto| {
+            t.options
+                .as_ref()
+                .is_some_and(|o| o.map_entry.unwrap_or(false))
+        };
+        let map_type = |field: &prost_types::FieldDescriptorProto| {
+            let type_name = field.type_name.as_ref()?;
+            let sub_type = &type_name[type_name.rfind('.').map_or(0, |i| i + 1)..];
+            let entry = msg
+                .nested_type
+                .iter()
+                .find(|t| t.name() == sub_type && is_map_entry(t))?;
+            let key = field_type(entry.field.first()?);
+            let value = field_type(entry.field.get(1)?);
+            Some(Cow::Owned(format!("map<{}, {}>", key, value)))
+        };
+
+        // Fields are kept in declaration order, with the fields of a oneof
+        // grouped where its first field appears. The synthetic oneof of a
+        // proto3 `optional` field is not written. Each item is paired with
+        // where it starts in the source, to restore the order in which
+        // fields and nested declarations were interleaved.
+        let mut items = Vec::new();
+        let mut oneofs = HashMap::new();
+        for (i, field) in msg.field.iter().enumerate() {
+            src.path.extend([path::MESSAGE_FIELD, i as i32]);
+            let mut node = Field::from_source(field, src);
+            let position = src.position();
+            src.path.truncate(src.path.len() - 2);
+            if let Some(typ) = map_type(field) {
+                node.typ = typ;
+                node.label = None;
+            }
+
+            let index = match field.oneof_index {
+                Some(index) if !field.proto3_optional() => index,
+                _ => {
+                    items.push((position, MessageItem::Field(node)));
+                    continue;
+                }
+            };
+            match oneofs.get(&index) {
+                Some(&i) => {
+                    if let (_, MessageItem::Oneof(ref mut oneof)) = items[i] {
+                        oneof.fields.push(node);
+                    }
+                }
+                None => {
+                    oneofs.insert(index, items.len());
+                    let oneof = msg.oneof_decl.get(index as usize);
+                    let options = oneof
+                        .and_then(|o| o.options.as_ref())
+                        .filter(|_| src.lossless)
+                        .map_or_else(Vec::new, options::oneof_options);
+                    src.path.extend([path::MESSAGE_ONEOF_DECL, index]);
+                    let node = Oneof {
+                        comments: src.comments_at(&[]),
+                        name: oneof.map_or("", |o| o.name()).into(),
+                        options: option_statements(src, path::ONEOF_OPTIONS, options),
+                        fields: vec![node],
+                    };
+                    items.push((src.position().or(position), MessageItem::Oneof(node)));
+                    src.path.truncate(src.path.len() - 2);
+                }
+            }
+        }
+
+        let extends = extend_blocks(
+            src,
+            path::MESSAGE_EXTENSION,
+            src.lossless_only(&msg.extension),
+        )
+        .into_iter()
+        .map(|(position, extend)| (position, MessageItem::Extend(extend)))
+        .collect();
+
+        let (mut messages, mut enums) = (Vec::new(), Vec::new());
+        src.each(
+            path::MESSAGE_NESTED_TYPE,
+            &msg.nested_type,
+            |src, nested_type| {
+                if !is_map_entry(nested_type) {
+                    let node = Message::from_source(nested_type, src);
+                    messages.push((src.position(), MessageItem::Message(node)));
+                }
+            },
+        );
+        src.each(path::MESSAGE_ENUM_TYPE, &msg.enum_type, |src, enum_type| {
+            let node = Enum::from_source(enum_type, src);
+            enums.push((src.position(), MessageItem::Enum(node)))
+        });
+
+        // Ranges of numbers set aside for extensions, grouped into the
+        // `extensions` statements declaring them; `end` is exclusive.
+        let mut statements: Vec<(Option<usize>, Option<Position>, Extensions)> = Vec::new();
+        for (i, range) in src.lossless_only(&msg.extension_range).iter().enumerate() {
+            let statement = src.statement_of(path::MESSAGE_EXTENSION_RANGE, i);
+            let range_node = ReservedRange {
+                start: range.start(),
+                end: match range.end() {
+                    FIELD_NUMBER_MAX_EXCLUSIVE => None,
+                    end => Some(end - 1),
+                },
+            };
+            match statements.last_mut() {
+                Some((last, _, node)) if statement.is_some() && *last == statement => {
+                    node.ranges.push(range_node)
+                }
+                _ => {
+                    let (comments, position) = match statement {
+                        Some(index) => (src.comments_of(index), src.position_of(index)),
+                        None => (
+                            Comments::default(),
+                            src.position_at(&[path::MESSAGE_EXTENSION_RANGE, i as i32]),
+                        ),
+                    };
+                    let options = range
+                        .options
+                        .as_ref()
+                        .map_or_else(Vec::new, options::extension_range_options);
+                    statements.push((
+                        statement,
+                        position,
+                        Extensions {
+                            comments,
+                            ranges: vec![range_node],
+                            options,
+                        },
+                    ));
+                }
+            }
+        }
+        let extensions = statements
+            .into_iter()
+            .map(|(_, position, node)| (position, MessageItem::Extensions(node)))
+            .collect();
+
+        // Range of reserved tag numbers; `end` is exclusive.
+        let mut reserved = Vec::new();
+        if !msg.reserved_range.is_empty() {
+            let ranges = msg
+                .reserved_range
+                .iter()
+                .map(|range| ReservedRange {
+                    start: range.start(),
+                    end: match range.end() {
+                        FIELD_NUMBER_MAX_EXCLUSIVE => None,
+                        end => Some(end - 1),
+                    },
+                })
+                .collect();
+            reserved.push((
+                src.position_at(&[path::MESSAGE_RESERVED_RANGE]),
+                MessageItem::Reserved(Reserved {
+                    comments: src.comments_at(&[path::MESSAGE_RESERVED_RANGE]),
+                    kind: ReservedKind::Ranges(ranges),
+                }),
+            ));
+        }
+
+        // Reserved field names, which may not be used by fields in the same message.
+        // A given name may only be reserved once.
+        if !msg.reserved_name.is_empty() {
+            reserved.push((
+                src.position_at(&[path::MESSAGE_RESERVED_NAME]),
+                MessageItem::Reserved(Reserved {
+                    comments: src.comments_at(&[path::MESSAGE_RESERVED_NAME]),
+                    kind: ReservedKind::Names(msg.reserved_name.iter().map(Cow::from).collect()),
+                }),
+            ));
+        }
+
+        let options = msg
+            .options
+            .as_ref()
+            .filter(|_| src.lossless)
+            .map_or_else(Vec::new, options::message_options);
+
+        Message {
+            comments: src.comments_at(&[]),
+            name: msg.name().into(),
+            options: option_statements(src, path::MESSAGE_OPTIONS, options),
+            items: src.ordered(vec![items, extends, messages, enums, extensions, reserved]),
+        }
+    }
+}
+
+// One past the largest field number, as stored for `reserved 5 to max`.
+const FIELD_NUMBER_MAX_EXCLUSIVE: i32 = 536_870_912;
+
+impl<'a> Field<'a> {
+    pub(crate) fn from_source<'s: 'a>(
+        field: &'a prost_types::FieldDescriptorProto,
+        src: &Source<'s>,
+    ) -> Field<'a> {
+        use prost_types::field_descriptor_proto;
+
+        let label = match field
+            .label
+            .and_then(|l| field_descriptor_proto::Label::try_from(l).ok())
+        {
+            Some(field_descriptor_proto::Label::Optional) => {
+                // Implicit in proto3, unless the field tracks presence.
+                if src.syntax == prost_types::Syntax::Proto2 || field.proto3_optional() {
+                    Some(Label::Optional)
+                } else {
+                    None
+                }
+            }
+            Some(field_descriptor_proto::Label::Required) => Some(Label::Required),
+            Some(field_descriptor_proto::Label::Repeated) => Some(Label::Repeated),
+            None => None,
+        };
+
+        // For extensions, the name of the type being extended is written by
+        // the enclosing `extend` block.
+
+        Field {
+            comments: src.comments_at(&[]),
+            label,
+            typ: field_type(field).into(),
+            name: field.name().into(),
+            number: field.number(),
+            options: options::field_options(field),
+        }
+    }
+}
+
+// The type of a field as written: the type name for message and enum types,
+// or the scalar type's keyword.
+fn field_type(field: &prost_types::FieldDescriptorProto) -> &str {
+    // For message and enum types, this is the name of the type.  If the name
+    // starts with a '.', it is fully-qualified.  Otherwise, C++-like scoping
+    // rules are used to find the type (i.e. first the nested types within this
+    // message are searched, then within the parent, on up to the root
+    // namespace).
+    if let Some(ref type_name) = field.type_name {
+        return type_name;
+    }
+
+    // If type_name is set, this need not be set.  If both this and type_name
+    // are set, this must be one of TYPE_ENUM, TYPE_MESSAGE or TYPE_GROUP.
+    use prost_types::field_descriptor_proto::Type;
+    let typ = match field.r#type.and_then(|t| Type::try_from(t).ok()) {
+        Some(typ) => typ,
+        None => return "",
+    };
+    match typ {
+        Type::Double => "double",
+        Type::Float => "float",
+        Type::Int64 => "int64",
+        Type::Uint64 => "uint64",
+        Type::Int32 => "int32",
+        Type::Fixed64 => "fixed64",
+        Type::Fixed32 => "fixed32",
+        Type::Bool => "bool",
+        Type::String => "string",
+        // Tag-delimited aggregate.
+        // Group type is deprecated and not supported in proto3. However, Proto3
+        // implementations should still be able to parse the group wire format and
+        // treat group fields as unknown fields.
+        Type::Group => "group",
+        // Length-delimited aggregate.
+        Type::Message => "message",
+        // New in version 2.
+        Type::Bytes => "bytes",
+        Type::Uint32 => "uint32",
+        Type::Enum => "enum",
+        Type::Sfixed32 => "sfixed32",
+        Type::Sfixed64 => "sfixed64",
+        Type::Sint32 => "sint32",
+        Type::Sint64 => "sint64",
+    }
+}
+
+impl<'a> Enum<'a> {
+    pub(crate) fn from_source<'s: 'a>(
+        enum_type: &'a prost_types::EnumDescriptorProto,
+        src: &mut Source<'s>,
+    ) -> Enum<'a> {
+        let mut values = Vec::with_capacity(enum_type.value.len());
+        src.each(path::ENUM_VALUE, &enum_type.value, |src, value| {
+            values.push(EnumValue::from_source(value, src))
+        });
+
+        let options = enum_type
+            .options
+            .as_ref()
+            .filter(|_| src.lossless)
+            .map_or_else(Vec::new, options::enum_options);
+
+        let mut reserved = Vec::new();
+
+        // Range of reserved numeric values. Reserved numeric values may not be used
+        // by enum values in the same enum declaration. Reserved ranges may not
+        // overlap. Unlike for messages, `end` is inclusive.
+        if !enum_type.reserved_range.is_empty() {
+            let ranges = enum_type
+                .reserved_range
+                .iter()
+                .map(|range| ReservedRange {
+                    start: range.start(),
+                    end: match range.end() {
+                        i32::MAX => None,
+                        end => Some(end),
+                    },
+                })
+                .collect();
+            reserved.push(Reserved {
+                comments: src.comments_at(&[path::ENUM_RESERVED_RANGE]),
+                kind: ReservedKind::Ranges(ranges),
+            });
+        }
+
+        // Reserved enum value names, which may not be reused. A given name may only
+        // be reserved once.
+        if !enum_type.reserved_name.is_empty() {
+            reserved.push(Reserved {
+                comments: src.comments_at(&[path::ENUM_RESERVED_NAME]),
+                kind: ReservedKind::Names(enum_type.reserved_name.iter().map(Cow::from).collect()),
+            });
+        }
+
+        Enum {
+            comments: src.comments_at(&[]),
+            name: enum_type.name().into(),
+            options: option_statements(src, path::ENUM_OPTIONS, options),
+            values,
+            reserved,
+        }
+    }
+}
+
+impl<'a> EnumValue<'a> {
+    pub(crate) fn from_source<'s: 'a>(
+        value: &'a prost_types::EnumValueDescriptorProto,
+        src: &Source<'s>,
+    ) -> EnumValue<'a> {
+        EnumValue {
+            comments: src.comments_at(&[]),
+            name: value.name().into(),
+            number: value.number(),
+            options: options::enum_value_options(value),
+        }
+    }
+}
+
+impl<'a> Service<'a> {
+    pub(crate) fn from_source<'s: 'a>(
+        service: &'a prost_types::ServiceDescriptorProto,
+        src: &mut Source<'s>,
+    ) -> Service<'a> {
+        let mut methods = Vec::with_capacity(service.method.len());
+        src.each(path::SERVICE_METHOD, &service.method, |src, method| {
+            methods.push(Method::from_source(method, src))
+        });
+
+        let options = service
+            .options
+            .as_ref()
+            .filter(|_| src.lossless)
+            .map_or_else(Vec::new, options::service_options);
+
+        Service {
+            comments: src.comments_at(&[]),
+            name: service.name().into(),
+            options: option_statements(src, path::SERVICE_OPTIONS, options),
+            methods,
+        }
+    }
+}
+
+impl<'a> Method<'a> {
+    pub(crate) fn from_source<'s: 'a>(
+        method: &'a prost_types::MethodDescriptorProto,
+        src: &Source<'s>,
+    ) -> Method<'a> {
+        // Input and output type names.  These are resolved in the same way as
+        // FieldDescriptorProto.type_name, but must refer to a message type.
+
+        let options = method
+            .options
+            .as_ref()
+            .filter(|_| src.lossless)
+            .map_or_else(Vec::new, options::method_options);
+
+        Method {
+            comments: src.comments_at(&[]),
+            name: method.name().into(),
+            input_type: method.input_type().into(),
+            client_streaming: method.client_streaming(),
+            output_type: method.output_type().into(),
+            server_streaming: method.server_streaming(),
+            options: option_statements(src, path::METHOD_OPTIONS, options),
+        }
+    }
+}
+
+// Where an element starts in the source: its line and column.
+type Position = (i32, i32);
+
+// Orders items by where they start in the source. An item without a
+// location, such as one added to the descriptor after compiling it, stays
+// after the item preceding it.
+fn in_source_order<T>(items: Vec<(Option<Position>, T)>) -> Vec<T> {
+    let mut items = inherit_positions(items);
+    items.sort_by_key(|(position, _)| *position);
+    items.into_iter().map(|(_, item)| item).collect()
+}
+
+// Interleaves lists of items of different kinds, such as the fields and the
+// nested messages of a message, by where they start in the source. Each list
+// keeps its own order, which is that of the descriptor: an element moved
+// there by an edit keeps its new place even though its location is stale.
+fn interleaved<T>(kinds: Vec<Vec<(Option<Position>, T)>>) -> Vec<T> {
+    let mut kinds: Vec<_> = kinds
+        .into_iter()
+        .map(|items| inherit_positions(items).into_iter().peekable())
+        .collect();
+    let mut items = Vec::new();
+    loop {
+        let next = kinds
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, kind)| kind.peek().map(|(position, _)| (*position, i)))
+            .min();
+        match next {
+            Some((_, i)) => items.extend(kinds[i].next().map(|(_, item)| item)),
+            None => return items,
+        }
+    }
+}
+
+fn inherit_positions<T>(items: Vec<(Option<Position>, T)>) -> Vec<(Option<Position>, T)> {
+    let mut last = None;
+    items
+        .into_iter()
+        .map(|(position, item)| {
+            last = position.or(last);
+            (last, item)
+        })
+        .collect()
+}
+
+// The `option` statements of the current element, whose options are under
+// field `field` of it, in the order they were written. Like protoc before
+// interpreting options, custom options are located by their index in
+// `uninterpreted_option`.
+fn option_statements<'a, 's: 'a>(
+    src: &Source<'s>,
+    field: i32,
+    options: options::Statements<'a>,
+) -> Vec<OptionStatement<'a>> {
+    let mut uninterpreted = 0;
+    let statements = options
+        .into_iter()
+        .map(|(number, option)| {
+            let path = match number {
+                Some(number) => [field, number, 0],
+                None => {
+                    uninterpreted += 1;
+                    [field, path::UNINTERPRETED_OPTION, uninterpreted - 1]
+                }
+            };
+            let path = &path[..if number.is_some() { 2 } else { 3 }];
+            let statement = OptionStatement {
+                comments: src.comments_at(path),
+                number,
+                option,
+            };
+            (src.position_at(path), statement)
+        })
+        .collect();
+    in_source_order(statements)
+}
+
+// Groups `extensions`, under field `field` of the current element, into the
+// `extend` blocks declaring them.
+fn extend_blocks<'a, 's: 'a>(
+    src: &mut Source<'s>,
+    field: i32,
+    extensions: &'a [prost_types::FieldDescriptorProto],
+) -> Vec<(Option<Position>, Extend<'a>)> {
+    let mut blocks: Vec<(Option<usize>, Option<Position>, Extend)> = Vec::new();
+    for (i, extension) in extensions.iter().enumerate() {
+        src.path.extend([field, i as i32]);
+        let node = Field::from_source(extension, src);
+        let position = src.position();
+        src.path.truncate(src.path.len() - 2);
+
+        let block = src.statement_of(field, i);
+        match blocks.last_mut() {
+            Some((last, _, extend))
+                if *last == block && extend.extendee == extension.extendee() =>
+            {
+                extend.fields.push(node)
+            }
+            _ => {
+                let (comments, position) = match block {
+                    Some(index) => (src.comments_of(index), src.position_of(index)),
+                    None => (Comments::default(), position),
+                };
+                let extend = Extend {
+                    comments,
+                    extendee: extension.extendee().into(),
+                    fields: vec![node],
+                };
+                blocks.push((block, position, extend));
+            }
+        }
+    }
+    blocks
+        .into_iter()
+        .map(|(_, position, extend)| (position, extend))
+        .collect()
+}
+
+/// Fails if rendering `file` would lose something its descriptor holds,
+/// naming the first such element: a comment the syntax tree has no place
+/// for, such as one on a custom option, a group field, or an `editions`
+/// file, or an option that was decoded without being recognized, such as
+/// a custom option whose extension is missing from the descriptor set it
+/// was read from. Descriptors are otherwise written back completely.
+pub fn check_lossless(file: &prost_types::FileDescriptorProto) -> std::io::Result<()> {
+    let error = |message: String| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", file.name(), message),
+        ))
+    };
+
+    match file.syntax() {
+        "" | "proto2" | "proto3" => {}
+        syntax => return error(format!("syntax \"{}\" cannot be rendered", syntax)),
+    }
+
+    if let Some(group) = find_group(file.package(), &file.extension, &file.message_type) {
+        return error(format!("group field {} cannot be rendered", group));
+    }
+
+    if let Some((element, number)) = find_unknown_option(file) {
+        return error(format!(
+            "option number {} of {} is unknown and cannot be rendered",
+            number, element
+        ));
+    }
+
+    let mut src = Source::new(file);
+    src.lossless = true;
+    src.taken = Some(vec![Cell::new(false); src.locations.len()]);
+    File::from_source(file, &mut src);
+    let taken = src.taken.unwrap_or_default();
+    for (location, taken) in src.locations.iter().zip(taken) {
+        let has_comments = location.leading_comments.is_some()
+            || location.trailing_comments.is_some()
+            || !location.leading_detached_comments.is_empty();
+        if has_comments && !taken.get() {
+            let element = match path::Path::parse(&location.path) {
+                Some(path) => path.to_string(),
+                None => format!("{:?}", location.path),
+            };
+            return error(format!(
+                "the comment at {} (line {}) cannot be rendered",
+                element,
+                location.span.first().map_or(0, |line| line + 1),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The name of the first group field or extension in `scope`, whose type
+// would be written as a reference to the group's message.
+fn find_group(
+    scope: &str,
+    extensions: &[prost_types::FieldDescriptorProto],
+    messages: &[prost_types::DescriptorProto],
+) -> Option<String> {
+    use prost_types::field_descriptor_proto::Type;
+
+    let qualify = |name: &str| match scope {
+        "" => name.to_owned(),
+        scope => format!("{}.{}", scope, name),
+    };
+    if let Some(group) = extensions.iter().find(|f| f.r#type() == Type::Group) {
+        return Some(qualify(group.name()));
+    }
+    messages.iter().find_map(|message| {
+        let name = qualify(message.name());
+        match message.field.iter().find(|f| f.r#type() == Type::Group) {
+            Some(group) => Some(format!("{}.{}", name, group.name())),
+            None => find_group(&name, &message.extension, &message.nested_type),
+        }
+    })
+}
+
+// The first element of `file` with an option that custom_options.rs could
+// not recover, which it keeps as an option without a name, and the option's
+// number.
+fn find_unknown_option(file: &prost_types::FileDescriptorProto) -> Option<(String, u64)> {
+    use prost_types::{
+        DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, UninterpretedOption,
+    };
+
+    fn unknown(options: Option<&[UninterpretedOption]>) -> Option<u64> {
+        options?
+            .iter()
+            .find(|option| option.name.is_empty())
+            .map(|option| option.positive_int_value())
+    }
+    fn qualify(scope: &str, name: &str) -> String {
+        match scope {
+            "" => name.to_owned(),
+            scope => format!("{}.{}", scope, name),
+        }
+    }
+    fn in_fields(scope: &str, fields: &[FieldDescriptorProto]) -> Option<(String, u64)> {
+        fields.iter().find_map(|field| {
+            let options = field.options.as_ref();
+            unknown(options.map(|o| &o.uninterpreted_option[..]))
+                .map(|number| (qualify(scope, field.name()), number))
+        })
+    }
+    fn in_enums(scope: &str, enums: &[EnumDescriptorProto]) -> Option<(String, u64)> {
+        enums.iter().find_map(|enum_type| {
+            let name = qualify(scope, enum_type.name());
+            let options = enum_type.options.as_ref();
+            if let Some(number) = unknown(options.map(|o| &o.uninterpreted_option[..])) {
+                return Some((name, number));
+            }
+            enum_type.value.iter().find_map(|value| {
+                let options = value.options.as_ref();
+                unknown(options.map(|o| &o.uninterpreted_option[..]))
+                    .map(|number| (qualify(scope, value.name()), number))
+            })
+        })
+    }
+    fn in_messages(scope: &str, messages: &[DescriptorProto]) -> Option<(String, u64)> {
+        messages.iter().find_map(|message| {
+            let name = qualify(scope, message.name());
+            let options = message.options.as_ref();
+            let oneofs = message.oneof_decl.iter().find_map(|oneof| {
+                let options = oneof.options.as_ref();
+                unknown(options.map(|o| &o.uninterpreted_option[..]))
+                    .map(|number| (qualify(&name, oneof.name()), number))
+            });
+            let ranges = message.extension_range.iter().find_map(|range| {
+                let options = range.options.as_ref();
+                unknown(options.map(|o| &o.uninterpreted_option[..]))
+                    .map(|number| (name.clone(), number))
+            });
+            unknown(options.map(|o| &o.uninterpreted_option[..]))
+                .map(|number| (name.clone(), number))
+                .or_else(|| in_fields(&name, &message.field))
+                .or_else(|| in_fields(&name, &message.extension))
+                .or(oneofs)
+                .or(ranges)
+                .or_else(|| in_messages(&name, &message.nested_type))
+                .or_else(|| in_enums(&name, &message.enum_type))
+        })
+    }
+
+    let options = file.options.as_ref();
+    if let Some(number) = unknown(options.map(|o| &o.uninterpreted_option[..])) {
+        return Some((file.name().to_owned(), number));
+    }
+    let scope = file.package();
+    let services = file.service.iter().find_map(|service| {
+        let name = qualify(scope, service.name());
+        let options = service.options.as_ref();
+        unknown(options.map(|o| &o.uninterpreted_option[..]))
+            .map(|number| (name.clone(), number))
+            .or_else(|| {
+                service.method.iter().find_map(|method| {
+                    let options = method.options.as_ref();
+                    unknown(options.map(|o| &o.uninterpreted_option[..]))
+                        .map(|number| (qualify(&name, method.name()), number))
+                })
+            })
+    });
+    in_messages(scope, &file.message_type)
+        .or_else(|| in_enums(scope, &file.enum_type))
+        .or(services)
+        .or_else(|| in_fields(scope, &file.extension))
+}
+
+// The syntax and comments of the file being converted, and the path of the
+// element being converted within it.
+#[derive(Default)]
+pub(crate) struct Source<'a> {
+    syntax: prost_types::Syntax,
+    // Sorted by path.
+    locations: Vec<&'a Location>,
+    path: Vec<i32>,
+    // Which of `locations` had their comments taken, when checking that
+    // none are lost.
+    taken: Option<Vec<Cell<bool>>>,
+    // Whether to keep everything that is written back to the source, see
+    // `GeneratorConfig::lossless`.
+    pub(crate) lossless: bool,
+}
+
+impl<'a> Source<'a> {
+    pub(crate) fn new(file: &'a prost_types::FileDescriptorProto) -> Source<'a> {
+        let syntax = match file.syntax.as_deref() {
+            Some("proto3") => prost_types::Syntax::Proto3,
+            _ => prost_types::Syntax::Proto2,
+        };
+
+        let mut locations = file
+            .source_code_info
+            .as_ref()
+            .map_or_else(Vec::new, |info| info.location.iter().collect::<Vec<_>>());
+        locations.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Source {
+            syntax,
+            locations,
+            path: Vec::with_capacity(10),
+            taken: None,
+            lossless: false,
+        }
+    }
+
+    // `elements` when rendering losslessly, or none of them otherwise.
+    fn lossless_only<'e, T>(&self, elements: &'e [T]) -> &'e [T] {
+        match self.lossless {
+            true => elements,
+            false => &[],
+        }
+    }
+
+    // Puts the items of each kind in the order they were written when
+    // rendering losslessly, or one kind after the other otherwise.
+    fn ordered<T>(&self, kinds: Vec<Vec<(Option<Position>, T)>>) -> Vec<T> {
+        match self.lossless {
+            true => interleaved(kinds),
+            false => kinds.into_iter().flatten().map(|(_, item)| item).collect(),
+        }
+    }
+
+    // Starts at the element at `path` of the file, for rendering it on its
+    // own with its comments.
+    #[cfg(feature = "reflect")]
+    pub(crate) fn at(mut self, path: &[i32]) -> Source<'a> {
+        self.path.extend_from_slice(path);
+        self
+    }
+
+    // Converts each of `elements`, found under field `field` of the current
+    // element.
+    fn each<'e, T, F>(&mut self, field: i32, elements: &'e [T], mut f: F)
+    where
+        F: FnMut(&mut Source<'a>, &'e T),
+    {
+        self.path.push(field);
+        for (i, element) in elements.iter().enumerate() {
+            self.path.push(i as i32);
+            f(self, element);
+            self.path.pop();
+        }
+        self.path.pop();
+    }
+
+    // The locations at `relative` to the current path: one for most
+    // elements, or one per statement for, e.g., `extend` blocks.
+    fn locations_at(&self, relative: &[i32]) -> std::ops::Range<usize> {
+        let path = || self.path.iter().chain(relative);
+        let start = self
+            .locations
+            .partition_point(|location| location.path.iter().lt(path()));
+        let len = self.locations[start..]
+            .iter()
+            .take_while(|location| location.path.iter().eq(path()))
+            .count();
+        start..start + len
+    }
+
+    // The comments of the element at `relative` to the current path.
+    fn comments_at(&self, relative: &[i32]) -> Comments<'a> {
+        let locations = self.locations_at(relative);
+        if locations.is_empty() {
+            return Comments::default();
+        }
+        self.comments_of(locations.start)
+    }
+
+    // The comments of the location at `index`.
+    fn comments_of(&self, index: usize) -> Comments<'a> {
+        if let Some(ref taken) = self.taken {
+            taken[index].set(true);
+        }
+        let location = self.locations[index];
+        Comments {
+            detached: location
+                .leading_detached_comments
+                .iter()
+                .map(|comment| Cow::Borrowed(comment.as_str()))
+                .collect(),
+            leading: location.leading_comments.as_deref().map(Cow::Borrowed),
+            trailing: location.trailing_comments.as_deref().map(Cow::Borrowed),
+        }
+    }
+
+    // Where the current element starts in the source.
+    fn position(&self) -> Option<Position> {
+        self.position_at(&[])
+    }
+
+    fn position_at(&self, relative: &[i32]) -> Option<Position> {
+        let locations = self.locations_at(relative);
+        if locations.is_empty() {
+            return None;
+        }
+        self.position_of(locations.start)
+    }
+
+    fn position_of(&self, index: usize) -> Option<Position> {
+        match self.locations[index].span[..] {
+            [line, column, ..] => Some((line, column)),
+            _ => None,
+        }
+    }
+
+    // The index of the location of the statement declaring element `index`
+    // under field `field` of the current element, e.g. the `extend` block
+    // of an extension: the one at `field` whose span contains the element's.
+    fn statement_of(&self, field: i32, index: usize) -> Option<usize> {
+        let element = self.locations_at(&[field, index as i32]);
+        let element = span_bounds(&self.locations.get(element.start)?.span)?;
+        self.locations_at(&[field]).find(|&i| {
+            span_bounds(&self.locations[i].span)
+                .is_some_and(|statement| statement.0 <= element.0 && element.1 <= statement.1)
+        })
+    }
+}
+
+// The start and end of a span, which has three numbers if it is on one line.
+fn span_bounds(span: &[i32]) -> Option<(Position, Position)> {
+    match *span {
+        [line, start, end] => Some(((line, start), (line, end))),
+        [start_line, start, end_line, end] => Some(((start_line, start), (end_line, end))),
+        _ => None,
+    }
+}
@@ -0,0 +1,11 @@
+use prost::Message;
+use std::io::{self, Read, Write};
+
+fn main() -> io::Result<()> {
+    let mut buf = Vec::new();
+    io::stdin().read_to_end(&mut buf)?;
+    let request = protobuf_gen::plugin::decode_request(&buf)?;
+
+    let response = protobuf_gen::plugin::generate(&request);
+    io::stdout().write_all(&response.encode_to_vec())
+}
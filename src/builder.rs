@@ -0,0 +1,710 @@
+//! Building descriptors in Rust.
+//!
+//! A [`FileBuilder`] fills in a `FileDescriptorProto` the way protoc would
+//! for the equivalent `.proto` source: type names are resolved to their
+//! fully-qualified form, map fields get their `FooEntry` message, proto3
+//! `optional` fields get their synthetic oneof, and every field gets its
+//! `json_name`.
+//!
+//! ```
+//! use protobuf_gen::builder::{FieldType, FileBuilder};
+//! use protobuf_gen::ProtobufString;
+//!
+//! let file = FileBuilder::new("user.proto")
+//!     .package("acme")
+//!     .message("User", |m| {
+//!         m.field("id", FieldType::Int64, 1)
+//!             .repeated("tags", FieldType::String, 2)
+//!             .map("labels", FieldType::String, FieldType::String, 3)
+//!             .optional("status", FieldType::enumeration("Status"), 4)
+//!     })
+//!     .enumeration("Status", |e| e.value("STATUS_UNKNOWN", 0).value("STATUS_ACTIVE", 1))
+//!     .build();
+//!
+//! let user = &file.message_type[0];
+//! assert_eq!(Some("LabelsEntry"), user.nested_type[0].name.as_deref());
+//! assert_eq!(Some(".acme.Status"), user.field[3].type_name.as_deref());
+//! assert!(file.to_protobuf(&file).contains("  map<string, string> labels = 3;\n"));
+//! ```
+//!
+//! Names of types that are not defined in the file, such as
+//! `google.protobuf.Timestamp`, are taken to be fully qualified.
+
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+    FileDescriptorProto, MessageOptions, MethodDescriptorProto, OneofDescriptorProto,
+    ServiceDescriptorProto,
+};
+use std::collections::HashMap;
+
+/// The type of a field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Bytes,
+    Uint32,
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
+    /// A message, by name as it would be written in the `.proto` file.
+    Message(std::string::String),
+    /// An enum, by name as it would be written in the `.proto` file.
+    Enum(std::string::String),
+    /// A message or enum, by name as it would be written in the `.proto`
+    /// file. Which one it is is looked up when the file is built; a type
+    /// that is not defined in the file is taken to be a message.
+    Named(std::string::String),
+}
+
+impl FieldType {
+    pub fn message<S: Into<std::string::String>>(name: S) -> FieldType {
+        FieldType::Message(name.into())
+    }
+
+    pub fn enumeration<S: Into<std::string::String>>(name: S) -> FieldType {
+        FieldType::Enum(name.into())
+    }
+
+    fn to_type(&self) -> Type {
+        match self {
+            FieldType::Double => Type::Double,
+            FieldType::Float => Type::Float,
+            FieldType::Int64 => Type::Int64,
+            FieldType::Uint64 => Type::Uint64,
+            FieldType::Int32 => Type::Int32,
+            FieldType::Fixed64 => Type::Fixed64,
+            FieldType::Fixed32 => Type::Fixed32,
+            FieldType::Bool => Type::Bool,
+            FieldType::String => Type::String,
+            FieldType::Bytes => Type::Bytes,
+            FieldType::Uint32 => Type::Uint32,
+            FieldType::Sfixed32 => Type::Sfixed32,
+            FieldType::Sfixed64 => Type::Sfixed64,
+            FieldType::Sint32 => Type::Sint32,
+            FieldType::Sint64 => Type::Sint64,
+            FieldType::Message(_) | FieldType::Named(_) => Type::Message,
+            FieldType::Enum(_) => Type::Enum,
+        }
+    }
+
+    fn type_name(&self) -> Option<&str> {
+        match self {
+            FieldType::Message(name) | FieldType::Enum(name) | FieldType::Named(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Builds a `FileDescriptorProto`.
+#[derive(Clone, Debug)]
+pub struct FileBuilder {
+    file: FileDescriptorProto,
+}
+
+/// Builds a message, in the closure passed to [`FileBuilder::message`] or
+/// [`MessageBuilder::message`].
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    message: DescriptorProto,
+    proto3: bool,
+    // Fields declared `optional` in proto3, whose synthetic oneofs are added
+    // after the others when the message is built.
+    proto3_optional: Vec<usize>,
+}
+
+/// Builds a field, in the closure passed to [`MessageBuilder::field_with`].
+#[derive(Clone, Debug)]
+pub struct FieldBuilder {
+    field: FieldDescriptorProto,
+}
+
+/// Builds the fields of a oneof, in the closure passed to
+/// [`MessageBuilder::oneof`].
+#[derive(Clone, Debug)]
+pub struct OneofBuilder {
+    fields: Vec<FieldDescriptorProto>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EnumBuilder {
+    enumeration: EnumDescriptorProto,
+}
+
+#[derive(Clone, Debug)]
+pub struct ServiceBuilder {
+    service: ServiceDescriptorProto,
+}
+
+impl FileBuilder {
+    /// Starts a proto3 file named `name`.
+    pub fn new<S: Into<std::string::String>>(name: S) -> FileBuilder {
+        FileBuilder {
+            file: FileDescriptorProto {
+                name: Some(name.into()),
+                syntax: Some("proto3".to_owned()),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Sets the syntax, `"proto2"` or `"proto3"`.
+    pub fn syntax(mut self, syntax: &str) -> FileBuilder {
+        self.file.syntax = match syntax {
+            "proto2" => None,
+            _ => Some(syntax.to_owned()),
+        };
+        self
+    }
+
+    pub fn package(mut self, package: &str) -> FileBuilder {
+        self.file.package = Some(package.to_owned());
+        self
+    }
+
+    pub fn import(mut self, name: &str) -> FileBuilder {
+        self.file.dependency.push(name.to_owned());
+        self
+    }
+
+    pub fn message<F>(mut self, name: &str, build: F) -> FileBuilder
+    where
+        F: FnOnce(MessageBuilder) -> MessageBuilder,
+    {
+        let proto3 = self.file.syntax() == "proto3";
+        let message = build(MessageBuilder::new(name, proto3)).finish();
+        self.file.message_type.push(message);
+        self
+    }
+
+    pub fn enumeration<F>(mut self, name: &str, build: F) -> FileBuilder
+    where
+        F: FnOnce(EnumBuilder) -> EnumBuilder,
+    {
+        let enumeration = build(EnumBuilder::new(name)).enumeration;
+        self.file.enum_type.push(enumeration);
+        self
+    }
+
+    pub fn service<F>(mut self, name: &str, build: F) -> FileBuilder
+    where
+        F: FnOnce(ServiceBuilder) -> ServiceBuilder,
+    {
+        let service = ServiceBuilder {
+            service: ServiceDescriptorProto {
+                name: Some(name.to_owned()),
+                ..Default::default()
+            },
+        };
+        self.file.service.push(build(service).service);
+        self
+    }
+
+    /// Resolves the type names and returns the file.
+    pub fn build(mut self) -> FileDescriptorProto {
+        let scope = match self.file.package() {
+            "" => std::string::String::new(),
+            package => format!(".{}", package),
+        };
+        let mut defined = HashMap::new();
+        for message in &self.file.message_type {
+            define_message(&scope, message, &mut defined);
+        }
+        for enumeration in &self.file.enum_type {
+            defined.insert(format!("{}.{}", scope, enumeration.name()), Type::Enum);
+        }
+
+        for message in &mut self.file.message_type {
+            resolve_message(&scope, message, &defined);
+        }
+        for service in &mut self.file.service {
+            for method in &mut service.method {
+                let names = method.input_type.iter_mut();
+                for name in names.chain(method.output_type.iter_mut()) {
+                    *name = resolve(&scope, name, &defined);
+                }
+            }
+        }
+        self.file
+    }
+}
+
+impl MessageBuilder {
+    fn new(name: &str, proto3: bool) -> MessageBuilder {
+        MessageBuilder {
+            message: DescriptorProto {
+                name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            proto3,
+            proto3_optional: Vec::new(),
+        }
+    }
+
+    /// A singular field, without presence in proto3.
+    pub fn field(self, name: &str, typ: FieldType, number: i32) -> MessageBuilder {
+        self.field_with(name, typ, number, |f| f)
+    }
+
+    /// A singular field, customized by `build`.
+    pub fn field_with<F>(self, name: &str, typ: FieldType, number: i32, build: F) -> MessageBuilder
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        self.push_field(Label::Optional, name, typ, number, build)
+    }
+
+    /// An `optional` field, which has presence even in proto3.
+    pub fn optional(self, name: &str, typ: FieldType, number: i32) -> MessageBuilder {
+        self.optional_with(name, typ, number, |f| f)
+    }
+
+    pub fn optional_with<F>(
+        mut self,
+        name: &str,
+        typ: FieldType,
+        number: i32,
+        build: F,
+    ) -> MessageBuilder
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        if self.proto3 {
+            let index = self.message.field.len();
+            self.proto3_optional.push(index);
+            return self.push_field(Label::Optional, name, typ, number, build);
+        }
+        self.push_field(Label::Optional, name, typ, number, build)
+    }
+
+    /// A `required` field, which only proto2 has.
+    pub fn required(self, name: &str, typ: FieldType, number: i32) -> MessageBuilder {
+        self.required_with(name, typ, number, |f| f)
+    }
+
+    pub fn required_with<F>(
+        self,
+        name: &str,
+        typ: FieldType,
+        number: i32,
+        build: F,
+    ) -> MessageBuilder
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        self.push_field(Label::Required, name, typ, number, build)
+    }
+
+    pub fn repeated(self, name: &str, typ: FieldType, number: i32) -> MessageBuilder {
+        self.repeated_with(name, typ, number, |f| f)
+    }
+
+    pub fn repeated_with<F>(
+        self,
+        name: &str,
+        typ: FieldType,
+        number: i32,
+        build: F,
+    ) -> MessageBuilder
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        self.push_field(Label::Repeated, name, typ, number, build)
+    }
+
+    /// A `map<key, value>` field, with its nested entry message.
+    pub fn map(
+        mut self,
+        name: &str,
+        key: FieldType,
+        value: FieldType,
+        number: i32,
+    ) -> MessageBuilder {
+        let entry_name = map_entry_name(name);
+        let key = FieldBuilder::new("key", key, 1, Label::Optional).field;
+        let value = FieldBuilder::new("value", value, 2, Label::Optional).field;
+        self.message.nested_type.push(DescriptorProto {
+            name: Some(entry_name.clone()),
+            field: vec![key, value],
+            options: Some(MessageOptions {
+                map_entry: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        self.repeated(name, FieldType::Message(entry_name), number)
+    }
+
+    /// A oneof, with the fields added by `build`.
+    pub fn oneof<F>(mut self, name: &str, build: F) -> MessageBuilder
+    where
+        F: FnOnce(OneofBuilder) -> OneofBuilder,
+    {
+        let index = self.message.oneof_decl.len() as i32;
+        self.message.oneof_decl.push(OneofDescriptorProto {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        });
+        for mut field in build(OneofBuilder { fields: Vec::new() }).fields {
+            field.oneof_index = Some(index);
+            self.message.field.push(field);
+        }
+        self
+    }
+
+    pub fn message<F>(mut self, name: &str, build: F) -> MessageBuilder
+    where
+        F: FnOnce(MessageBuilder) -> MessageBuilder,
+    {
+        let message = build(MessageBuilder::new(name, self.proto3)).finish();
+        self.message.nested_type.push(message);
+        self
+    }
+
+    pub fn enumeration<F>(mut self, name: &str, build: F) -> MessageBuilder
+    where
+        F: FnOnce(EnumBuilder) -> EnumBuilder,
+    {
+        let enumeration = build(EnumBuilder::new(name)).enumeration;
+        self.message.enum_type.push(enumeration);
+        self
+    }
+
+    /// Reserves the field numbers `start` to `end`, inclusive.
+    pub fn reserved_range(mut self, start: i32, end: i32) -> MessageBuilder {
+        self.message
+            .reserved_range
+            .push(prost_types::descriptor_proto::ReservedRange {
+                start: Some(start),
+                end: Some(end + 1),
+            });
+        self
+    }
+
+    pub fn reserved_name(mut self, name: &str) -> MessageBuilder {
+        self.message.reserved_name.push(name.to_owned());
+        self
+    }
+
+    fn push_field<F>(
+        mut self,
+        label: Label,
+        name: &str,
+        typ: FieldType,
+        number: i32,
+        build: F,
+    ) -> MessageBuilder
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        let field = build(FieldBuilder::new(name, typ, number, label)).field;
+        self.message.field.push(field);
+        self
+    }
+
+    // Adds the synthetic oneofs of proto3 `optional` fields after the others,
+    // as protoc does.
+    fn finish(mut self) -> DescriptorProto {
+        for i in std::mem::take(&mut self.proto3_optional) {
+            make_proto3_optional(&mut self.message, i);
+        }
+        self.message
+    }
+}
+
+impl FieldBuilder {
+    fn new(name: &str, typ: FieldType, number: i32, label: Label) -> FieldBuilder {
+        FieldBuilder {
+            field: FieldDescriptorProto {
+                name: Some(name.to_owned()),
+                number: Some(number),
+                label: Some(label as i32),
+                r#type: Some(typ.to_type() as i32),
+                type_name: typ.type_name().map(str::to_owned),
+                json_name: Some(json_name(name)),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Overrides the `json_name` derived from the field name.
+    pub fn json_name(mut self, json_name: &str) -> FieldBuilder {
+        self.field.json_name = Some(json_name.to_owned());
+        self
+    }
+
+    /// The default value of a proto2 field, as written in the `.proto` file
+    /// without quotes.
+    pub fn default_value(mut self, value: &str) -> FieldBuilder {
+        self.field.default_value = Some(value.to_owned());
+        self
+    }
+
+    pub fn deprecated(mut self, deprecated: bool) -> FieldBuilder {
+        self.field
+            .options
+            .get_or_insert_with(Default::default)
+            .deprecated = Some(deprecated);
+        self
+    }
+
+    pub fn packed(mut self, packed: bool) -> FieldBuilder {
+        self.field
+            .options
+            .get_or_insert_with(Default::default)
+            .packed = Some(packed);
+        self
+    }
+
+    pub fn options(mut self, options: prost_types::FieldOptions) -> FieldBuilder {
+        self.field.options = Some(options);
+        self
+    }
+}
+
+impl OneofBuilder {
+    pub fn field(self, name: &str, typ: FieldType, number: i32) -> OneofBuilder {
+        self.field_with(name, typ, number, |f| f)
+    }
+
+    pub fn field_with<F>(
+        mut self,
+        name: &str,
+        typ: FieldType,
+        number: i32,
+        build: F,
+    ) -> OneofBuilder
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        let field = build(FieldBuilder::new(name, typ, number, Label::Optional)).field;
+        self.fields.push(field);
+        self
+    }
+}
+
+impl EnumBuilder {
+    fn new(name: &str) -> EnumBuilder {
+        EnumBuilder {
+            enumeration: EnumDescriptorProto {
+                name: Some(name.to_owned()),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn value(mut self, name: &str, number: i32) -> EnumBuilder {
+        self.enumeration.value.push(EnumValueDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Reserves the numbers `start` to `end`, inclusive.
+    pub fn reserved_range(mut self, start: i32, end: i32) -> EnumBuilder {
+        self.enumeration.reserved_range.push(
+            prost_types::enum_descriptor_proto::EnumReservedRange {
+                start: Some(start),
+                end: Some(end),
+            },
+        );
+        self
+    }
+
+    pub fn reserved_name(mut self, name: &str) -> EnumBuilder {
+        self.enumeration.reserved_name.push(name.to_owned());
+        self
+    }
+}
+
+impl ServiceBuilder {
+    /// A unary method; `input` and `output` are message names as they would
+    /// be written in the `.proto` file.
+    pub fn method(self, name: &str, input: &str, output: &str) -> ServiceBuilder {
+        self.streaming_method(name, input, false, output, false)
+    }
+
+    pub fn streaming_method(
+        mut self,
+        name: &str,
+        input: &str,
+        client_streaming: bool,
+        output: &str,
+        server_streaming: bool,
+    ) -> ServiceBuilder {
+        self.service.method.push(MethodDescriptorProto {
+            name: Some(name.to_owned()),
+            input_type: Some(input.to_owned()),
+            output_type: Some(output.to_owned()),
+            client_streaming: Some(client_streaming).filter(|&s| s),
+            server_streaming: Some(server_streaming).filter(|&s| s),
+            ..Default::default()
+        });
+        self
+    }
+}
+
+// Adds the full names of `message` and the types nested in it, with whether
+// each is a message or an enum.
+fn define_message(
+    scope: &str,
+    message: &DescriptorProto,
+    defined: &mut HashMap<std::string::String, Type>,
+) {
+    let name = format!("{}.{}", scope, message.name());
+    for nested in &message.nested_type {
+        define_message(&name, nested, defined);
+    }
+    for enumeration in &message.enum_type {
+        defined.insert(format!("{}.{}", name, enumeration.name()), Type::Enum);
+    }
+    defined.insert(name, Type::Message);
+}
+
+fn resolve_message(
+    scope: &str,
+    message: &mut DescriptorProto,
+    defined: &HashMap<std::string::String, Type>,
+) {
+    let name = format!("{}.{}", scope, message.name());
+    for field in &mut message.field {
+        if let Some(ref mut type_name) = field.type_name {
+            *type_name = resolve(&name, type_name, defined);
+            if let Some(&typ) = defined.get(type_name.as_str()) {
+                field.r#type = Some(typ as i32);
+            }
+        }
+    }
+    for nested in &mut message.nested_type {
+        resolve_message(&name, nested, defined);
+    }
+}
+
+// Resolves a type name the way protoc does: its first component is looked
+// up in `scope`, then in each enclosing scope. A name that is not defined in
+// the file is taken to be fully qualified.
+fn resolve(
+    scope: &str,
+    name: &str,
+    defined: &HashMap<std::string::String, Type>,
+) -> std::string::String {
+    if name.starts_with('.') {
+        return name.to_owned();
+    }
+    let first = name.split('.').next().unwrap_or(name);
+    let mut scope = scope;
+    loop {
+        if defined.contains_key(&format!("{}.{}", scope, first)) {
+            return format!("{}.{}", scope, name);
+        }
+        match scope.rfind('.') {
+            Some(i) => scope = &scope[..i],
+            None => return format!(".{}", name),
+        }
+    }
+}
+
+// The name protoc gives the entry message of a map field: the field name in
+// camel case, starting with a capital, followed by `Entry`.
+fn map_entry_name(field: &str) -> std::string::String {
+    let mut name = std::string::String::with_capacity(field.len() + 5);
+    let mut capitalize_next = true;
+    for c in field.chars() {
+        if c == '_' {
+            capitalize_next = true;
+        } else if capitalize_next {
+            name.push(c.to_ascii_uppercase());
+            capitalize_next = false;
+        } else {
+            name.push(c);
+        }
+    }
+    name.push_str("Entry");
+    name
+}
+
+// The `json_name` protoc derives from a field name: with underscores
+// dropped and the letter after each one capitalized.
+pub(crate) fn json_name(field: &str) -> std::string::String {
+    let mut name = std::string::String::with_capacity(field.len());
+    let mut capitalize_next = false;
+    for c in field.chars() {
+        if c == '_' {
+            capitalize_next = true;
+        } else if capitalize_next {
+            name.push(c.to_ascii_uppercase());
+            capitalize_next = false;
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
+
+// Renames `field`, along with its `json_name` if that was derived from the
+// old name.
+pub(crate) fn rename_field(field: &mut FieldDescriptorProto, name: std::string::String) {
+    if field.json_name.as_deref() == Some(&*json_name(field.name())) {
+        field.json_name = Some(json_name(&name));
+    }
+    field.name = Some(name);
+}
+
+/// Makes the field at `index` of `message` a proto3 `optional` field, as
+/// protoc builds one: `proto3_optional` is set and the field is put in a
+/// synthetic oneof of its own, added after the other oneofs.
+///
+/// ```
+/// use protobuf_gen::builder::{make_proto3_optional, FieldType, FileBuilder};
+///
+/// let mut file = FileBuilder::new("user.proto")
+///     .message("User", |m| m.field("name", FieldType::String, 1))
+///     .build();
+/// let user = &mut file.message_type[0];
+/// make_proto3_optional(user, 0);
+/// assert_eq!(Some(0), user.field[0].oneof_index);
+/// assert_eq!(Some("_name"), user.oneof_decl[0].name.as_deref());
+/// ```
+///
+/// Does nothing if the field already is one, and panics if it is in a
+/// oneof otherwise.
+pub fn make_proto3_optional(message: &mut DescriptorProto, index: usize) {
+    let field = &message.field[index];
+    if field.oneof_index.is_some() {
+        assert!(field.proto3_optional(), "{} is in a oneof", field.name());
+        return;
+    }
+    let name = synthetic_oneof_name(message, index);
+    let field = &mut message.field[index];
+    field.proto3_optional = Some(true);
+    field.oneof_index = Some(message.oneof_decl.len() as i32);
+    message.oneof_decl.push(OneofDescriptorProto {
+        name: Some(name),
+        ..Default::default()
+    });
+}
+
+// The name protoc gives the synthetic oneof of the `optional` field at
+// `index`: the field name after an underscore, prefixed with `X` until it
+// does not clash with a field or oneof of the message.
+pub(crate) fn synthetic_oneof_name(message: &DescriptorProto, index: usize) -> std::string::String {
+    let mut name = format!("_{}", message.field[index].name());
+    while message.field.iter().any(|f| f.name() == name)
+        || message.oneof_decl.iter().any(|o| o.name() == name)
+    {
+        name.insert(0, 'X');
+    }
+    name
+}
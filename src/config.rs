@@ -0,0 +1,123 @@
+//! Project configuration.
+//!
+//! A `protobuf-gen.toml` next to the `.proto` files, or in any directory
+//! above them, pins the settings used to compile and render them, so that
+//! every developer and CI job produces identical output:
+//!
+//! ```toml
+//! # Import paths, relative to this file.
+//! includes = ["protos", "third_party"]
+//! # protoc to run; looked up on $PATH unless it contains a `/`.
+//! protoc = "tools/protoc"
+//! # Rewrite rules applied before rendering, see `rules`.
+//! rules = ["rules.toml"]
+//!
+//! # Generator options, see `GeneratorConfig`.
+//! [format]
+//! indent = 4
+//! ```
+
+use crate::rules::Rules;
+use crate::GeneratorConfig;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = "protobuf-gen.toml";
+
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub includes: Vec<PathBuf>,
+    pub protoc: Option<PathBuf>,
+    pub rules: Vec<PathBuf>,
+    pub format: GeneratorConfig,
+}
+
+impl Config {
+    /// Finds the closest `protobuf-gen.toml` in `start` (a file or a
+    /// directory) or any of its ancestors, and returns its path along with the
+    /// parsed config.
+    pub fn discover<P>(start: P) -> io::Result<Option<(PathBuf, Config)>>
+    where
+        P: AsRef<Path>,
+    {
+        let start = start.as_ref().canonicalize()?;
+        for dir in start.ancestors() {
+            let path = dir.join(FILE_NAME);
+            if path.is_file() {
+                let config = Config::from_file(&path)?;
+                return Ok(Some((path, config)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads a config file. Relative paths in it are resolved against the
+    /// directory containing the file.
+    pub fn from_file<P>(path: P) -> io::Result<Config>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut config = Config::from_toml(&text)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for include in &mut config.includes {
+            *include = base.join(&*include);
+        }
+        for rules in &mut config.rules {
+            *rules = base.join(&*rules);
+        }
+        if let Some(ref mut protoc) = config.protoc {
+            if protoc.components().count() > 1 {
+                *protoc = base.join(&*protoc);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> io::Result<Config> {
+        toml::from_str(text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// The protoc executable to run, `protoc` from `$PATH` unless configured.
+    pub fn protoc(&self) -> &Path {
+        self.protoc
+            .as_deref()
+            .unwrap_or_else(|| Path::new("protoc"))
+    }
+
+    /// Reads the configured rules files, in order.
+    pub fn rules(&self) -> io::Result<Rules> {
+        let mut rules = Rules::default();
+        for path in &self.rules {
+            rules.extend(Rules::from_file(path)?);
+        }
+        Ok(rules)
+    }
+
+    /// Compiles `protos` with the configured protoc, searching `includes`
+    /// first and then the configured include paths.
+    pub fn read_files<P>(
+        &self,
+        protos: &[P],
+        includes: &[P],
+    ) -> io::Result<prost_types::FileDescriptorSet>
+    where
+        P: AsRef<Path>,
+    {
+        let tmp = tempfile::Builder::new().prefix("protobuf-gen").tempdir()?;
+
+        let protos: Vec<&Path> = protos.iter().map(AsRef::as_ref).collect();
+        let includes: Vec<&Path> = includes
+            .iter()
+            .map(AsRef::as_ref)
+            .chain(self.includes.iter().map(PathBuf::as_path))
+            .collect();
+        crate::compile(self.protoc(), tmp.path(), &protos, &includes)
+    }
+}
@@ -0,0 +1,826 @@
+// Custom options, which protoc stores as extensions of the options messages
+// of descriptor.proto, e.g. `FieldOptions`.
+//
+// prost has no place for extensions and drops them while decoding. So the
+// options are decoded a second time through a view of the descriptors that
+// keeps their raw bytes, and the extensions found there that are defined in
+// the same set are put back in `uninterpreted_option` form, the one protoc
+// uses before interpreting them and the one options.rs renders. Their
+// locations in `SourceCodeInfo` are moved along, from under the extension's
+// number to under `uninterpreted_option`, so that comments follow them.
+//
+// The same is done for the standard options prost-types predates, such as
+// `debug_redact` or `retention`, which are named without parentheses. Any
+// other field of an options message is kept as an option without a name
+// whose `positive_int_value` is the field's number: it is not rendered, and
+// `ast::check_lossless` rejects the files that have one.
+
+use crate::path;
+use prost::bytes::Buf;
+use prost::encoding::{decode_key, decode_varint, skip_field, DecodeContext, WireType};
+use prost::{DecodeError, Message};
+use prost_types::compiler::CodeGeneratorRequest;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::uninterpreted_option::NamePart;
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    FileDescriptorSet, UninterpretedOption,
+};
+use std::collections::HashMap;
+
+/// Decodes a `FileDescriptorSet`, keeping its custom options.
+pub(crate) fn decode_set(bytes: &[u8]) -> Result<FileDescriptorSet, DecodeError> {
+    let mut set = FileDescriptorSet::decode(bytes)?;
+    if has_custom_options(&set.file) || set.encoded_len() != bytes.len() {
+        let raw = RawSet::decode(bytes)?;
+        recover(&mut set.file, &raw.file)?;
+    }
+    Ok(set)
+}
+
+/// Decodes a `CodeGeneratorRequest`, keeping the custom options of its files.
+pub(crate) fn decode_request(bytes: &[u8]) -> Result<CodeGeneratorRequest, DecodeError> {
+    let mut request = CodeGeneratorRequest::decode(bytes)?;
+    if has_custom_options(&request.proto_file) || request.encoded_len() != bytes.len() {
+        let raw = RawRequest::decode(bytes)?;
+        recover(&mut request.proto_file, &raw.proto_file)?;
+    }
+    Ok(request)
+}
+
+// The descriptors down to their options, whose bytes are kept as they are.
+// Fields are numbered as in descriptor.proto.
+
+#[derive(Clone, PartialEq, Message)]
+struct RawSet {
+    #[prost(message, repeated, tag = "1")]
+    file: Vec<RawFile>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RawRequest {
+    #[prost(message, repeated, tag = "15")]
+    proto_file: Vec<RawFile>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RawFile {
+    #[prost(message, repeated, tag = "4")]
+    message_type: Vec<RawMessage>,
+    #[prost(message, repeated, tag = "5")]
+    enum_type: Vec<RawEnum>,
+    #[prost(message, repeated, tag = "6")]
+    service: Vec<RawService>,
+    #[prost(message, repeated, tag = "7")]
+    extension: Vec<RawField>,
+    #[prost(bytes = "vec", optional, tag = "8")]
+    options: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RawMessage {
+    #[prost(message, repeated, tag = "2")]
+    field: Vec<RawField>,
+    #[prost(message, repeated, tag = "3")]
+    nested_type: Vec<RawMessage>,
+    #[prost(message, repeated, tag = "4")]
+    enum_type: Vec<RawEnum>,
+    #[prost(message, repeated, tag = "5")]
+    extension_range: Vec<RawOptions<3>>,
+    #[prost(message, repeated, tag = "6")]
+    extension: Vec<RawField>,
+    #[prost(bytes = "vec", optional, tag = "7")]
+    options: Option<Vec<u8>>,
+    #[prost(message, repeated, tag = "8")]
+    oneof_decl: Vec<RawOptions<2>>,
+}
+
+type RawField = RawOptions<8>;
+
+#[derive(Clone, PartialEq, Message)]
+struct RawEnum {
+    #[prost(message, repeated, tag = "2")]
+    value: Vec<RawOptions<3>>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    options: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RawService {
+    #[prost(message, repeated, tag = "2")]
+    method: Vec<RawOptions<4>>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    options: Option<Vec<u8>>,
+}
+
+// An element whose only part of interest is its options, at field `N`.
+#[derive(Clone, Debug, PartialEq, Default)]
+struct RawOptions<const N: u32> {
+    options: Option<Vec<u8>>,
+}
+
+impl<const N: u32> Message for RawOptions<N> {
+    fn encode_raw(&self, buf: &mut impl prost::bytes::BufMut) {
+        if let Some(ref options) = self.options {
+            prost::encoding::bytes::encode(N, options, buf);
+        }
+    }
+
+    fn merge_field(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut impl Buf,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        if tag == N {
+            let options = self.options.get_or_insert_with(Vec::new);
+            prost::encoding::bytes::merge(wire_type, options, buf, ctx)
+        } else {
+            skip_field(wire_type, tag, buf, ctx)
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.options
+            .as_ref()
+            .map_or(0, |options| prost::encoding::bytes::encoded_len(N, options))
+    }
+
+    fn clear(&mut self) {
+        self.options = None;
+    }
+}
+
+// Whether any file defines an extension of an options message. Otherwise
+// there is only something to recover if prost dropped fields, which makes
+// the decoded descriptors shorter than their encoding.
+fn has_custom_options(files: &[FileDescriptorProto]) -> bool {
+    fn extends_options(extensions: &[FieldDescriptorProto]) -> bool {
+        extensions.iter().any(|e| {
+            e.extendee().starts_with(".google.protobuf.") && e.extendee().ends_with("Options")
+        })
+    }
+    fn in_message(message: &DescriptorProto) -> bool {
+        extends_options(&message.extension) || message.nested_type.iter().any(in_message)
+    }
+    files
+        .iter()
+        .any(|f| extends_options(&f.extension) || f.message_type.iter().any(in_message))
+}
+
+fn recover(files: &mut [FileDescriptorProto], raw: &[RawFile]) -> Result<(), DecodeError> {
+    let snapshot = files.to_vec();
+    let index = Index::new(&snapshot);
+    for (file, raw) in files.iter_mut().zip(raw) {
+        let mut recovery = Recovery {
+            index: &index,
+            path: Vec::new(),
+            moved: HashMap::new(),
+        };
+        recovery.file(file, raw)?;
+        if let Some(ref mut info) = file.source_code_info {
+            recovery.move_locations(&mut info.location);
+        }
+    }
+    Ok(())
+}
+
+// The definitions options are decoded against, by fully-qualified name with
+// a leading dot.
+struct Index<'a> {
+    extensions: HashMap<(&'a str, i32), (String, &'a FieldDescriptorProto)>,
+    messages: HashMap<String, &'a DescriptorProto>,
+    enums: HashMap<String, &'a EnumDescriptorProto>,
+}
+
+impl<'a> Index<'a> {
+    fn new(files: &'a [FileDescriptorProto]) -> Index<'a> {
+        let mut index = Index {
+            extensions: HashMap::new(),
+            messages: HashMap::new(),
+            enums: HashMap::new(),
+        };
+        for file in files {
+            let scope = match file.package() {
+                "" => String::new(),
+                package => format!(".{}", package),
+            };
+            index.add(&scope, &file.extension, &file.message_type, &file.enum_type);
+        }
+        index
+    }
+
+    fn add(
+        &mut self,
+        scope: &str,
+        extensions: &'a [FieldDescriptorProto],
+        messages: &'a [DescriptorProto],
+        enums: &'a [EnumDescriptorProto],
+    ) {
+        for extension in extensions {
+            let name = format!("{}.{}", scope, extension.name());
+            self.extensions.insert(
+                (extension.extendee(), extension.number()),
+                (name, extension),
+            );
+        }
+        for enum_type in enums {
+            self.enums
+                .insert(format!("{}.{}", scope, enum_type.name()), enum_type);
+        }
+        for message in messages {
+            let name = format!("{}.{}", scope, message.name());
+            self.add(
+                &name,
+                &message.extension,
+                &message.nested_type,
+                &message.enum_type,
+            );
+            self.messages.insert(name, message);
+        }
+    }
+}
+
+struct Recovery<'i, 'a> {
+    index: &'i Index<'a>,
+    // The path of the element whose options are being recovered.
+    path: Vec<i32>,
+    // For each custom option recovered, by the path of its options and its
+    // number, the indexes in `uninterpreted_option` it was put at: one for
+    // each value of a repeated option, otherwise a single one.
+    moved: HashMap<Vec<i32>, (bool, Vec<i32>)>,
+}
+
+impl Recovery<'_, '_> {
+    fn file(&mut self, file: &mut FileDescriptorProto, raw: &RawFile) -> Result<(), DecodeError> {
+        if let Some(ref mut options) = file.options {
+            self.options(
+                path::FILE_OPTIONS,
+                "FileOptions",
+                &raw.options,
+                &mut options.uninterpreted_option,
+            )?;
+        }
+        for (i, (message, raw)) in file
+            .message_type
+            .iter_mut()
+            .zip(&raw.message_type)
+            .enumerate()
+        {
+            self.path.extend([path::FILE_MESSAGE_TYPE, i as i32]);
+            self.message(message, raw)?;
+            self.path.truncate(self.path.len() - 2);
+        }
+        for (i, (enum_type, raw)) in file.enum_type.iter_mut().zip(&raw.enum_type).enumerate() {
+            self.path.extend([path::FILE_ENUM_TYPE, i as i32]);
+            self.enumeration(enum_type, raw)?;
+            self.path.truncate(self.path.len() - 2);
+        }
+        for (i, (service, raw)) in file.service.iter_mut().zip(&raw.service).enumerate() {
+            self.path.extend([path::FILE_SERVICE, i as i32]);
+            if let Some(ref mut options) = service.options {
+                self.options(
+                    path::SERVICE_OPTIONS,
+                    "ServiceOptions",
+                    &raw.options,
+                    &mut options.uninterpreted_option,
+                )?;
+            }
+            for (j, (method, raw)) in service.method.iter_mut().zip(&raw.method).enumerate() {
+                self.path.extend([path::SERVICE_METHOD, j as i32]);
+                if let Some(ref mut options) = method.options {
+                    self.options(
+                        path::METHOD_OPTIONS,
+                        "MethodOptions",
+                        &raw.options,
+                        &mut options.uninterpreted_option,
+                    )?;
+                }
+                self.path.truncate(self.path.len() - 2);
+            }
+            self.path.truncate(self.path.len() - 2);
+        }
+        self.fields(path::FILE_EXTENSION, &mut file.extension, &raw.extension)
+    }
+
+    fn message(
+        &mut self,
+        message: &mut DescriptorProto,
+        raw: &RawMessage,
+    ) -> Result<(), DecodeError> {
+        if let Some(ref mut options) = message.options {
+            self.options(
+                path::MESSAGE_OPTIONS,
+                "MessageOptions",
+                &raw.options,
+                &mut options.uninterpreted_option,
+            )?;
+        }
+        self.fields(path::MESSAGE_FIELD, &mut message.field, &raw.field)?;
+        self.fields(
+            path::MESSAGE_EXTENSION,
+            &mut message.extension,
+            &raw.extension,
+        )?;
+        for (i, (oneof, raw)) in message
+            .oneof_decl
+            .iter_mut()
+            .zip(&raw.oneof_decl)
+            .enumerate()
+        {
+            if let Some(ref mut options) = oneof.options {
+                self.path.extend([path::MESSAGE_ONEOF_DECL, i as i32]);
+                self.options(
+                    path::ONEOF_OPTIONS,
+                    "OneofOptions",
+                    &raw.options,
+                    &mut options.uninterpreted_option,
+                )?;
+                self.path.truncate(self.path.len() - 2);
+            }
+        }
+        let ranges = message.extension_range.iter_mut().zip(&raw.extension_range);
+        for (i, (range, raw)) in ranges.enumerate() {
+            if let Some(ref mut options) = range.options {
+                self.path.extend([path::MESSAGE_EXTENSION_RANGE, i as i32]);
+                self.options(
+                    path::EXTENSION_RANGE_OPTIONS,
+                    "ExtensionRangeOptions",
+                    &raw.options,
+                    &mut options.uninterpreted_option,
+                )?;
+                self.path.truncate(self.path.len() - 2);
+            }
+        }
+        let nested_types = message.nested_type.iter_mut().zip(&raw.nested_type);
+        for (i, (nested_type, raw)) in nested_types.enumerate() {
+            self.path.extend([path::MESSAGE_NESTED_TYPE, i as i32]);
+            self.message(nested_type, raw)?;
+            self.path.truncate(self.path.len() - 2);
+        }
+        for (i, (enum_type, raw)) in message.enum_type.iter_mut().zip(&raw.enum_type).enumerate() {
+            self.path.extend([path::MESSAGE_ENUM_TYPE, i as i32]);
+            self.enumeration(enum_type, raw)?;
+            self.path.truncate(self.path.len() - 2);
+        }
+        Ok(())
+    }
+
+    fn fields(
+        &mut self,
+        field: i32,
+        fields: &mut [FieldDescriptorProto],
+        raw: &[RawField],
+    ) -> Result<(), DecodeError> {
+        for (i, (descriptor, raw)) in fields.iter_mut().zip(raw).enumerate() {
+            if let Some(ref mut options) = descriptor.options {
+                self.path.extend([field, i as i32]);
+                self.options(
+                    path::FIELD_OPTIONS,
+                    "FieldOptions",
+                    &raw.options,
+                    &mut options.uninterpreted_option,
+                )?;
+                self.path.truncate(self.path.len() - 2);
+            }
+        }
+        Ok(())
+    }
+
+    fn enumeration(
+        &mut self,
+        enum_type: &mut EnumDescriptorProto,
+        raw: &RawEnum,
+    ) -> Result<(), DecodeError> {
+        if let Some(ref mut options) = enum_type.options {
+            self.options(
+                path::ENUM_OPTIONS,
+                "EnumOptions",
+                &raw.options,
+                &mut options.uninterpreted_option,
+            )?;
+        }
+        for (i, (value, raw)) in enum_type.value.iter_mut().zip(&raw.value).enumerate() {
+            if let Some(ref mut options) = value.options {
+                self.path.extend([path::ENUM_VALUE, i as i32]);
+                self.options(
+                    path::ENUM_VALUE_OPTIONS,
+                    "EnumValueOptions",
+                    &raw.options,
+                    &mut options.uninterpreted_option,
+                )?;
+                self.path.truncate(self.path.len() - 2);
+            }
+        }
+        Ok(())
+    }
+
+    // Appends the custom options in `raw`, the encoded options of the
+    // current element at field `field` of it, to `uninterpreted`.
+    fn options(
+        &mut self,
+        field: i32,
+        message: &str,
+        raw: &Option<Vec<u8>>,
+        uninterpreted: &mut Vec<UninterpretedOption>,
+    ) -> Result<(), DecodeError> {
+        let mut buf = match raw {
+            Some(raw) => &raw[..],
+            None => return Ok(()),
+        };
+        let extendee = format!(".google.protobuf.{}", message);
+        // Put after the options recovered, whose locations are found by
+        // counting the options that have a name.
+        let mut unknown = Vec::new();
+        while buf.has_remaining() {
+            let (number, wire_type) = decode_key(&mut buf)?;
+            let standard;
+            let (name, is_extension, extension, values) =
+                match self.index.extensions.get(&(&*extendee, number as i32)) {
+                    Some((name, extension)) => (&**name, true, *extension, None),
+                    None if known_to_prost(message, number) => {
+                        skip_field(wire_type, number, &mut buf, DecodeContext::default())?;
+                        continue;
+                    }
+                    None => match standard_option(message, number) {
+                        Some((name, field, values)) => {
+                            standard = field;
+                            (name, false, &standard, values)
+                        }
+                        None => {
+                            unknown.push(UninterpretedOption {
+                                positive_int_value: Some(number.into()),
+                                ..Default::default()
+                            });
+                            skip_field(wire_type, number, &mut buf, DecodeContext::default())?;
+                            continue;
+                        }
+                    },
+                };
+
+            let repeated = extension.label() == Label::Repeated;
+            let mut path = self.path.clone();
+            path.extend([field, number as i32]);
+            for value in decode_values(extension, wire_type, &mut buf)? {
+                let mut option = UninterpretedOption {
+                    name: vec![NamePart {
+                        name_part: name.to_owned(),
+                        is_extension,
+                    }],
+                    ..Default::default()
+                };
+                let value = match (self.index.value(extension, value), values) {
+                    (Value::Int(number), Some(values)) => match values.get(number as usize) {
+                        Some(value) => Value::Identifier((*value).to_owned()),
+                        None => Value::Int(number),
+                    },
+                    (value, _) => value,
+                };
+                match value {
+                    Value::Int(value) if value < 0 => option.negative_int_value = Some(value),
+                    Value::Int(value) => option.positive_int_value = Some(value as u64),
+                    Value::Uint(value) => option.positive_int_value = Some(value),
+                    Value::Float(value) => option.double_value = Some(value),
+                    Value::Identifier(value) => option.identifier_value = Some(value),
+                    Value::Bytes(value) => option.string_value = Some(value),
+                    Value::Aggregate(value) => option.aggregate_value = Some(value),
+                }
+
+                // A value given more than once for an option that is not
+                // repeated is merged into the first, as parsers do.
+                let indexes = &mut self
+                    .moved
+                    .entry(path.clone())
+                    .or_insert((repeated, Vec::new()))
+                    .1;
+                if repeated || indexes.is_empty() {
+                    indexes.push(uninterpreted.len() as i32);
+                    uninterpreted.push(option);
+                } else {
+                    let index = indexes[0] as usize;
+                    merge_option(&mut uninterpreted[index], option);
+                }
+            }
+        }
+        uninterpreted.extend(unknown);
+        Ok(())
+    }
+
+    // Moves the locations of recovered options from e.g. `[4, 0, 7, 50000]`
+    // to `[4, 0, 7, 999, 0]`.
+    fn move_locations(&self, locations: &mut [prost_types::source_code_info::Location]) {
+        if self.moved.is_empty() {
+            return;
+        }
+        for location in locations {
+            let found = (2..=location.path.len()).find_map(|len| {
+                self.moved
+                    .get(&location.path[..len])
+                    .map(|moved| (len, moved))
+            });
+            let (len, (repeated, indexes)) = match found {
+                Some(found) => found,
+                None => continue,
+            };
+            let index = match repeated {
+                true => location
+                    .path
+                    .get(len)
+                    .and_then(|&i| indexes.get(i as usize)),
+                false => indexes.first(),
+            };
+            if let Some(&index) = index {
+                location.path.truncate(len - 1);
+                location.path.extend([path::UNINTERPRETED_OPTION, index]);
+            }
+        }
+    }
+}
+
+// Whether field `number` of the options message `message` is one prost-types
+// decodes, `uninterpreted_option` included.
+fn known_to_prost(message: &str, number: u32) -> bool {
+    let known: &[u32] = match message {
+        "FileOptions" => &[
+            1, 8, 9, 10, 11, 16, 17, 18, 20, 23, 27, 31, 36, 37, 39, 40, 41, 42, 44, 45,
+        ],
+        "MessageOptions" => &[1, 2, 3, 7],
+        "FieldOptions" => &[1, 2, 3, 5, 6, 10],
+        "EnumOptions" => &[2, 3],
+        "EnumValueOptions" => &[1],
+        "ServiceOptions" => &[33],
+        "MethodOptions" => &[33, 34],
+        _ => &[],
+    };
+    number == path::UNINTERPRETED_OPTION as u32 || known.contains(&number)
+}
+
+// The standard options of descriptor.proto that prost-types has no field
+// for, as a field of the options message `message` and, for enums, the names
+// of their values by number. Those only editions files can set are left out.
+fn standard_option(
+    message: &str,
+    number: u32,
+) -> Option<(
+    &'static str,
+    FieldDescriptorProto,
+    Option<&'static [&'static str]>,
+)> {
+    const RETENTION: &[&str] = &["RETENTION_UNKNOWN", "RETENTION_RUNTIME", "RETENTION_SOURCE"];
+    const TARGET_TYPE: &[&str] = &[
+        "TARGET_TYPE_UNKNOWN",
+        "TARGET_TYPE_FILE",
+        "TARGET_TYPE_EXTENSION_RANGE",
+        "TARGET_TYPE_MESSAGE",
+        "TARGET_TYPE_FIELD",
+        "TARGET_TYPE_ONEOF",
+        "TARGET_TYPE_ENUM",
+        "TARGET_TYPE_ENUM_ENTRY",
+        "TARGET_TYPE_SERVICE",
+        "TARGET_TYPE_METHOD",
+    ];
+    const VERIFICATION_STATE: &[&str] = &["DECLARATION", "UNVERIFIED"];
+
+    let (name, typ, label, values) = match (message, number) {
+        ("MessageOptions", 11) | ("EnumOptions", 6) => (
+            "deprecated_legacy_json_field_conflicts",
+            Type::Bool,
+            Label::Optional,
+            None,
+        ),
+        ("FieldOptions", 15) => ("unverified_lazy", Type::Bool, Label::Optional, None),
+        ("FieldOptions", 16) | ("EnumValueOptions", 3) => {
+            ("debug_redact", Type::Bool, Label::Optional, None)
+        }
+        ("FieldOptions", 17) => ("retention", Type::Enum, Label::Optional, Some(RETENTION)),
+        ("FieldOptions", 19) => ("targets", Type::Enum, Label::Repeated, Some(TARGET_TYPE)),
+        ("ExtensionRangeOptions", 3) => (
+            "verification",
+            Type::Enum,
+            Label::Optional,
+            Some(VERIFICATION_STATE),
+        ),
+        _ => return None,
+    };
+    let field = FieldDescriptorProto {
+        name: Some(name.to_owned()),
+        number: Some(number as i32),
+        label: Some(label as i32),
+        r#type: Some(typ as i32),
+        ..Default::default()
+    };
+    Some((name, field, values))
+}
+
+// Values of one field of a message, as encoded.
+enum Encoded<'a> {
+    Varint(u64),
+    Fixed32(u32),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+}
+
+// Reads the value of `field` at the start of `buf`, or all of its values if
+// they are packed.
+fn decode_values<'a>(
+    field: &FieldDescriptorProto,
+    wire_type: WireType,
+    buf: &mut &'a [u8],
+) -> Result<Vec<Encoded<'a>>, DecodeError> {
+    let mut values = Vec::new();
+    match wire_type {
+        WireType::Varint => values.push(Encoded::Varint(decode_varint(buf)?)),
+        WireType::ThirtyTwoBit => values.push(Encoded::Fixed32(take(buf, 4)?.get_u32_le())),
+        WireType::SixtyFourBit => values.push(Encoded::Fixed64(take(buf, 8)?.get_u64_le())),
+        WireType::LengthDelimited => {
+            let len = decode_varint(buf)? as usize;
+            let mut bytes = take(buf, len)?;
+            match field.r#type() {
+                Type::String | Type::Bytes | Type::Message | Type::Group => {
+                    values.push(Encoded::Bytes(bytes))
+                }
+                typ => {
+                    while !bytes.is_empty() {
+                        values.push(match packed_wire_type(typ) {
+                            WireType::ThirtyTwoBit => {
+                                Encoded::Fixed32(take(&mut bytes, 4)?.get_u32_le())
+                            }
+                            WireType::SixtyFourBit => {
+                                Encoded::Fixed64(take(&mut bytes, 8)?.get_u64_le())
+                            }
+                            _ => Encoded::Varint(decode_varint(&mut bytes)?),
+                        });
+                    }
+                }
+            }
+        }
+        // Groups are not options protoc accepts.
+        WireType::StartGroup | WireType::EndGroup => skip_field(
+            wire_type,
+            field.number() as u32,
+            buf,
+            DecodeContext::default(),
+        )?,
+    }
+    Ok(values)
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if buf.len() < len {
+        return Err(DecodeError::new("buffer underflow"));
+    }
+    let (value, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(value)
+}
+
+fn packed_wire_type(typ: Type) -> WireType {
+    match typ {
+        Type::Fixed32 | Type::Sfixed32 | Type::Float => WireType::ThirtyTwoBit,
+        Type::Fixed64 | Type::Sfixed64 | Type::Double => WireType::SixtyFourBit,
+        _ => WireType::Varint,
+    }
+}
+
+// A value as `UninterpretedOption` holds it.
+enum Value {
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+    Identifier(String),
+    Bytes(Vec<u8>),
+    Aggregate(String),
+}
+
+impl Index<'_> {
+    fn value(&self, field: &FieldDescriptorProto, encoded: Encoded) -> Value {
+        match (field.r#type(), encoded) {
+            (Type::Int32, Encoded::Varint(v)) => Value::Int(v as i32 as i64),
+            (Type::Int64, Encoded::Varint(v)) => Value::Int(v as i64),
+            (Type::Uint32 | Type::Uint64, Encoded::Varint(v)) => Value::Uint(v),
+            (Type::Sint32 | Type::Sint64, Encoded::Varint(v)) => {
+                Value::Int((v >> 1) as i64 ^ -((v & 1) as i64))
+            }
+            (Type::Bool, Encoded::Varint(v)) => Value::Identifier((v != 0).to_string()),
+            (Type::Enum, Encoded::Varint(v)) => {
+                let number = v as i32;
+                let name = self
+                    .enums
+                    .get(field.type_name())
+                    .and_then(|e| e.value.iter().find(|value| value.number() == number));
+                match name {
+                    Some(value) => Value::Identifier(value.name().to_owned()),
+                    None => Value::Int(number.into()),
+                }
+            }
+            (Type::Fixed32, Encoded::Fixed32(v)) => Value::Uint(v.into()),
+            (Type::Sfixed32, Encoded::Fixed32(v)) => Value::Int((v as i32).into()),
+            // Through its shortest text, so that e.g. 0.1 is not written out
+            // as 0.10000000149011612.
+            (Type::Float, Encoded::Fixed32(v)) => {
+                let value = f32::from_bits(v);
+                Value::Float(value.to_string().parse().unwrap_or(value.into()))
+            }
+            (Type::Fixed64, Encoded::Fixed64(v)) => Value::Uint(v),
+            (Type::Sfixed64, Encoded::Fixed64(v)) => Value::Int(v as i64),
+            (Type::Double, Encoded::Fixed64(v)) => Value::Float(f64::from_bits(v)),
+            (Type::Message | Type::Group, Encoded::Bytes(bytes)) => {
+                let mut text = String::new();
+                self.write_text(&mut text, field.type_name(), bytes);
+                Value::Aggregate(text)
+            }
+            (_, Encoded::Bytes(bytes)) => Value::Bytes(bytes.to_vec()),
+            // A value whose encoding does not match the type of the extension.
+            (_, Encoded::Varint(v) | Encoded::Fixed64(v)) => Value::Uint(v),
+            (_, Encoded::Fixed32(v)) => Value::Uint(v.into()),
+        }
+    }
+
+    // Writes the message of type `type_name` encoded in `bytes` in text
+    // format, e.g. `min: 1 tags: "a" rule { max: 2 }`, as `aggregate_value`
+    // holds it. Fields missing from the descriptor set are left out.
+    fn write_text(&self, text: &mut String, type_name: &str, mut buf: &[u8]) {
+        let message = match self.messages.get(type_name) {
+            Some(message) => message,
+            None => return,
+        };
+        while buf.has_remaining() {
+            let (number, wire_type) = match decode_key(&mut buf) {
+                Ok(key) => key,
+                Err(_) => return,
+            };
+            let field = message.field.iter().find(|f| f.number() == number as i32);
+            let (name, field) = match field {
+                Some(field) => (field.name().to_owned(), field),
+                None => match self.extensions.get(&(type_name, number as i32)) {
+                    Some((name, extension)) => (format!("[{}]", &name[1..]), *extension),
+                    None => {
+                        if skip_field(wire_type, number, &mut buf, DecodeContext::default())
+                            .is_err()
+                        {
+                            return;
+                        }
+                        continue;
+                    }
+                },
+            };
+            let values = match decode_values(field, wire_type, &mut buf) {
+                Ok(values) => values,
+                Err(_) => return,
+            };
+            for value in values {
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(&name);
+                match self.value(field, value) {
+                    Value::Aggregate(value) => {
+                        text.push_str(" { ");
+                        text.push_str(&value);
+                        text.push_str(" }");
+                        continue;
+                    }
+                    value => {
+                        text.push_str(": ");
+                        push_scalar(text, value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn push_scalar(text: &mut String, value: Value) {
+    match value {
+        Value::Int(value) => text.push_str(&value.to_string()),
+        Value::Uint(value) => text.push_str(&value.to_string()),
+        Value::Float(value) => text.push_str(&format!("{:?}", value)),
+        Value::Identifier(value) => text.push_str(&value),
+        Value::Bytes(value) => {
+            text.push('"');
+            text.push_str(&crate::options::c_escape(&value));
+            text.push('"');
+        }
+        Value::Aggregate(value) => text.push_str(&value),
+    }
+}
+
+// Merges `option`, a later value of the same option, into `into`: the
+// fields of a message are added to it, and other values replace it.
+fn merge_option(into: &mut UninterpretedOption, option: UninterpretedOption) {
+    match (into.aggregate_value.as_mut(), option.aggregate_value) {
+        (Some(aggregate), Some(value)) => {
+            if !aggregate.is_empty() && !value.is_empty() {
+                aggregate.push(' ');
+            }
+            aggregate.push_str(&value);
+        }
+        (_, aggregate_value) => {
+            *into = UninterpretedOption {
+                aggregate_value,
+                ..option
+            }
+        }
+    }
+}
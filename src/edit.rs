@@ -0,0 +1,467 @@
+//! Structural edits that keep comments with their elements, and comments
+//! set on elements by path.
+//!
+//! Comments live in `SourceCodeInfo`, at the paths of their elements, which
+//! contain the index of each element in its list. Inserting, removing or
+//! reordering elements of a descriptor directly leaves those paths pointing
+//! at the wrong elements. An [`Editor`] makes the change and renumbers the
+//! paths along with it:
+//!
+//! ```
+//! use protobuf_gen::edit::Editor;
+//! use protobuf_gen::path::Path;
+//! use protobuf_gen::ProtobufString;
+//! use prost_types::field_descriptor_proto::Type;
+//! use prost_types::FieldDescriptorProto;
+//!
+//! let mut file = protobuf_gen::read_sources(
+//!     vec![("user.proto", "syntax = \"proto3\";\nmessage User {\n  // Shown to others.\n  string name = 1;\n}\n")],
+//!     &["."],
+//! )
+//! .file
+//! .remove(0);
+//!
+//! let user = Path::file().message(0);
+//! let id = FieldDescriptorProto {
+//!     name: Some("id".to_owned()),
+//!     number: Some(2),
+//!     r#type: Some(Type::String as i32),
+//!     ..Default::default()
+//! };
+//! Editor::new(&mut file).insert(&user.clone().field(0), id);
+//!
+//! let name = user.clone().field(1).location(&file).unwrap();
+//! assert_eq!(Some(" Shown to others.\n"), name.leading_comments.as_deref());
+//!
+//! Editor::new(&mut file).set_leading_comment(&user.field(0), "Primary key.");
+//! assert!(file.to_protobuf(&file).contains("  // Primary key.\n  string id = 2;\n"));
+//! ```
+
+use crate::path::{self, Kind, Path};
+use prost_types::source_code_info::Location;
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+    FileDescriptorProto, MethodDescriptorProto, OneofDescriptorProto, ServiceDescriptorProto,
+};
+
+/// Edits a file, keeping its `SourceCodeInfo` in step.
+///
+/// Like the methods of `Vec`, the methods panic if a path does not point
+/// into a list of the right kind of element, or its index is out of bounds.
+/// Extensions are `FieldDescriptorProto`s at extension paths. Moving a
+/// message or enum to another parent changes its full name, and fields
+/// referring to it are not updated.
+pub struct Editor<'a> {
+    file: &'a mut FileDescriptorProto,
+}
+
+/// A descriptor found in a list of a file, such as a field of a message.
+pub trait Element: Sized {
+    /// The kind of the paths of elements of this type.
+    const KIND: Kind;
+
+    // The list at `list`, the path of its parent followed by its field
+    // number.
+    #[doc(hidden)]
+    fn list<'f>(file: &'f mut FileDescriptorProto, list: &[i32]) -> &'f mut Vec<Self>;
+}
+
+impl<'a> Editor<'a> {
+    pub fn new(file: &'a mut FileDescriptorProto) -> Editor<'a> {
+        Editor { file }
+    }
+
+    /// Inserts `element` at `path`, shifting the elements after it.
+    pub fn insert<T: Element>(&mut self, path: &Path, element: T) {
+        let (list_path, index) = split::<T>(path);
+        let list = T::list(self.file, list_path);
+        assert!(index <= list.len(), "{}: index out of bounds", path);
+        list.insert(index, element);
+        self.shift(list_path, index as i32, 1);
+        if T::KIND == Kind::Oneof {
+            self.shift_oneof_indexes(parent_of(list_path), index as i32, 1);
+        }
+    }
+
+    /// Appends `element` to the list of its kind in `parent`, returning its
+    /// path. A `FieldDescriptorProto` is appended to the fields of a
+    /// message; [`insert`](Self::insert) it at an extension path instead to
+    /// add an extension.
+    pub fn push<T: Element>(&mut self, parent: &Path, element: T) -> Path {
+        let first = child::<T>(parent.clone(), 0);
+        let (list_path, _) = split::<T>(&first);
+        let len = T::list(self.file, list_path).len() as i32;
+        let path = child::<T>(parent.clone(), len);
+        self.insert(&path, element);
+        path
+    }
+
+    /// Removes the element at `path` along with its comments, shifting the
+    /// elements after it. The fields of a removed oneof are left outside any
+    /// oneof, and a oneof whose last field is removed is removed as well.
+    pub fn remove<T: Element>(&mut self, path: &Path) -> T {
+        let (list_path, index) = split::<T>(path);
+        let list = T::list(self.file, list_path);
+        assert!(index < list.len(), "{}: index out of bounds", path);
+        let oneof_index = match path.kind() {
+            Kind::Field => message(self.file, parent_of(list_path)).field[index].oneof_index,
+            _ => None,
+        };
+        let element = T::list(self.file, list_path).remove(index);
+        self.extract(path.as_slice());
+        self.shift(list_path, index as i32 + 1, -1);
+        if T::KIND == Kind::Oneof {
+            for field in &mut message(self.file, parent_of(list_path)).field {
+                if field.oneof_index == Some(index as i32) {
+                    field.oneof_index = None;
+                }
+            }
+            self.shift_oneof_indexes(parent_of(list_path), index as i32 + 1, -1);
+        }
+        if let Some(oneof_index) = oneof_index {
+            self.remove_empty_oneof(path, oneof_index);
+        }
+        element
+    }
+
+    /// Moves the element at `from` to `to`, which is its path after the
+    /// move, with its comments and those of everything inside it. A oneof
+    /// can only be moved within its message. A field moved to another
+    /// message leaves its oneof, which is removed if it is left empty; a
+    /// proto3 `optional` field gets a synthetic oneof in its new message.
+    /// An extension can be moved between the file and its messages.
+    pub fn move_to(&mut self, from: &Path, to: &Path) {
+        assert_eq!(from.kind(), to.kind(), "cannot move {} to {}", from, to);
+        match from.kind() {
+            Kind::Message => self.move_element::<DescriptorProto>(from, to),
+            Kind::Field if from.parent() != to.parent() => self.move_field(from, to),
+            Kind::Field | Kind::Extension => self.move_element::<FieldDescriptorProto>(from, to),
+            Kind::Oneof => {
+                assert_eq!(from.parent(), to.parent(), "cannot move {} to {}", from, to);
+                let (list_path, _) = split::<OneofDescriptorProto>(from);
+                let parent = parent_of(list_path);
+                let (from_index, to_index) = (index(from), index(to));
+                self.move_element::<OneofDescriptorProto>(from, to);
+                for field in &mut message(self.file, parent).field {
+                    field.oneof_index = field
+                        .oneof_index
+                        .map(|i| moved_index(i, from_index, to_index));
+                }
+            }
+            Kind::Enum => self.move_element::<EnumDescriptorProto>(from, to),
+            Kind::EnumValue => self.move_element::<EnumValueDescriptorProto>(from, to),
+            Kind::Service => self.move_element::<ServiceDescriptorProto>(from, to),
+            Kind::Method => self.move_element::<MethodDescriptorProto>(from, to),
+            kind => panic!("{}: cannot move a {}", from, kind),
+        }
+    }
+
+    /// Sets the comment written above the element at `path`. `text` is
+    /// the comment without `//` markers, each of its lines being written as
+    /// `// line`. An empty `text` removes the comment.
+    pub fn set_leading_comment(&mut self, path: &Path, text: &str) {
+        self.location(path).leading_comments = comment(text);
+    }
+
+    /// Sets the comment written after the element at `path`, on its last
+    /// line. Only fields and enum values are written with one. An empty
+    /// `text` removes the comment.
+    pub fn set_trailing_comment(&mut self, path: &Path, text: &str) {
+        self.location(path).trailing_comments = comment(text);
+    }
+
+    /// Adds a comment above the element at `path` and its leading comment,
+    /// separated from them by a blank line. An empty `text` adds nothing.
+    pub fn add_detached_comment(&mut self, path: &Path, text: &str) {
+        if let Some(comment) = comment(text) {
+            self.location(path).leading_detached_comments.push(comment);
+        }
+    }
+
+    /// Removes the leading, trailing and detached comments of the element at
+    /// `path`.
+    pub fn clear_comments(&mut self, path: &Path) {
+        let location = self.location(path);
+        location.leading_comments = None;
+        location.trailing_comments = None;
+        location.leading_detached_comments.clear();
+    }
+
+    // The location of the element at `path`, which is added without a span
+    // if there is none.
+    fn location(&mut self, path: &Path) -> &mut Location {
+        let info = self
+            .file
+            .source_code_info
+            .get_or_insert_with(Default::default);
+        let index = match info.location.iter().position(|l| l.path == path.as_slice()) {
+            Some(index) => index,
+            None => {
+                info.location.push(Location {
+                    path: path.as_slice().to_vec(),
+                    ..Default::default()
+                });
+                info.location.len() - 1
+            }
+        };
+        &mut info.location[index]
+    }
+
+    fn move_element<T: Element>(&mut self, from: &Path, to: &Path) {
+        let (list_path, from_index) = split::<T>(from);
+        let list = T::list(self.file, list_path);
+        assert!(from_index < list.len(), "{}: index out of bounds", from);
+        let element = list.remove(from_index);
+        let mut locations = self.extract(from.as_slice());
+        self.shift(list_path, from_index as i32 + 1, -1);
+
+        let (list_path, to_index) = split::<T>(to);
+        let list = T::list(self.file, list_path);
+        assert!(to_index <= list.len(), "{}: index out of bounds", to);
+        list.insert(to_index, element);
+        self.shift(list_path, to_index as i32, 1);
+
+        let len = from.as_slice().len();
+        for location in &mut locations {
+            location.path.splice(..len, to.as_slice().iter().copied());
+        }
+        if let Some(ref mut info) = self.file.source_code_info {
+            info.location.extend(locations);
+        }
+    }
+
+    // Moves a field to another message, where the indexes of the oneofs of
+    // its old message mean nothing.
+    fn move_field(&mut self, from: &Path, to: &Path) {
+        let (list_path, from_index) = split::<FieldDescriptorProto>(from);
+        let fields = &message(self.file, parent_of(list_path)).field;
+        assert!(from_index < fields.len(), "{}: index out of bounds", from);
+        let oneof_index = fields[from_index].oneof_index;
+        self.move_element::<FieldDescriptorProto>(from, to);
+        if let Some(oneof_index) = oneof_index {
+            self.remove_empty_oneof(from, oneof_index);
+        }
+
+        let (list_path, to_index) = split::<FieldDescriptorProto>(to);
+        let message = message(self.file, parent_of(list_path));
+        message.field[to_index].oneof_index = None;
+        if message.field[to_index].proto3_optional() {
+            crate::builder::make_proto3_optional(message, to_index);
+        }
+    }
+
+    // Removes the oneof at `oneof_index` in the message of the field that
+    // was at `field` if it has no fields left.
+    fn remove_empty_oneof(&mut self, field: &Path, oneof_index: i32) {
+        let parent = field.parent().expect("field in a message");
+        let fields = &message(self.file, parent.as_slice()).field;
+        if !fields.iter().any(|f| f.oneof_index == Some(oneof_index)) {
+            self.remove::<OneofDescriptorProto>(&parent.oneof(oneof_index));
+        }
+    }
+
+    // Adds `delta` to the index of the elements in the list at `list`, a
+    // path ending in the field number of the list, from `start` on.
+    fn shift(&mut self, list: &[i32], start: i32, delta: i32) {
+        let info = match self.file.source_code_info {
+            Some(ref mut info) => info,
+            None => return,
+        };
+        for location in &mut info.location {
+            if location.path.len() > list.len()
+                && location.path.starts_with(list)
+                && location.path[list.len()] >= start
+            {
+                location.path[list.len()] += delta;
+            }
+        }
+    }
+
+    // Removes the locations of the element at `path` and of everything in
+    // it, returning them.
+    fn extract(&mut self, path: &[i32]) -> Vec<Location> {
+        let info = match self.file.source_code_info {
+            Some(ref mut info) => info,
+            None => return Vec::new(),
+        };
+        let (extracted, kept) = std::mem::take(&mut info.location)
+            .into_iter()
+            .partition(|location| location.path.starts_with(path));
+        info.location = kept;
+        extracted
+    }
+
+    fn shift_oneof_indexes(&mut self, message_path: &[i32], start: i32, delta: i32) {
+        for field in &mut message(self.file, message_path).field {
+            match field.oneof_index {
+                Some(ref mut index) if *index >= start => *index += delta,
+                _ => {}
+            }
+        }
+    }
+}
+
+// `text` in the form of `SourceCodeInfo`, with the space after `//` and a
+// line break after every line, or `None` for no comment.
+fn comment(text: &str) -> Option<String> {
+    if text.is_empty() {
+        return None;
+    }
+    let mut comment = String::with_capacity(text.len() + 2);
+    for line in text.lines() {
+        if !line.is_empty() {
+            comment.push(' ');
+            comment.push_str(line);
+        }
+        comment.push('\n');
+    }
+    Some(comment)
+}
+
+// The path of the list of the element at `path`, and its index in it.
+fn split<T: Element>(path: &Path) -> (&[i32], usize) {
+    // Extensions are fields declared outside the message they extend.
+    let kind = match path.kind() {
+        Kind::Extension => Kind::Field,
+        kind => kind,
+    };
+    assert_eq!(T::KIND, kind, "{} is not the path of a {}", path, T::KIND);
+    let numbers = path.as_slice();
+    (&numbers[..numbers.len() - 1], index(path) as usize)
+}
+
+// The path of the element containing the list at `list`.
+fn parent_of(list: &[i32]) -> &[i32] {
+    &list[..list.len() - 1]
+}
+
+fn index(path: &Path) -> i32 {
+    path.index().expect("path of an element in a list")
+}
+
+fn child<T: Element>(parent: Path, index: i32) -> Path {
+    match T::KIND {
+        Kind::Message => parent.message(index),
+        Kind::Field => parent.field(index),
+        Kind::Extension => parent.extension(index),
+        Kind::Oneof => parent.oneof(index),
+        Kind::Enum => parent.enumeration(index),
+        Kind::EnumValue => parent.value(index),
+        Kind::Service => parent.service(index),
+        Kind::Method => parent.method(index),
+        kind => unreachable!("{} is not in a list", kind),
+    }
+}
+
+// Where the element at `index` ends up when the one at `from` moves to `to`.
+fn moved_index(index: i32, from: i32, to: i32) -> i32 {
+    if index == from {
+        to
+    } else if from < index && index <= to {
+        index - 1
+    } else if to <= index && index < from {
+        index + 1
+    } else {
+        index
+    }
+}
+
+// The message at `path`, e.g. `[4, 0, 3, 1]`.
+fn message<'f>(file: &'f mut FileDescriptorProto, path: &[i32]) -> &'f mut DescriptorProto {
+    let (first, mut rest) = match path {
+        [path::FILE_MESSAGE_TYPE, index, rest @ ..] => (*index, rest),
+        _ => panic!("{:?} is not the path of a message", path),
+    };
+    let mut message = &mut file.message_type[first as usize];
+    while let [path::MESSAGE_NESTED_TYPE, index, tail @ ..] = rest {
+        message = &mut message.nested_type[*index as usize];
+        rest = tail;
+    }
+    assert!(rest.is_empty(), "{:?} is not the path of a message", path);
+    message
+}
+
+fn enumeration<'f>(file: &'f mut FileDescriptorProto, path: &[i32]) -> &'f mut EnumDescriptorProto {
+    match path {
+        [path::FILE_ENUM_TYPE, index] => &mut file.enum_type[*index as usize],
+        [parent @ .., path::MESSAGE_ENUM_TYPE, index] => {
+            &mut message(file, parent).enum_type[*index as usize]
+        }
+        _ => panic!("{:?} is not the path of an enum", path),
+    }
+}
+
+impl Element for DescriptorProto {
+    const KIND: Kind = Kind::Message;
+
+    fn list<'f>(file: &'f mut FileDescriptorProto, list: &[i32]) -> &'f mut Vec<Self> {
+        match list {
+            [path::FILE_MESSAGE_TYPE] => &mut file.message_type,
+            [parent @ .., path::MESSAGE_NESTED_TYPE] => &mut message(file, parent).nested_type,
+            _ => panic!("{:?} is not a list of messages", list),
+        }
+    }
+}
+
+impl Element for FieldDescriptorProto {
+    const KIND: Kind = Kind::Field;
+
+    fn list<'f>(file: &'f mut FileDescriptorProto, list: &[i32]) -> &'f mut Vec<Self> {
+        match list {
+            [path::FILE_EXTENSION] => &mut file.extension,
+            [parent @ .., path::MESSAGE_FIELD] => &mut message(file, parent).field,
+            [parent @ .., path::MESSAGE_EXTENSION] => &mut message(file, parent).extension,
+            _ => panic!("{:?} is not a list of fields", list),
+        }
+    }
+}
+
+impl Element for OneofDescriptorProto {
+    const KIND: Kind = Kind::Oneof;
+
+    fn list<'f>(file: &'f mut FileDescriptorProto, list: &[i32]) -> &'f mut Vec<Self> {
+        &mut message(file, parent_of(list)).oneof_decl
+    }
+}
+
+impl Element for EnumDescriptorProto {
+    const KIND: Kind = Kind::Enum;
+
+    fn list<'f>(file: &'f mut FileDescriptorProto, list: &[i32]) -> &'f mut Vec<Self> {
+        match list {
+            [path::FILE_ENUM_TYPE] => &mut file.enum_type,
+            [parent @ .., path::MESSAGE_ENUM_TYPE] => &mut message(file, parent).enum_type,
+            _ => panic!("{:?} is not a list of enums", list),
+        }
+    }
+}
+
+impl Element for EnumValueDescriptorProto {
+    const KIND: Kind = Kind::EnumValue;
+
+    fn list<'f>(file: &'f mut FileDescriptorProto, list: &[i32]) -> &'f mut Vec<Self> {
+        &mut enumeration(file, parent_of(list)).value
+    }
+}
+
+impl Element for ServiceDescriptorProto {
+    const KIND: Kind = Kind::Service;
+
+    fn list<'f>(file: &'f mut FileDescriptorProto, list: &[i32]) -> &'f mut Vec<Self> {
+        match list {
+            [path::FILE_SERVICE] => &mut file.service,
+            _ => panic!("{:?}: services are only declared in files", list),
+        }
+    }
+}
+
+impl Element for MethodDescriptorProto {
+    const KIND: Kind = Kind::Method;
+
+    fn list<'f>(file: &'f mut FileDescriptorProto, list: &[i32]) -> &'f mut Vec<Self> {
+        match parent_of(list) {
+            [path::FILE_SERVICE, index] => &mut file.service[*index as usize].method,
+            parent => panic!("{:?} is not the path of a service", parent),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Formatting options for the [`Generator`](crate::Generator).
+//!
+//! The defaults are what [`to_protobuf`](crate::ProtobufString::to_protobuf)
+//! uses. The same options can be set in the `[format]` table of
+//! `protobuf-gen.toml`:
+//!
+//! ```toml
+//! [format]
+//! indent = 4             # number of spaces, or "tab"
+//! blank_lines_between_declarations = 1
+//! blank_lines_between_field_groups = 1
+//! line_ending = "crlf"   # or "lf"
+//! final_newline = true
+//! comments = true
+//! align = false
+//! max_width = 100
+//! reflow_comments = false
+//! lossless = false
+//! ```
+
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratorConfig {
+    pub(crate) indent: Indent,
+    pub(crate) blank_lines_between_declarations: usize,
+    pub(crate) blank_lines_between_field_groups: usize,
+    pub(crate) line_ending: LineEnding,
+    pub(crate) final_newline: bool,
+    pub(crate) comments: bool,
+    pub(crate) align: bool,
+    pub(crate) max_width: usize,
+    pub(crate) reflow_comments: bool,
+    pub(crate) lossless: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "IndentRepr")]
+pub enum Indent {
+    Spaces(usize),
+    Tabs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            indent: Indent::Spaces(2),
+            blank_lines_between_declarations: 1,
+            blank_lines_between_field_groups: 1,
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            comments: true,
+            align: false,
+            max_width: 100,
+            reflow_comments: false,
+            lossless: false,
+        }
+    }
+}
+
+impl GeneratorConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The unit of indentation for each nesting level.
+    pub fn indent(mut self, indent: Indent) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Blank lines written before each message, enum and service.
+    pub fn blank_lines_between_declarations(mut self, lines: usize) -> Self {
+        self.blank_lines_between_declarations = lines;
+        self
+    }
+
+    /// Blank lines written before a commented field, enum value, oneof or
+    /// method, which starts a new group of them.
+    pub fn blank_lines_between_field_groups(mut self, lines: usize) -> Self {
+        self.blank_lines_between_field_groups = lines;
+        self
+    }
+
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// End the output with exactly one line ending if `true`, or none at
+    /// all if `false`.
+    pub fn final_newline(mut self, final_newline: bool) -> Self {
+        self.final_newline = final_newline;
+        self
+    }
+
+    /// Whether to carry the comments from `SourceCodeInfo` over.
+    pub fn comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    /// Pad each run of consecutive fields or enum values into columns, so
+    /// that names, `=` signs, numbers and trailing comments line up. A
+    /// comment or blank line starts a new run, and oneof bodies are aligned
+    /// on their own. Trailing comments are only written with `align` or
+    /// [`lossless`](Self::lossless) set.
+    pub fn align(mut self, align: bool) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// The column to keep lines within. Option lists, aggregate option
+    /// values and rpc signatures that don't fit are broken one item per
+    /// line.
+    pub fn max_width(mut self, max_width: usize) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    /// Rewrap comments to fit within `max_width`. Paragraphs are filled
+    /// with as many words per line as fit, while indented lines and list
+    /// items are left as they are.
+    pub fn reflow_comments(mut self, reflow_comments: bool) -> Self {
+        self.reflow_comments = reflow_comments;
+        self
+    }
+
+    /// Write back everything the descriptor holds that `.proto` source can
+    /// express: `option` statements, `extend` blocks, `extensions` ranges,
+    /// `import public` and `import weak`, the comments heading the file and
+    /// its imports, and `syntax = "proto2"` if the source said so, with
+    /// declarations in the order they were written rather than grouped by
+    /// kind, and trailing comments. `protobuf-gen fmt` renders this way, so that formatting a file
+    /// only changes its layout.
+    pub fn lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    /// Sets an option by its name in the config file, e.g. `("indent",
+    /// "tab")`, as used for protoc plugin parameters.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value for {}: {}", key, value))
+        }
+
+        match key {
+            "indent" => {
+                self.indent = Indent::try_from(match value.parse() {
+                    Ok(width) => IndentRepr::Width(width),
+                    Err(_) => IndentRepr::Name(value.to_owned()),
+                })?
+            }
+            "blank_lines_between_declarations" => {
+                self.blank_lines_between_declarations = parse(key, value)?
+            }
+            "blank_lines_between_field_groups" => {
+                self.blank_lines_between_field_groups = parse(key, value)?
+            }
+            "line_ending" => {
+                self.line_ending = match value {
+                    "lf" => LineEnding::Lf,
+                    "crlf" => LineEnding::CrLf,
+                    _ => return Err(format!("invalid value for {}: {}", key, value)),
+                }
+            }
+            "final_newline" => self.final_newline = parse(key, value)?,
+            "comments" => self.comments = parse(key, value)?,
+            "align" => self.align = parse(key, value)?,
+            "max_width" => self.max_width = parse(key, value)?,
+            "reflow_comments" => self.reflow_comments = parse(key, value)?,
+            "lossless" => self.lossless = parse(key, value)?,
+            _ => return Err(format!("unknown option: {}", key)),
+        }
+        Ok(())
+    }
+
+    // Trailing comments were not written before fields could be aligned,
+    // and are kept to that so that the default output stays the same.
+    pub(crate) fn trailing_comments(&self) -> bool {
+        self.comments && (self.align || self.lossless)
+    }
+}
+
+impl Indent {
+    pub(crate) fn push_to(self, indent: &mut String) {
+        match self {
+            Indent::Spaces(width) => indent.extend(std::iter::repeat_n(' ', width)),
+            Indent::Tabs => indent.push('\t'),
+        }
+    }
+
+    pub(crate) fn width(self) -> usize {
+        match self {
+            Indent::Spaces(width) => width,
+            Indent::Tabs => 1,
+        }
+    }
+}
+
+// `indent = 4` or `indent = "tab"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IndentRepr {
+    Width(usize),
+    Name(String),
+}
+
+impl TryFrom<IndentRepr> for Indent {
+    type Error = String;
+
+    fn try_from(repr: IndentRepr) -> Result<Self, Self::Error> {
+        match repr {
+            IndentRepr::Width(width) => Ok(Indent::Spaces(width)),
+            IndentRepr::Name(ref name) if name == "tab" => Ok(Indent::Tabs),
+            IndentRepr::Name(name) => Err(format!("invalid value for indent: {}", name)),
+        }
+    }
+}
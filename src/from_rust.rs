@@ -0,0 +1,815 @@
+//! Reconstructing descriptors from Rust code derived with prost.
+//!
+//! prost-build turns each package into a Rust file of structs and enums
+//! deriving `prost::Message`, `prost::Oneof` and `prost::Enumeration`, whose
+//! `#[prost(...)]` attributes keep the tags, types and labels of the fields.
+//! [`from_source`] reads them back into a `FileDescriptorProto`, which
+//! [`ProtobufString`](crate::ProtobufString) renders as a matching `.proto`
+//! file:
+//!
+//! ```
+//! use protobuf_gen::ProtobufString;
+//!
+//! let source = r#"
+//!     #[derive(Clone, PartialEq, ::prost::Message)]
+//!     pub struct User {
+//!         #[prost(string, tag = "1")]
+//!         pub name: ::prost::alloc::string::String,
+//!         #[prost(string, repeated, tag = "2")]
+//!         pub tags: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+//!     }
+//! "#;
+//! let file = protobuf_gen::from_rust::from_source("acme", source)?;
+//! assert!(file.to_protobuf(&file).contains("  repeated string tags = 2;\n"));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Some of the schema does not survive into the Rust code:
+//!
+//! - Names are those of the Rust items, so a message `HTTPRequest` comes
+//!   back as `HttpRequest`. Enum values keep their names when the code has
+//!   prost's `as_str_name` methods.
+//! - A file is proto2 if it has `required` fields or default values, and
+//!   proto3 otherwise. Message fields are never proto3 `optional`.
+//! - Oneofs are placed before the first field with a larger number, and
+//!   nested messages and enums after the entries of map fields.
+//! - Types of other packages are found by their Rust paths, and only the
+//!   well-known types of `prost_types` get an import.
+
+use crate::builder::{FieldBuilder, FieldType, FileBuilder, MessageBuilder};
+use prost_types::FileDescriptorProto;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Fields, Item, Meta, Token};
+
+/// Reads a file generated by prost-build, which names it after the package,
+/// e.g. `acme.users.rs`, or `_.rs` for no package.
+pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<FileDescriptorProto> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)?;
+    let package = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some("_") | None => "",
+        Some(stem) => stem,
+    };
+    from_source(package, &source).map_err(|err| annotate(path, err))
+}
+
+/// Reconstructs the file of `package` from the Rust code prost generated
+/// for it. The file is named after the package, e.g. `acme/users.proto`.
+pub fn from_source(package: &str, source: &str) -> io::Result<FileDescriptorProto> {
+    let file = syn::parse_file(source).map_err(|err| invalid(err.to_string()))?;
+    let scope = Scope {
+        package: package
+            .split('.')
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        messages: Vec::new(),
+    };
+    let mut imports = Vec::new();
+    let mut items = read_items(&file.items, &scope, &mut imports)?;
+    let mut enum_values = HashMap::new();
+    items.enum_values(&mut enum_values);
+    items.name_enum_defaults(&enum_values);
+
+    let name = match package {
+        "" => "_.proto".to_owned(),
+        _ => format!("{}.proto", package.replace('.', "/")),
+    };
+    let proto3 = !items.is_proto2();
+    let mut builder = FileBuilder::new(name);
+    if !proto3 {
+        builder = builder.syntax("proto2");
+    }
+    if !package.is_empty() {
+        builder = builder.package(package);
+    }
+    for import in &imports {
+        builder = builder.import(import);
+    }
+    for message in items.messages {
+        let name = message.name.clone();
+        builder = builder.message(&name, |m| message.build(m, proto3));
+    }
+    for enumeration in items.enums {
+        builder = builder.enumeration(&enumeration.name, |e| enumeration.build(e));
+    }
+    Ok(builder.build())
+}
+
+// The messages and enums defined in a module.
+#[derive(Default)]
+struct Items {
+    messages: Vec<Message>,
+    enums: Vec<Enum>,
+}
+
+struct Message {
+    name: String,
+    members: Vec<Member>,
+    nested: Items,
+}
+
+enum Member {
+    Field(Field),
+    Oneof(String, Vec<Field>),
+}
+
+struct Field {
+    name: String,
+    label: Label,
+    typ: FieldType,
+    // The key and value types of a map field.
+    map: Option<(FieldType, FieldType)>,
+    number: i32,
+    packed: Option<bool>,
+    default: Option<String>,
+    deprecated: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Label {
+    None,
+    Optional,
+    Required,
+    Repeated,
+}
+
+struct Enum {
+    name: String,
+    // With a leading dot, as field types name it.
+    full_name: String,
+    values: Vec<(String, i32)>,
+    // The names of the values by Rust variant.
+    variants: HashMap<String, String>,
+}
+
+// Where a module of the generated code is: in the modules of a package,
+// nested in the modules of messages.
+#[derive(Clone)]
+struct Scope {
+    package: Vec<String>,
+    messages: Vec<String>,
+}
+
+impl Scope {
+    fn full_name(&self, name: &str) -> String {
+        let mut full_name = String::new();
+        for part in self.package.iter().chain(&self.messages) {
+            full_name.push('.');
+            full_name.push_str(part);
+        }
+        full_name.push('.');
+        full_name.push_str(name);
+        full_name
+    }
+}
+
+impl Items {
+    // The names of the values of the enums here and in nested messages, by
+    // Rust variant, by the full name of the enum.
+    fn enum_values(&self, values: &mut HashMap<String, HashMap<String, String>>) {
+        for enumeration in &self.enums {
+            values.insert(enumeration.full_name.clone(), enumeration.variants.clone());
+        }
+        for message in &self.messages {
+            message.nested.enum_values(values);
+        }
+    }
+
+    // Replaces the defaults of enum fields, which prost-build writes as the
+    // Rust variant, with the name of the value.
+    fn name_enum_defaults(&mut self, values: &HashMap<String, HashMap<String, String>>) {
+        for message in &mut self.messages {
+            for member in &mut message.members {
+                let fields = match member {
+                    Member::Field(field) => std::slice::from_mut(field),
+                    Member::Oneof(_, fields) => &mut fields[..],
+                };
+                for field in fields {
+                    if let (FieldType::Enum(ref name), Some(ref mut default)) =
+                        (&field.typ, &mut field.default)
+                    {
+                        let variant = default.strip_prefix("r#").unwrap_or(default);
+                        if let Some(value) = values.get(name).and_then(|v| v.get(variant)) {
+                            *default = value.clone();
+                        }
+                    }
+                }
+            }
+            message.nested.name_enum_defaults(values);
+        }
+    }
+
+    // Whether the file is proto2, which only it has required fields and
+    // default values.
+    fn is_proto2(&self) -> bool {
+        let is_proto2 = |f: &Field| f.label == Label::Required || f.default.is_some();
+        self.messages.iter().any(|message| {
+            message.nested.is_proto2()
+                || message.members.iter().any(|member| match member {
+                    Member::Field(field) => is_proto2(field),
+                    Member::Oneof(_, fields) => fields.iter().any(is_proto2),
+                })
+        })
+    }
+}
+
+impl Message {
+    fn build(self, mut m: MessageBuilder, proto3: bool) -> MessageBuilder {
+        for member in self.members {
+            m = match member {
+                Member::Field(field) => field.add_to(m, proto3),
+                Member::Oneof(name, fields) => m.oneof(&name, |mut o| {
+                    for field in fields {
+                        let (name, typ, number) =
+                            (field.name.clone(), field.typ.clone(), field.number);
+                        o = o.field_with(&name, typ, number, |f| field.options(f));
+                    }
+                    o
+                }),
+            };
+        }
+        for message in self.nested.messages {
+            let name = message.name.clone();
+            m = m.message(&name, |n| message.build(n, proto3));
+        }
+        for enumeration in self.nested.enums {
+            m = m.enumeration(&enumeration.name, |e| enumeration.build(e));
+        }
+        m
+    }
+}
+
+impl Field {
+    fn add_to(self, m: MessageBuilder, proto3: bool) -> MessageBuilder {
+        let (name, typ, number) = (self.name.clone(), self.typ.clone(), self.number);
+        if let Some((key, value)) = self.map {
+            return m.map(&name, key, value, number);
+        }
+        match self.label {
+            Label::Repeated => m.repeated_with(&name, typ, number, |f| self.options(f)),
+            Label::Required => m.required_with(&name, typ, number, |f| self.options(f)),
+            // Message fields have presence without being `optional`.
+            Label::Optional if proto3 && matches!(typ, FieldType::Message(_)) => {
+                m.field_with(&name, typ, number, |f| self.options(f))
+            }
+            Label::Optional => m.optional_with(&name, typ, number, |f| self.options(f)),
+            Label::None => m.field_with(&name, typ, number, |f| self.options(f)),
+        }
+    }
+
+    fn options(&self, mut f: FieldBuilder) -> FieldBuilder {
+        if let Some(packed) = self.packed {
+            f = f.packed(packed);
+        }
+        if let Some(ref default) = self.default {
+            f = f.default_value(default);
+        }
+        if self.deprecated {
+            f = f.deprecated(true);
+        }
+        f
+    }
+}
+
+impl Enum {
+    fn build(&self, mut e: crate::builder::EnumBuilder) -> crate::builder::EnumBuilder {
+        for (name, number) in &self.values {
+            e = e.value(name, *number);
+        }
+        e
+    }
+}
+
+fn read_items(items: &[Item], scope: &Scope, imports: &mut Vec<String>) -> io::Result<Items> {
+    // The original names of enum values, from the `as_str_name` methods.
+    let mut value_names = HashMap::new();
+    for item in items {
+        if let Item::Impl(item) = item {
+            if let syn::Type::Path(ref typ) = *item.self_ty {
+                if let Some(names) = as_str_names(item) {
+                    value_names.insert(last_segment(&typ.path), names);
+                }
+            }
+        }
+    }
+
+    let mut read = Items::default();
+    for item in items {
+        match item {
+            Item::Struct(item) if derives(&item.attrs, "Message") => {
+                read.messages
+                    .push(read_message(item, items, scope, imports)?);
+            }
+            Item::Enum(item) if derives(&item.attrs, "Enumeration") => {
+                let name = item.ident.to_string();
+                let names = value_names.get(&name);
+                let mut values = Vec::new();
+                let mut variants = HashMap::new();
+                for variant in &item.variants {
+                    let number = variant
+                        .discriminant
+                        .as_ref()
+                        .and_then(|(_, expr)| int_expr(expr))
+                        .ok_or_else(|| error(&name, "enum values need a number"))?;
+                    let variant = variant.ident.to_string();
+                    let value = names
+                        .and_then(|names| names.get(&variant))
+                        .cloned()
+                        .unwrap_or_else(|| snake_case(&variant).to_uppercase());
+                    variants.insert(variant, value.clone());
+                    values.push((value, number));
+                }
+                read.enums.push(Enum {
+                    full_name: scope.full_name(&name),
+                    name,
+                    values,
+                    variants,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(read)
+}
+
+// Reads a message struct of a module with `items`, whose nested types live
+// in a module named after it, as do its oneof enums.
+fn read_message(
+    item: &syn::ItemStruct,
+    items: &[Item],
+    scope: &Scope,
+    imports: &mut Vec<String>,
+) -> io::Result<Message> {
+    let name = item.ident.to_string();
+    let mut nested_scope = scope.clone();
+    nested_scope.messages.push(name.clone());
+    let module = snake_case(&name);
+    let nested_items = items
+        .iter()
+        .find_map(|item| match item {
+            Item::Mod(m) if ident_name(&m.ident) == module => m.content.as_ref(),
+            _ => None,
+        })
+        .map_or(&[][..], |(_, items)| &items[..]);
+    let nested = read_items(nested_items, &nested_scope, imports)?;
+
+    let fields = match item.fields {
+        Fields::Named(ref fields) => fields.named.iter().collect(),
+        Fields::Unit => Vec::new(),
+        Fields::Unnamed(_) => return Err(error(&name, "expected named fields")),
+    };
+    let mut members = Vec::new();
+    let mut oneofs = Vec::new();
+    for field in fields {
+        let field_name = field.ident.as_ref().map_or(String::new(), ident_name);
+        let context = format!("{}.{}", name, field_name);
+        let metas = match prost_attribute(&field.attrs) {
+            Some(metas) => metas.map_err(|err| error(&context, err))?,
+            None => continue,
+        };
+
+        let oneof = metas.iter().find_map(|meta| match meta {
+            Meta::NameValue(nv) if nv.path.is_ident("oneof") => lit_string(&nv.value),
+            _ => None,
+        });
+        if let Some(path) = oneof {
+            let enum_name = path.rsplit("::").next().unwrap_or(&path);
+            let (variants, variant_scope) = find_oneof(nested_items, enum_name)
+                .map(|e| (e, &nested_scope))
+                .or_else(|| find_oneof(items, enum_name).map(|e| (e, scope)))
+                .ok_or_else(|| error(&context, format!("oneof {} not found", path)))?;
+            let mut fields = Vec::new();
+            for variant in &variants.variants {
+                let variant_name = snake_case(&variant.ident.to_string());
+                let context = format!("{}.{}", context, variant_name);
+                let metas = prost_attribute(&variant.attrs)
+                    .ok_or_else(|| error(&context, "missing #[prost] attribute"))?
+                    .map_err(|err| error(&context, err))?;
+                let typ = match variant.fields {
+                    Fields::Unnamed(ref fields) => fields.unnamed.first().map(|f| &f.ty),
+                    _ => None,
+                };
+                let mut field = read_field(variant_name, &metas, typ, variant_scope, imports)
+                    .map_err(|err| error(&context, err))?;
+                field.deprecated = is_deprecated(&variant.attrs);
+                fields.push(field);
+            }
+            oneofs.push(Member::Oneof(field_name, fields));
+            continue;
+        }
+
+        let mut read = read_field(field_name, &metas, Some(&field.ty), scope, imports)
+            .map_err(|err| error(&context, err))?;
+        read.deprecated = is_deprecated(&field.attrs);
+        members.push(Member::Field(read));
+    }
+
+    // prost puts oneofs after the other fields.
+    for oneof in oneofs {
+        let first = first_number(&oneof);
+        let position = members
+            .iter()
+            .position(|member| first_number(member) > first)
+            .unwrap_or(members.len());
+        members.insert(position, oneof);
+    }
+    Ok(Message {
+        name,
+        members,
+        nested,
+    })
+}
+
+// Reads a field from its `#[prost(...)]` attribute and Rust type, which
+// names the type of message fields.
+fn read_field(
+    name: String,
+    metas: &Punctuated<Meta, Token![,]>,
+    typ: Option<&syn::Type>,
+    scope: &Scope,
+    imports: &mut Vec<String>,
+) -> Result<Field, String> {
+    let mut field = Field {
+        name,
+        label: Label::None,
+        typ: FieldType::Bool,
+        map: None,
+        number: 0,
+        packed: None,
+        default: None,
+        deprecated: false,
+    };
+    let mut typed = false;
+    for meta in metas {
+        let key = meta.path().get_ident().map_or(String::new(), ident_name);
+        let value = match meta {
+            Meta::NameValue(nv) => Some(lit_string(&nv.value).ok_or("expected a string")?),
+            Meta::Path(_) => None,
+            Meta::List(_) => return Err(format!("unexpected {}(...)", key)),
+        };
+        match (key.as_str(), value) {
+            ("optional", None) => field.label = Label::Optional,
+            ("required", None) => field.label = Label::Required,
+            ("repeated", None) => field.label = Label::Repeated,
+            ("boxed", None) => {}
+            ("tag", Some(tag)) => field.number = tag.trim().parse().map_err(|_| "invalid tag")?,
+            ("packed", Some(packed)) => field.packed = Some(packed == "true"),
+            ("default", Some(default)) => field.default = Some(default),
+            ("bytes", _) => {
+                field.typ = FieldType::Bytes;
+                typed = true;
+            }
+            ("message", None) => {
+                let path = rust_type(typ.ok_or("message fields need a type")?)?;
+                field.typ = FieldType::Message(resolve(&path, scope, imports)?);
+                typed = true;
+            }
+            ("enumeration", Some(path)) => {
+                field.typ = FieldType::Enum(resolve(&split_path(&path), scope, imports)?);
+                typed = true;
+            }
+            // `map` and `hash_map` are `HashMap` fields, and `btree_map` are
+            // `BTreeMap` ones.
+            ("map" | "hash_map" | "btree_map", Some(map)) => {
+                let (key, value) = map.split_once(',').ok_or("expected map = \"key, value\"")?;
+                let key = scalar(key.trim()).ok_or_else(|| format!("unknown map key {}", key))?;
+                let value = match value.trim() {
+                    "message" => {
+                        let value = typ.and_then(map_value_type).ok_or("expected a map type")?;
+                        FieldType::Message(resolve(&rust_type(value)?, scope, imports)?)
+                    }
+                    value => match value
+                        .strip_prefix("enumeration(")
+                        .and_then(|v| v.strip_suffix(')'))
+                    {
+                        Some(path) => FieldType::Enum(resolve(&split_path(path), scope, imports)?),
+                        None => {
+                            scalar(value).ok_or_else(|| format!("unknown map value {}", value))?
+                        }
+                    },
+                };
+                field.map = Some((key, value));
+                typed = true;
+            }
+            ("group", _) => return Err("groups are not supported".to_owned()),
+            (scalar_type, None) if scalar(scalar_type).is_some() => {
+                field.typ = scalar(scalar_type).unwrap();
+                typed = true;
+            }
+            (other, _) => return Err(format!("unknown prost attribute {}", other)),
+        }
+    }
+    if !typed {
+        return Err("missing field type".to_owned());
+    }
+    // prost-build writes the default of a bytes field as the text of a
+    // `b"..."` literal, where protoc keeps it C-escaped. Enum defaults are
+    // named once all enums are read.
+    if let (FieldType::Bytes, Some(default)) = (&field.typ, &field.default) {
+        let bytes = default
+            .strip_prefix("b\"")
+            .and_then(|d| d.strip_suffix('"'))
+            .ok_or("expected a byte string default")?;
+        field.default = Some(crate::options::c_escape_bytes(&unescape(bytes)?));
+    }
+    Ok(field)
+}
+
+fn scalar(name: &str) -> Option<FieldType> {
+    Some(match name {
+        "double" => FieldType::Double,
+        "float" => FieldType::Float,
+        "int64" => FieldType::Int64,
+        "uint64" => FieldType::Uint64,
+        "int32" => FieldType::Int32,
+        "fixed64" => FieldType::Fixed64,
+        "fixed32" => FieldType::Fixed32,
+        "bool" => FieldType::Bool,
+        "string" => FieldType::String,
+        "bytes" => FieldType::Bytes,
+        "uint32" => FieldType::Uint32,
+        "sfixed32" => FieldType::Sfixed32,
+        "sfixed64" => FieldType::Sfixed64,
+        "sint32" => FieldType::Sint32,
+        "sint64" => FieldType::Sint64,
+        _ => return None,
+    })
+}
+
+// The well-known types prost maps to `prost_types`, and their files.
+const WELL_KNOWN_TYPES: &[(&str, &str)] = &[
+    ("Any", "any"),
+    ("Duration", "duration"),
+    ("Empty", "empty"),
+    ("FieldMask", "field_mask"),
+    ("Struct", "struct"),
+    ("Value", "struct"),
+    ("ListValue", "struct"),
+    ("Timestamp", "timestamp"),
+    ("DoubleValue", "wrappers"),
+    ("FloatValue", "wrappers"),
+    ("Int64Value", "wrappers"),
+    ("UInt64Value", "wrappers"),
+    ("Int32Value", "wrappers"),
+    ("UInt32Value", "wrappers"),
+    ("BoolValue", "wrappers"),
+    ("StringValue", "wrappers"),
+    ("BytesValue", "wrappers"),
+];
+
+// Resolves the Rust path of a message or enum, relative to the module of
+// `scope`, to its fully-qualified name. Modules of messages within the
+// file's package are named after them; outside it, modules are taken to be
+// packages.
+fn resolve(path: &[String], scope: &Scope, imports: &mut Vec<String>) -> Result<String, String> {
+    let (last, modules) = path.split_last().ok_or("empty type path")?;
+    if modules.first().map(String::as_str) == Some("prost_types") {
+        let name = last;
+        if let Some(&(_, file)) = WELL_KNOWN_TYPES.iter().find(|&&(t, _)| t == name.as_str()) {
+            let import = format!("google/protobuf/{}.proto", file);
+            if !imports.contains(&import) {
+                imports.push(import);
+            }
+        }
+        return Ok(format!(".google.protobuf.{}", name));
+    }
+
+    let mut package = scope.package.clone();
+    let mut messages = scope.messages.clone();
+    for module in modules {
+        match module.as_str() {
+            "self" => {}
+            "super" if !messages.is_empty() => {
+                messages.pop();
+            }
+            "super" => {
+                package.pop().ok_or("path leaves the crate")?;
+            }
+            "crate" => return Err("paths from the crate root are not supported".to_owned()),
+            _ if package == scope.package => messages.push(upper_camel_case(module)),
+            _ => package.push(module.clone()),
+        }
+    }
+    let mut name = String::new();
+    for part in package.iter().chain(&messages).chain(Some(last)) {
+        name.push('.');
+        name.push_str(part);
+    }
+    Ok(name)
+}
+
+// The path of the message type of a field, inside any `Option`, `Box` or
+// `Vec`.
+fn rust_type(typ: &syn::Type) -> Result<Vec<String>, String> {
+    let path = match typ {
+        syn::Type::Path(typ) => &typ.path,
+        _ => return Err("expected a type path".to_owned()),
+    };
+    let last = path.segments.last().ok_or("empty type path")?;
+    if let "Option" | "Box" | "Vec" = last.ident.to_string().as_str() {
+        if let Some(inner) = generic_args(last).first() {
+            return rust_type(inner);
+        }
+    }
+    Ok(path.segments.iter().map(|s| ident_name(&s.ident)).collect())
+}
+
+// The value type of a `HashMap` or `BTreeMap`.
+fn map_value_type(typ: &syn::Type) -> Option<&syn::Type> {
+    match typ {
+        syn::Type::Path(typ) => generic_args(typ.path.segments.last()?).get(1).copied(),
+        _ => None,
+    }
+}
+
+fn generic_args(segment: &syn::PathSegment) -> Vec<&syn::Type> {
+    match segment.arguments {
+        syn::PathArguments::AngleBracketed(ref args) => args
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                syn::GenericArgument::Type(typ) => Some(typ),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Splits a path written in an attribute, such as `user::Kind`.
+fn split_path(path: &str) -> Vec<String> {
+    path.split("::")
+        .map(|s| s.trim().trim_start_matches("r#").to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn find_oneof<'a>(items: &'a [Item], name: &str) -> Option<&'a syn::ItemEnum> {
+    items.iter().find_map(|item| match item {
+        Item::Enum(e) if e.ident == name && derives(&e.attrs, "Oneof") => Some(e),
+        _ => None,
+    })
+}
+
+// The names of the variants of an enum in its `as_str_name` method, by
+// variant.
+fn as_str_names(item: &syn::ItemImpl) -> Option<HashMap<String, String>> {
+    let method = item.items.iter().find_map(|item| match item {
+        syn::ImplItem::Fn(f) if f.sig.ident == "as_str_name" => Some(f),
+        _ => None,
+    })?;
+    let arms = method.block.stmts.iter().find_map(|stmt| match stmt {
+        syn::Stmt::Expr(syn::Expr::Match(m), _) => Some(&m.arms),
+        _ => None,
+    })?;
+    let mut names = HashMap::new();
+    for arm in arms {
+        if let (syn::Pat::Path(pat), Some(name)) = (&arm.pat, lit_string(&arm.body)) {
+            names.insert(last_segment(&pat.path), name);
+        }
+    }
+    Some(names)
+}
+
+fn derives(attrs: &[Attribute], name: &str) -> bool {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("derive"))
+        .any(|attr| {
+            attr.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated)
+                .is_ok_and(|paths| paths.iter().any(|path| last_segment(path) == name))
+        })
+}
+
+fn prost_attribute(attrs: &[Attribute]) -> Option<syn::Result<Punctuated<Meta, Token![,]>>> {
+    let attr = attrs.iter().find(|attr| attr.path().is_ident("prost"))?;
+    Some(attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated))
+}
+
+fn is_deprecated(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| attr.path().is_ident("deprecated"))
+}
+
+fn lit_string(expr: &syn::Expr) -> Option<String> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(s),
+            ..
+        }) => Some(s.value()),
+        _ => None,
+    }
+}
+
+fn int_expr(expr: &syn::Expr) -> Option<i32> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(i),
+            ..
+        }) => i.base10_parse().ok(),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => int_expr(expr).map(|i| -i),
+        _ => None,
+    }
+}
+
+// The bytes of the text of a Rust byte string literal, whose escapes are
+// those of `escape_default`.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("invalid escape in {:?}", text);
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next().ok_or_else(invalid)? {
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            '0' => bytes.push(0),
+            c @ ('\\' | '\'' | '"') => bytes.push(c as u8),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| invalid())?);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(bytes)
+}
+
+fn first_number(member: &Member) -> i32 {
+    match member {
+        Member::Field(field) => field.number,
+        Member::Oneof(_, fields) => fields.iter().map(|f| f.number).min().unwrap_or(0),
+    }
+}
+
+fn last_segment(path: &syn::Path) -> String {
+    path.segments
+        .last()
+        .map_or(String::new(), |s| ident_name(&s.ident))
+}
+
+// The name of an identifier, without the `r#` of a raw one.
+fn ident_name(ident: &syn::Ident) -> String {
+    let name = ident.to_string();
+    match name.strip_prefix("r#") {
+        Some(name) => name.to_owned(),
+        None => name,
+    }
+}
+
+// `HttpRequest` to `http_request`, as prost names the module of a message.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+// `http_request` to `HttpRequest`.
+fn upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            chars.next().map_or(String::new(), |first| {
+                first.to_uppercase().chain(chars).collect()
+            })
+        })
+        .collect()
+}
+
+fn error<E: std::fmt::Display>(context: &str, err: E) -> io::Error {
+    invalid(format!("{}: {}", context, err))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn annotate(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
@@ -0,0 +1,126 @@
+//! Buf images.
+//!
+//! An image is what `buf build -o image.binpb` writes: a `FileDescriptorSet`
+//! whose files carry an extra `buf_extension` field (number 8042) with
+//! per-file metadata, such as whether the file was only pulled in as an
+//! import.
+//!
+//! Reference: https://github.com/bufbuild/buf/blob/main/proto/buf/alpha/image/v1/image.proto
+
+use prost::Message;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Image {
+    pub file: Vec<ImageFile>,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ImageFile {
+    pub descriptor: prost_types::FileDescriptorProto,
+    pub buf_extension: Option<ImageFileExtension>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ImageFileExtension {
+    // True if this file was not explicitly specified as part of the build,
+    // but was only included as an import of another file.
+    #[prost(bool, optional, tag = "1")]
+    pub is_import: Option<bool>,
+    // The module this file came from, if any.
+    #[prost(message, optional, tag = "2")]
+    pub module_info: Option<ModuleInfo>,
+    // True if the file had no `syntax` statement. The descriptor then says
+    // "proto2" even though nothing in the source did.
+    #[prost(bool, optional, tag = "3")]
+    pub is_syntax_unspecified: Option<bool>,
+    // Indexes of the dependencies that are not used by this file.
+    #[prost(int32, repeated, packed = "false", tag = "4")]
+    pub unused_dependency: Vec<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ModuleInfo {
+    #[prost(message, optional, tag = "1")]
+    pub name: Option<ModuleName>,
+    #[prost(string, optional, tag = "2")]
+    pub commit: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ModuleName {
+    #[prost(string, optional, tag = "1")]
+    pub remote: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub owner: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub repository: Option<String>,
+}
+
+impl Image {
+    /// The files that were part of the build, leaving out imports.
+    pub fn files_to_generate(&self) -> impl Iterator<Item = &prost_types::FileDescriptorProto> {
+        self.file
+            .iter()
+            .filter(|f| !f.is_import())
+            .map(|f| &f.descriptor)
+    }
+
+    pub fn into_descriptor_set(self) -> prost_types::FileDescriptorSet {
+        prost_types::FileDescriptorSet {
+            file: self.file.into_iter().map(|f| f.descriptor).collect(),
+        }
+    }
+}
+
+impl ImageFile {
+    pub fn is_import(&self) -> bool {
+        self.buf_extension
+            .as_ref()
+            .and_then(|e| e.is_import)
+            .unwrap_or(false)
+    }
+}
+
+// `ImageFile` is a `FileDescriptorProto` plus field 8042, so the descriptors
+// are decoded as a plain set and the extensions separately from this view.
+#[derive(Clone, PartialEq, Message)]
+struct RawImage {
+    #[prost(message, repeated, tag = "1")]
+    file: Vec<RawImageFile>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RawImageFile {
+    #[prost(message, optional, tag = "8042")]
+    buf_extension: Option<ImageFileExtension>,
+}
+
+/// Reads a buf image, e.g. the output of `buf build -o image.binpb`.
+///
+/// Gzip-compressed files are detected and decompressed transparently.
+pub fn read_image<P>(path: P) -> io::Result<Image>
+where
+    P: AsRef<Path>,
+{
+    decode_image(&fs::read(path)?)
+}
+
+pub fn decode_image(bytes: &[u8]) -> io::Result<Image> {
+    let bytes = crate::gunzip(bytes)?;
+    let descriptor_set = crate::custom_options::decode_set(&bytes).map_err(crate::invalid_data)?;
+    let raw = RawImage::decode(&*bytes).map_err(crate::invalid_data)?;
+
+    let file = descriptor_set
+        .file
+        .into_iter()
+        .zip(raw.file)
+        .map(|(descriptor, raw)| ImageFile {
+            descriptor,
+            buf_extension: raw.buf_extension,
+        })
+        .collect();
+    Ok(Image { file })
+}
@@ -1,615 +1,1189 @@
-// use alloc_counter::{count_alloc, AllocCounterSystem};
-use prost::Message;
-use std::fmt::Write;
+use std::fmt::{self, Write};
 use std::fs;
-use std::path::Path;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 
-// #[global_allocator]
-// static A: AllocCounterSystem = AllocCounterSystem;
+pub mod ast;
+pub mod builder;
+pub mod config;
+mod custom_options;
+pub mod edit;
+pub mod format;
+#[cfg(feature = "from-rust")]
+pub mod from_rust;
+pub mod image;
+mod options;
+pub mod output;
+pub mod path;
+pub mod plugin;
+pub mod pool;
+pub mod pretty;
+#[cfg(feature = "reflect")]
+pub mod reflect;
+pub mod rules;
+#[cfg(feature = "script")]
+pub mod script;
+mod spans;
+pub mod transform;
+pub mod visit;
+
+pub use builder::FileBuilder;
+pub use format::{GeneratorConfig, Indent, LineEnding};
+pub use output::{render_set, RenderSetOptions};
+use pretty::Doc;
 
 pub trait ProtobufString {
     fn build_protobuf(&self, gen: &mut Generator);
 
-    // #[count_alloc]
-    fn to_protobuf(&self, file_descriptor: prost_types::FileDescriptorProto) -> String {
-        let syntax = file_descriptor
-            .syntax
-            .map_or(prost_types::Syntax::Proto2, |x| {
-                if x == "proto3" {
-                    prost_types::Syntax::Proto3
-                } else {
-                    prost_types::Syntax::Proto2
-                }
-            });
+    /// Renders `self`, taking the syntax and comments from `file_descriptor`,
+    /// the file it belongs to; for a file, that is the file itself:
+    /// `file.to_protobuf(&file)`.
+    fn to_protobuf(&self, file_descriptor: &prost_types::FileDescriptorProto) -> String {
+        self.to_protobuf_with(file_descriptor, &GeneratorConfig::default())
+    }
 
-        let source_info = if let Some(mut source_info) = file_descriptor.source_code_info {
-            source_info.location.sort_by(|a, b| a.path.cmp(&b.path));
-            Some(source_info)
-        } else {
-            None
-        };
+    fn to_protobuf_with(
+        &self,
+        file_descriptor: &prost_types::FileDescriptorProto,
+        config: &GeneratorConfig,
+    ) -> String {
+        let mut gen = Generator::new(config).source(file_descriptor);
+        self.build_protobuf(&mut gen);
+        gen.finish()
+    }
 
-        let mut buf = String::new();
-        buf.reserve(2048);
-        let path = Vec::with_capacity(10);
-        let indent = String::with_capacity(200);
-        let mut gen = Generator {
-            syntax,
-            source_info,
-            buf,
-            path,
-            indent,
-        };
+    /// Like [`to_protobuf_with`](Self::to_protobuf_with), but also returns
+    /// the location of every element in the output, along with the comments
+    /// written for it.
+    ///
+    /// Paths number the elements as protoc would when compiling the output,
+    /// which is how they are numbered in `file_descriptor` unless, e.g., a
+    /// message declares nested types between map fields.
+    fn to_protobuf_with_source_info(
+        &self,
+        file_descriptor: &prost_types::FileDescriptorProto,
+        config: &GeneratorConfig,
+    ) -> (String, prost_types::SourceCodeInfo) {
+        let mut gen = Generator::new(config)
+            .source(file_descriptor)
+            .record_source_info();
+        self.build_protobuf(&mut gen);
+        gen.finish_with_source_info()
+    }
+
+    /// Like [`to_protobuf_with`](Self::to_protobuf_with), but lets `hooks`
+    /// override or decorate how elements are rendered.
+    fn to_protobuf_with_hooks(
+        &self,
+        file_descriptor: &prost_types::FileDescriptorProto,
+        config: &GeneratorConfig,
+        hooks: &dyn RenderHooks,
+    ) -> String {
+        let mut gen = Generator::new(config).source(file_descriptor).hooks(hooks);
         self.build_protobuf(&mut gen);
-        gen.buf
+        gen.finish()
+    }
+
+    /// Renders into `out` as the output is produced, rather than into a
+    /// `String`, so that only a few kilobytes of it are held in memory.
+    fn write_protobuf(
+        &self,
+        file_descriptor: &prost_types::FileDescriptorProto,
+        config: &GeneratorConfig,
+        out: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        let mut gen = Generator::new(config).source(file_descriptor);
+        gen.sink = Some(Sink::Io(out));
+        self.build_protobuf(&mut gen);
+        gen.finish_sink()
+    }
+
+    /// Like [`write_protobuf`](Self::write_protobuf), for a `fmt::Write`
+    /// such as a `Formatter`.
+    fn write_protobuf_fmt(
+        &self,
+        file_descriptor: &prost_types::FileDescriptorProto,
+        config: &GeneratorConfig,
+        out: &mut dyn fmt::Write,
+    ) -> fmt::Result {
+        let mut gen = Generator::new(config).source(file_descriptor);
+        gen.sink = Some(Sink::Fmt(out));
+        self.build_protobuf(&mut gen);
+        gen.finish_sink().map_err(|_| fmt::Error)
     }
 }
 
-pub struct Generator {
-    syntax: prost_types::Syntax,
-    source_info: Option<prost_types::SourceCodeInfo>,
+pub struct Generator<'a> {
+    // Where comments are looked up while converting descriptors.
+    source: ast::Source<'a>,
+    // The output not yet handed to `sink`, or all of it if there is none.
     buf: String,
+    sink: Option<Sink<'a>>,
+    // The first error from `sink`, after which nothing more is written.
+    error: Option<io::Error>,
+    // The path of the element being written, as in `SourceCodeInfo`.
     path: Vec<i32>,
     indent: String,
+    config: GeneratorConfig,
+    // Fields or enum values waiting to be written as one aligned group.
+    rows: Vec<Row>,
+    row_text: String,
+    row_paths: Vec<i32>,
+    // The locations of the elements written, if requested.
+    spans: Option<spans::Spans>,
+    file_location: Span,
+    hooks: Option<&'a dyn RenderHooks>,
+}
+
+/// An element whose location is being recorded, from
+/// [`Generator::begin`] until it is passed to [`Generator::end`].
+#[must_use]
+#[derive(Debug)]
+pub struct Span(Option<usize>);
+
+pub(crate) enum Sink<'a> {
+    Io(&'a mut dyn io::Write),
+    Fmt(&'a mut dyn fmt::Write),
+}
+
+// Once `buf` grows past this, its complete lines are handed to the sink.
+const SINK_CHUNK_SIZE: usize = 8 * 1024;
+
+/// A syntax tree node that can be written by a [`Generator`].
+pub trait Print {
+    /// Writes the standard rendering of the node. The nodes nested in it are
+    /// still written through the generator's [`RenderHooks`].
+    fn print(&self, gen: &mut Generator);
+
+    /// Writes the node through the generator's [`RenderHooks`], if it has a
+    /// hook for this kind of node; [`Generator::print_at`] writes nodes this
+    /// way.
+    fn print_with_hooks(&self, gen: &mut Generator) {
+        self.print(gen)
+    }
+}
+
+/// Overrides or decorates how the elements of a file are rendered.
+///
+/// Each method is called in place of writing one element, at the
+/// [`path`](Generator::path) of that element, and defaults to its standard
+/// rendering. An override can write something around the standard rendering,
+/// or replace it altogether. For example, to put a banner above every
+/// service:
+///
+/// ```
+/// use protobuf_gen::{ast, Generator, GeneratorConfig, Print, ProtobufString, RenderHooks};
+///
+/// struct Banner;
+///
+/// impl RenderHooks for Banner {
+///     fn service(&self, gen: &mut Generator, service: &ast::Service) {
+///         gen.write_blank_lines(1);
+///         gen.write_detached_comment(" Generated by acme. DO NOT EDIT.\n");
+///         service.print(gen);
+///     }
+/// }
+///
+/// let file = protobuf_gen::read_sources(
+///     vec![("echo.proto", "syntax = \"proto3\"; message Ping {} service Echo { rpc Echo(Ping) returns (Ping); }")],
+///     &["."],
+/// )
+/// .file
+/// .remove(0);
+/// let text = file.to_protobuf_with_hooks(&file, &GeneratorConfig::default(), &Banner);
+/// assert!(text.contains("\n// Generated by acme. DO NOT EDIT.\n\nservice Echo {\n"));
+/// ```
+pub trait RenderHooks {
+    fn message(&self, gen: &mut Generator, message: &ast::Message) {
+        message.print(gen)
+    }
+
+    fn field(&self, gen: &mut Generator, field: &ast::Field) {
+        field.print(gen)
+    }
+
+    fn enumeration(&self, gen: &mut Generator, enumeration: &ast::Enum) {
+        enumeration.print(gen)
+    }
+
+    fn enum_value(&self, gen: &mut Generator, value: &ast::EnumValue) {
+        value.print(gen)
+    }
+
+    fn service(&self, gen: &mut Generator, service: &ast::Service) {
+        service.print(gen)
+    }
+
+    fn method(&self, gen: &mut Generator, method: &ast::Method) {
+        method.print(gen)
+    }
 }
 
-impl Generator {
+// A field or enum value statement, split into the columns that get aligned.
+// The text lives in the generator's `row_text`, so that queuing a row does
+// not allocate.
+struct Row {
+    // Label and type, empty for enum values.
+    prefix: Range<usize>,
+    name: Range<usize>,
+    number: Range<usize>,
+    options: Vec<Doc>,
+    trailing_comment: Option<Range<usize>>,
+    // Into `row_paths`, only kept when recording spans.
+    path: Option<Range<usize>>,
+}
+
+impl<'a> Generator<'a> {
+    /// A generator writing into a `String`, to be taken with
+    /// [`finish`](Self::finish). Implementations of [`ProtobufString`] are
+    /// handed one that is already set up.
+    pub fn new(config: &GeneratorConfig) -> Generator<'a> {
+        Generator {
+            source: ast::Source::default(),
+            buf: String::with_capacity(2048),
+            sink: None,
+            error: None,
+            path: Vec::with_capacity(10),
+            indent: String::with_capacity(200),
+            config: config.clone(),
+            rows: Vec::new(),
+            row_text: String::new(),
+            row_paths: Vec::new(),
+            spans: None,
+            file_location: Span(None),
+            hooks: None,
+        }
+    }
+
+    /// Takes the syntax and the comments of the elements converted from
+    /// descriptors from `file`, the file they belong to.
+    pub fn source(mut self, file: &'a prost_types::FileDescriptorProto) -> Self {
+        self.source = ast::Source::new(file);
+        self.source.lossless = self.config.lossless;
+        self
+    }
+
+    pub fn hooks(mut self, hooks: &'a dyn RenderHooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Records the location of every element written, for
+    /// [`finish_with_source_info`](Self::finish_with_source_info).
+    pub fn record_source_info(mut self) -> Self {
+        self.spans = Some(spans::Spans::default());
+        self
+    }
+
+    pub fn config(&self) -> &GeneratorConfig {
+        &self.config
+    }
+
+    /// The path of the element being written, as in `SourceCodeInfo`.
+    pub fn path(&self) -> &[i32] {
+        &self.path
+    }
+
+    /// Starts the location of the element at [`path`](Self::path) at the
+    /// current position, if locations are being recorded. Call it after
+    /// the indentation and before the first token of the element.
+    pub fn begin(&mut self) -> Span {
+        self.flush_rows();
+        match self.spans {
+            Some(ref mut spans) => Span(Some(spans.begin(&self.buf, &self.path))),
+            None => Span(None),
+        }
+    }
+
+    /// Ends the location started by [`begin`](Self::begin) at the current
+    /// position, which is after the last token of the element.
+    pub fn end(&mut self, span: Span) {
+        if let (Some(spans), Some(index)) = (self.spans.as_mut(), span.0) {
+            spans.end(&self.buf, index);
+        }
+    }
+
+    /// Writes `element`, through the hooks, at the current path.
+    pub fn print<T: Print + ?Sized>(&mut self, element: &T) {
+        element.print_with_hooks(self);
+    }
+
+    /// Writes `element`, through the hooks, at `path` relative to the
+    /// current element, e.g. `&[path::MESSAGE_FIELD, 0]` for the first field
+    /// of a message.
+    pub fn print_at<T: Print + ?Sized>(&mut self, path: &[i32], element: &T) {
+        self.path.extend_from_slice(path);
+        element.print_with_hooks(self);
+        self.path.truncate(self.path.len() - path.len());
+    }
+
+    // Writes the `option` statements of the current element, whose options
+    // are under field `field` of it.
+    fn write_options(&mut self, field: i32, options: &[ast::OptionStatement]) {
+        let mut uninterpreted = 0;
+        for option in options {
+            match option.number {
+                Some(number) => self.print_at(&[field, number], option),
+                None => {
+                    self.print_at(&[field, path::UNINTERPRETED_OPTION, uninterpreted], option);
+                    uninterpreted += 1;
+                }
+            }
+        }
+    }
+
+    // Writes an `extend` block, whose fields are numbered from `*index` among
+    // the extensions under field `field` of the current element.
+    fn write_extend(&mut self, field: i32, extend: &ast::Extend, index: &mut i32) {
+        self.path.push(field);
+        self.write_declaration_comment(&extend.comments);
+        self.write_indent();
+        let location = self.begin();
+        self.path.pop();
+        self.write("extend ");
+        self.write(&extend.extendee);
+        self.open_block();
+        for extension in &extend.fields {
+            self.print_at(&[field, *index], extension);
+            *index += 1;
+        }
+        self.close_block(location);
+    }
+
     fn inc_indent(&mut self) {
-        self.indent.push_str("  ");
+        self.flush_rows();
+        self.config.indent.push_to(&mut self.indent);
     }
 
     fn dec_indent(&mut self) {
-        self.indent.pop();
-        self.indent.pop();
+        self.flush_rows();
+        let len = self.indent.len() - self.config.indent.width();
+        self.indent.truncate(len);
     }
 
-    fn open_block(&mut self) {
+    /// Writes ` {` and a line break, and indents what follows.
+    pub fn open_block(&mut self) {
         self.write(" {\n");
         self.inc_indent();
     }
 
-    fn close_block(&mut self) {
+    /// Writes the `}` closing the block of the element at `span`, and
+    /// ends it.
+    pub fn close_block(&mut self, span: Span) {
         self.dec_indent();
         self.write_indent();
-        self.write("}\n");
+        self.write("}");
+        self.end(span);
+        self.write("\n");
     }
 
-    fn write(&mut self, text: &str) {
+    /// Writes `text`, which should not contain line breaks other than at
+    /// its end; start each line with [`write_indent`](Self::write_indent).
+    pub fn write(&mut self, text: &str) {
+        self.flush_rows();
         self.buf.push_str(text);
     }
 
-    fn write_indent(&mut self) {
+    /// Writes the indentation of the current nesting level.
+    pub fn write_indent(&mut self) {
+        self.flush_rows();
+        if self.buf.len() >= SINK_CHUNK_SIZE {
+            // Trailing newlines are kept back, since `finish` trims them.
+            let content = self.buf.trim_end_matches('\n');
+            if let Some(i) = content.rfind('\n') {
+                self.emit(i + 1);
+            }
+        }
         self.buf.push_str(&self.indent);
     }
 
-    fn write_leading_comment(&mut self) -> Option<()> {
-        if let Some(ref comment) = self.location()?.leading_comments.clone() {
-            self.buf.push_str("\n");
-            for line in comment.lines() {
-                self.buf.push_str(&self.indent);
-                self.buf.push_str("//");
-                self.buf.push_str(line);
-                self.buf.push_str("\n");
+    pub fn write_blank_lines(&mut self, lines: usize) {
+        self.flush_rows();
+        for _ in 0..lines {
+            self.buf.push('\n');
+        }
+    }
+
+    /// Writes the detached and leading comments of the element about to be
+    /// written, preceded by `blank_lines`, if it has any and comments are
+    /// enabled. Returns whether anything was written.
+    pub fn write_leading_comment(&mut self, comments: &ast::Comments, blank_lines: usize) -> bool {
+        if !self.config.comments || (comments.leading.is_none() && comments.detached.is_empty()) {
+            return false;
+        }
+        self.write_blank_lines(blank_lines);
+        for comment in &comments.detached {
+            self.write_detached_comment(comment);
+            self.write_blank_lines(1);
+        }
+        let comment = match comments.leading {
+            Some(ref comment) => self.write_comment_lines(comment),
+            None => return true,
+        };
+        if let Some(ref mut spans) = self.spans {
+            spans.set_leading_comment(comment);
+        }
+        true
+    }
+
+    /// Writes a comment that belongs to no element, such as a banner, in
+    /// the `SourceCodeInfo` text form, e.g. `" Generated.\n"`. It is
+    /// recorded as detached from the element written next, which should
+    /// follow after a blank line for protoc to read it back the same way.
+    pub fn write_detached_comment(&mut self, comment: &str) {
+        if !self.config.comments {
+            return;
+        }
+        self.flush_rows();
+        let comment = self.write_comment_lines(comment);
+        if let Some(ref mut spans) = self.spans {
+            spans.add_detached_comment(comment);
+        }
+    }
+
+    // Writes `comment` as `//` lines at the current indentation, returning
+    // it as written.
+    fn write_comment_lines(&mut self, comment: &str) -> String {
+        let comment = self.reflow(comment, self.indent.len() + "//".len());
+        for line in comment.lines() {
+            self.buf.push_str(&self.indent);
+            self.buf.push_str("//");
+            self.buf.push_str(line);
+            self.buf.push('\n');
+        }
+        comment.into_owned()
+    }
+
+    // Writes the blank lines and leading comment before a message, enum or
+    // service, which are separated by blank lines even without a comment.
+    fn write_declaration_comment(&mut self, comments: &ast::Comments) {
+        let blank_lines = self.config.blank_lines_between_declarations;
+        if !self.write_leading_comment(comments, blank_lines) {
+            self.write_blank_lines(blank_lines);
+        }
+    }
+
+    // With `align` set, rows are held back until something else is written,
+    // so that a run of them can be padded into columns.
+    fn write_row(
+        &mut self,
+        label: Option<ast::Label>,
+        typ: &str,
+        name: &str,
+        number: i32,
+        options: &[ast::CompactOption],
+        comments: &ast::Comments,
+    ) {
+        let text = &mut self.row_text;
+        let start = text.len();
+        if let Some(label) = label {
+            text.push_str(label.as_str());
+            text.push(' ');
+        }
+        text.push_str(typ);
+        let prefix = start..text.len();
+        text.push_str(name);
+        let name = prefix.end..text.len();
+        let _ = write!(text, "{}", number);
+        let number = name.end..text.len();
+        let trailing_comment = match comments.trailing {
+            Some(ref comment) if self.config.trailing_comments() => {
+                text.push_str(comment);
+                Some(number.end..text.len())
             }
-            Some(())
-        } else {
-            None
-        }
-    }
-
-    fn location(&self) -> Option<&prost_types::source_code_info::Location> {
-        let location = self
-            .source_info
-            .as_ref()?
-            .location
-            .binary_search_by_key(&&self.path[..], |location| &location.path[..]);
-
-        match location {
-            Ok(location_idx) => Some(&self.source_info.as_ref()?.location[location_idx]),
-            Err(_) => None,
-        }
-    }
-}
-
-fn write_field_type(buf: &mut String, field: &prost_types::FieldDescriptorProto) {
-    if let Some(ref type_name) = field.type_name {
-        buf.push_str(type_name);
-    } else if let Some(typ) = field.r#type {
-        if typ >= 1 && typ <= 18 {
-            use prost_types::field_descriptor_proto::Type;
-            match unsafe { std::mem::transmute(typ) } {
-                Type::Double => write!(buf, "{}", "double").unwrap(),
-                Type::Float => write!(buf, "{}", "float").unwrap(),
-                Type::Int64 => write!(buf, "{}", "int64").unwrap(),
-                Type::Uint64 => write!(buf, "{}", "uint64").unwrap(),
-                Type::Int32 => write!(buf, "{}", "int32").unwrap(),
-                Type::Fixed64 => write!(buf, "{}", "fixed64").unwrap(),
-                Type::Fixed32 => write!(buf, "{}", "fixed32").unwrap(),
-                Type::Bool => write!(buf, "{}", "bool").unwrap(),
-                Type::String => write!(buf, "{}", "string").unwrap(),
-                // Tag-delimited aggregate.
-                // Group type is deprecated and not supported in proto3. However, Proto3
-                // implementations should still be able to parse the group wire format and
-                // treat group fields as unknown fields.
-                Type::Group => write!(buf, "{}", "group").unwrap(),
-                // Length-delimited aggregate.
-                Type::Message => write!(buf, "{}", "message").unwrap(),
-                // New in version 2.
-                Type::Bytes => write!(buf, "{}", "bytes").unwrap(),
-                Type::Uint32 => write!(buf, "{}", "uint32").unwrap(),
-                Type::Enum => write!(buf, "{}", "enum").unwrap(),
-                Type::Sfixed32 => write!(buf, "{}", "sfixed32").unwrap(),
-                Type::Sfixed64 => write!(buf, "{}", "sfixed64").unwrap(),
-                Type::Sint32 => write!(buf, "{}", "sint32").unwrap(),
-                Type::Sint64 => write!(buf, "{}", "sint64").unwrap(),
+            _ => None,
+        };
+        let path = match self.spans {
+            Some(_) => {
+                let start = self.row_paths.len();
+                self.row_paths.extend_from_slice(&self.path);
+                Some(start..self.row_paths.len())
             }
+            None => None,
+        };
+
+        self.rows.push(Row {
+            prefix,
+            name,
+            number,
+            options: options.iter().map(options::doc).collect(),
+            trailing_comment,
+            path,
+        });
+        if !self.config.align {
+            self.flush_rows();
         }
     }
-}
 
-impl ProtobufString for prost_types::FileDescriptorProto {
-    fn build_protobuf(&self, gen: &mut Generator) {
-        // The syntax of the proto file.
-        // The supported values are "proto2" and "proto3".
-        if let Some(ref syntax) = self.syntax {
-            gen.path.push(12);
-            gen.write_indent();
-            gen.write("syntax = \"");
-            gen.write(syntax);
-            gen.write("\";\n\n");
-            gen.path.pop();
+    fn flush_rows(&mut self) {
+        if self.rows.is_empty() {
+            return;
         }
+        let mut rows = std::mem::take(&mut self.rows);
+        let text = std::mem::take(&mut self.row_text);
+        let paths = std::mem::take(&mut self.row_paths);
+
+        let width = |column: &dyn Fn(&Row) -> usize| rows.iter().map(column).max().unwrap_or(0);
+        let prefix_width = width(&|row| row.prefix.len());
+        let name_column = match prefix_width {
+            0 => self.indent.len(),
+            _ => self.indent.len() + prefix_width + 1,
+        };
+        let number_column = name_column + width(&|row| row.name.len()) + " = ".len();
+        let comment_column = number_column + width(&Row::flat_width) + ";".len();
+
+        for row in rows.drain(..) {
+            let start = self.buf.len();
+            self.buf.push_str(&self.indent);
+            let index = match (self.spans.as_mut(), row.path) {
+                (Some(spans), Some(path)) => Some(spans.begin(&self.buf, &paths[path])),
+                _ => None,
+            };
+            if !row.prefix.is_empty() {
+                self.buf.push_str(&text[row.prefix]);
+                self.buf.push(' ');
+            }
+            if self.config.align {
+                pad(&mut self.buf, start, name_column);
+            }
+            self.buf.push_str(&text[row.name]);
+            if self.config.align {
+                pad(&mut self.buf, start, number_column - " = ".len());
+            }
+            self.buf.push_str(" = ");
+            self.buf.push_str(&text[row.number]);
+            if row.options.is_empty() {
+                self.buf.push(';');
+            } else {
+                let doc = Doc::concat(vec![
+                    Doc::text(" "),
+                    Doc::bracket("[", row.options, "]"),
+                    Doc::text(";"),
+                ]);
+                self.render(&doc);
+            }
+            self.end(Span(index));
 
-        // e.g. "foo", "foo.bar", etc.
-        gen.path.push(2);
-        if let Some(ref package) = self.package {
-            gen.write_leading_comment();
-            gen.write_indent();
-            gen.write("package ");
-            gen.write(package);
-            gen.write(";\n\n");
+            if let Some(comment) = row.trailing_comment {
+                let broken = self.buf[start..].contains('\n');
+                if self.config.align && !broken {
+                    pad(&mut self.buf, start, comment_column);
+                }
+                self.buf.push_str("  ");
+                let line_start = self.buf.rfind('\n').map_or(0, |i| i + 1).max(start);
+                let column = self.buf.len() - line_start;
+                let comment = self.reflow(&text[comment], column + "//".len());
+                for (i, line) in comment.lines().enumerate() {
+                    if i > 0 {
+                        self.buf.push('\n');
+                        self.buf.extend(std::iter::repeat_n(' ', column));
+                    }
+                    self.buf.push_str("//");
+                    self.buf.push_str(line);
+                }
+                if let (Some(spans), Some(index)) = (self.spans.as_mut(), index) {
+                    spans.set_trailing_comment(index, comment.into_owned());
+                }
+            }
+            self.buf.push('\n');
         }
-        gen.path.pop();
 
-        // Names of files imported by this file.
-        gen.path.push(3);
-        for (i, dep) in self.dependency.iter().enumerate() {
-            gen.path.push(i as i32);
-            gen.write_indent();
-            gen.write("import \"");
-            gen.write(dep);
-            gen.write("\";\n");
-            gen.path.pop();
+        // Hand the buffers back, keeping their capacity for the next group.
+        self.rows = rows;
+        self.row_text = text;
+        self.row_text.clear();
+        self.row_paths = paths;
+        self.row_paths.clear();
+    }
+
+    // Lays `doc` out from the current position, within `max_width`.
+    fn render(&mut self, doc: &Doc) {
+        let mut unit = String::new();
+        self.config.indent.push_to(&mut unit);
+        doc.render(&mut self.buf, &self.indent, &unit, self.config.max_width);
+    }
+
+    // With `reflow_comments` set, rewraps a comment that starts at `column`.
+    fn reflow<'c>(&self, comment: &'c str, column: usize) -> std::borrow::Cow<'c, str> {
+        if self.config.reflow_comments {
+            let width = self.config.max_width.saturating_sub(column);
+            pretty::reflow(comment, width).into()
+        } else {
+            comment.into()
         }
-        gen.path.pop();
+    }
+
+    // Hands `buf[..len]` to the sink, if there is one.
+    fn emit(&mut self, len: usize) {
+        let sink = match self.sink {
+            Some(ref mut sink) if self.error.is_none() => sink,
+            _ => return,
+        };
 
-        // Indexes of the public imported files in the dependency list above.
-        // TODO: public_dependency: ::std::vec::Vec<i32>,
+        let chunk = match self.config.line_ending {
+            LineEnding::Lf => std::borrow::Cow::Borrowed(&self.buf[..len]),
+            LineEnding::CrLf => self.buf[..len].replace('\n', "\r\n").into(),
+        };
+        let result = match sink {
+            Sink::Io(out) => out.write_all(chunk.as_bytes()),
+            Sink::Fmt(out) => out
+                .write_str(&chunk)
+                .map_err(|_| io::Error::other("formatter error")),
+        };
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
 
-        // Indexes of the weak imported files in the dependency list.
-        // For Google-internal migration only. Do not use.
-        // weak_dependency: ::std::vec::Vec<i32>,
+        if let Some(ref mut spans) = self.spans {
+            spans.drain(&self.buf, len);
+        }
+        self.buf.drain(..len);
+    }
 
-        // All top-level definitions in this file.
-        gen.path.push(4);
-        for (i, msg) in self.message_type.iter().enumerate() {
-            gen.path.push(i as i32);
-            msg.build_protobuf(gen);
-            gen.path.pop();
+    // Ends the output with the configured final newline.
+    fn end_output(&mut self) {
+        self.flush_rows();
+        let len = self.buf.trim_end_matches('\n').len();
+        self.buf.truncate(len);
+        let span = std::mem::replace(&mut self.file_location, Span(None));
+        self.end(span);
+        if self.config.final_newline {
+            self.buf.push('\n');
         }
-        gen.path.pop();
+    }
 
-        gen.path.push(5);
-        for (i, enum_type) in self.enum_type.iter().enumerate() {
-            gen.path.push(i as i32);
-            enum_type.build_protobuf(gen);
-            gen.path.pop();
+    /// Ends the output, returning it.
+    pub fn finish(self) -> String {
+        self.finish_with_source_info().0
+    }
+
+    /// Ends the output, returning it along with the recorded locations,
+    /// which are empty unless [`record_source_info`](Self::record_source_info)
+    /// was set.
+    pub fn finish_with_source_info(mut self) -> (String, prost_types::SourceCodeInfo) {
+        self.end_output();
+        let buf = match self.config.line_ending {
+            LineEnding::Lf => self.buf,
+            LineEnding::CrLf => self.buf.replace('\n', "\r\n"),
+        };
+        (buf, self.spans.unwrap_or_default().finish())
+    }
+
+    fn finish_sink(mut self) -> io::Result<()> {
+        self.end_output();
+        self.emit(self.buf.len());
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(()),
         }
-        gen.path.pop();
+    }
+}
 
-        gen.path.push(6);
-        for (i, serv) in self.service.iter().enumerate() {
-            gen.path.push(i as i32);
-            serv.build_protobuf(gen);
-            gen.path.pop();
+impl Row {
+    // The width of the number and the options following it, on one line.
+    fn flat_width(&self) -> usize {
+        let number = self.number.len();
+        match self.options.len() {
+            0 => number,
+            n => {
+                let options: usize = self.options.iter().map(Doc::flat_width).sum();
+                number + " [".len() + options + (n - 1) * ", ".len() + "]".len()
+            }
         }
-        gen.path.pop();
+    }
+}
 
-        // TODO: extension: ::std::vec::Vec<FieldDescriptorProto>,
-        // TODO: options: ::std::option::Option<FileOptions>,
+// Pads the line that started at `start` with spaces up to `column`.
+fn pad(buf: &mut String, start: usize, column: usize) {
+    let len = buf.len() - start;
+    if len < column {
+        buf.extend(std::iter::repeat_n(' ', column - len));
+    }
+}
 
-        // This field contains optional information about the original source code.
-        // You may safely remove this entire field without harming runtime
-        // functionality of the descriptors -- the information is needed only by
-        // development tools.
-        // source_code_info: ::std::option::Option<SourceCodeInfo>,
+impl ProtobufString for prost_types::FileDescriptorProto {
+    fn build_protobuf(&self, gen: &mut Generator) {
+        let file = ast::File::from_source(self, &mut gen.source);
+        gen.print(&file);
     }
 }
 
 impl ProtobufString for prost_types::DescriptorProto {
     fn build_protobuf(&self, gen: &mut Generator) {
-        if gen.write_leading_comment().is_none() {
-            gen.write("\n");
-        }
+        let message = ast::Message::from_source(self, &mut gen.source);
+        gen.print(&message);
+    }
+}
 
-        gen.write_indent();
-        gen.write("message");
-        if let Some(ref name) = self.name {
-            gen.write(" ");
-            gen.write(name);
-        }
-        gen.open_block();
+impl ProtobufString for prost_types::FieldDescriptorProto {
+    fn build_protobuf(&self, gen: &mut Generator) {
+        let field = ast::Field::from_source(self, &gen.source);
+        gen.print(&field);
+    }
+}
 
-        // needed to handle `Map<Type, Type>` syntax
-        let map_entries: std::collections::HashMap<_, _> = self
-            .nested_type
-            .iter()
-            .filter_map(|t| {
-                if t.options.as_ref()?.map_entry? {
-                    Some((t.name.as_ref().unwrap(), t))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        gen.path.push(2);
-        for (i, field) in self
-            .field
-            .iter()
-            .enumerate()
-            .filter(|(_, f)| f.oneof_index.is_none())
-        {
-            gen.path.push(i as i32);
-            if let Some(ref type_name) = field.type_name {
-                let sub_type = &type_name[type_name.rfind('.').unwrap() + 1..];
-                if let Some(prost_types::DescriptorProto { field: fields, .. }) =
-                    map_entries.get(&sub_type.to_owned())
-                {
-                    let mut typ = String::with_capacity(32);
-                    typ.push_str("Map<");
-                    write_field_type(&mut typ, fields.get(0).unwrap());
-                    typ.push_str(", ");
-                    write_field_type(&mut typ, fields.get(1).unwrap());
-                    typ.push_str(">");
-                    let mut field = field.to_owned();
-                    field.type_name = Some(typ);
-                    field.label = None;
-                    field.build_protobuf(gen);
-                } else {
-                    field.build_protobuf(gen);
-                }
-            } else {
-                field.build_protobuf(gen);
-            }
-            gen.path.pop();
-        }
-        gen.path.pop();
+impl ProtobufString for prost_types::EnumDescriptorProto {
+    fn build_protobuf(&self, gen: &mut Generator) {
+        let enumeration = ast::Enum::from_source(self, &mut gen.source);
+        gen.print(&enumeration);
+    }
+}
 
-        for (i, oneof) in self.oneof_decl.iter().enumerate() {
-            gen.path.push(8);
-            gen.path.push(i as i32);
-            gen.write_leading_comment();
-            gen.path.pop();
-            gen.path.pop();
+impl ProtobufString for prost_types::EnumValueDescriptorProto {
+    fn build_protobuf(&self, gen: &mut Generator) {
+        let value = ast::EnumValue::from_source(self, &gen.source);
+        gen.print(&value);
+    }
+}
 
-            gen.write_indent();
-            gen.write("oneof");
-            if let Some(ref name) = oneof.name {
-                gen.write(" ");
-                gen.write(name);
-            }
-            gen.write(" {\n");
-            gen.inc_indent();
-            gen.path.push(2);
-            for (i, field) in self
-                .field
-                .iter()
-                .enumerate()
-                .filter(|(_, f)| f.oneof_index.map_or(false, |j| j == i as i32))
-            {
-                gen.path.push(i as i32);
-                field.build_protobuf(gen);
-                gen.path.pop();
-            }
-            gen.path.pop();
+impl ProtobufString for prost_types::ServiceDescriptorProto {
+    fn build_protobuf(&self, gen: &mut Generator) {
+        let service = ast::Service::from_source(self, &mut gen.source);
+        gen.print(&service);
+    }
+}
 
-            gen.dec_indent();
-            gen.write_indent();
-            gen.write("}\n");
-        }
+impl ProtobufString for prost_types::MethodDescriptorProto {
+    fn build_protobuf(&self, gen: &mut Generator) {
+        let method = ast::Method::from_source(self, &gen.source);
+        gen.print(&method);
+    }
+}
 
-        // TODO: extension: ::std::vec::Vec<FieldDescriptorProto>,
+impl Print for ast::File<'_> {
+    fn print(&self, gen: &mut Generator) {
+        gen.file_location = gen.begin();
 
-        gen.path.push(3);
-        for (i, nested_type) in self.nested_type.iter().enumerate().filter(|(_, t)| {
-            t.options
-                .as_ref()
-                .map_or(true, |o| o.map_entry.map_or(true, |e| !e))
-        }) {
-            gen.path.push(i as i32);
-            nested_type.build_protobuf(gen);
+        gen.path.push(path::FILE_SYNTAX);
+        gen.write_leading_comment(&self.comments, 0);
+        gen.path.pop();
+        // Like protoc, the file starts at its first token, after the
+        // comments heading it.
+        if let (Some(spans), Some(index)) = (gen.spans.as_mut(), gen.file_location.0) {
+            spans.restart(&gen.buf, index);
+        }
+        if let Some(ref syntax) = self.syntax {
+            gen.path.push(path::FILE_SYNTAX);
+            gen.write_indent();
+            let location = gen.begin();
+            gen.write("syntax = \"");
+            gen.write(syntax);
+            gen.write("\";");
+            gen.end(location);
+            gen.write("\n\n");
             gen.path.pop();
         }
-        gen.path.pop();
 
-        gen.path.push(4);
-        for (i, enum_type) in self.enum_type.iter().enumerate() {
-            gen.path.push(i as i32);
-            enum_type.build_protobuf(gen);
+        if let Some(ref package) = self.package {
+            gen.path.push(path::FILE_PACKAGE);
+            // Nothing separates the package from the top of the file if it
+            // comes first.
+            let blank_lines = match self.syntax {
+                Some(_) => gen.config.blank_lines_between_declarations,
+                None => 0,
+            };
+            gen.write_leading_comment(&package.comments, blank_lines);
+            gen.write_indent();
+            let location = gen.begin();
+            gen.write("package ");
+            gen.write(&package.name);
+            gen.write(";");
+            gen.end(location);
+            gen.write("\n\n");
             gen.path.pop();
         }
-        gen.path.pop();
-
-        // TODO: extension_range: ::std::vec::Vec<descriptor_proto::ExtensionRange>,
-        // TODO: options: ::std::option::Option<MessageOptions>,
 
-        if self.reserved_name.len() > 0 {
-            gen.write_leading_comment();
+        for (i, import) in self.imports.iter().enumerate() {
+            gen.path.extend([path::FILE_DEPENDENCY, i as i32]);
+            gen.write_leading_comment(&import.comments, 0);
             gen.write_indent();
-            gen.write("reserved ");
-            for (i, range) in self.reserved_range.iter().enumerate() {
-                if i > 0 {
-                    gen.write(", ");
-                }
-
-                // Inclusive
-                if let Some(ref start) = range.start {
-                    write!(gen.buf, "{}", start).unwrap();
-                }
-                gen.write(" to ");
+            let location = gen.begin();
+            gen.write("import ");
+            if import.public {
+                gen.write("public ");
+            } else if import.weak {
+                gen.write("weak ");
+            }
+            gen.write("\"");
+            gen.write(&import.path);
+            gen.write("\";");
+            gen.end(location);
+            gen.write("\n");
+            gen.path.truncate(gen.path.len() - 2);
+        }
 
-                // Exclusive.
-                if let Some(ref end) = range.end {
-                    write!(gen.buf, "{}", end).unwrap();
-                } else {
-                    gen.write("max");
-                }
+        if !self.options.is_empty() {
+            if !self.imports.is_empty() {
+                gen.write_blank_lines(1);
             }
+            gen.write_options(path::FILE_OPTIONS, &self.options);
         }
 
-        // Reserved field names, which may not be used by fields in the same message.
-        // A given name may only be reserved once.
-        if self.reserved_name.len() > 0 {
-            gen.write_leading_comment();
-            gen.write_indent();
-            gen.write("reserved ");
-            for (i, name) in self.reserved_name.iter().enumerate() {
-                if i > 0 {
-                    gen.write(", ");
+        let (mut messages, mut enums, mut services, mut extensions) = (0, 0, 0, 0);
+        for item in &self.items {
+            match item {
+                ast::Item::Message(msg) => {
+                    gen.print_at(&[path::FILE_MESSAGE_TYPE, messages], msg);
+                    messages += 1;
+                }
+                ast::Item::Enum(enum_type) => {
+                    gen.print_at(&[path::FILE_ENUM_TYPE, enums], enum_type);
+                    enums += 1;
+                }
+                ast::Item::Service(service) => {
+                    gen.print_at(&[path::FILE_SERVICE, services], service);
+                    services += 1;
+                }
+                ast::Item::Extend(extend) => {
+                    gen.write_extend(path::FILE_EXTENSION, extend, &mut extensions);
                 }
-                write!(gen.buf, "\"{}\"", name).unwrap();
             }
-            gen.write(";\n");
         }
-
-        gen.close_block();
     }
 }
 
-impl ProtobufString for prost_types::FieldDescriptorProto {
-    fn build_protobuf(&self, gen: &mut Generator) {
-        gen.write_leading_comment();
+impl Print for ast::Message<'_> {
+    fn print_with_hooks(&self, gen: &mut Generator) {
+        match gen.hooks {
+            Some(hooks) => hooks.message(gen, self),
+            None => self.print(gen),
+        }
+    }
 
+    fn print(&self, gen: &mut Generator) {
+        gen.write_declaration_comment(&self.comments);
         gen.write_indent();
-        // If type_name is set, this need not be set.  If both this and type_name
-        // are set, this must be one of TYPE_ENUM, TYPE_MESSAGE or TYPE_GROUP.
-
-        // For message and enum types, this is the name of the type.  If the name
-        // starts with a '.', it is fully-qualified.  Otherwise, C++-like scoping
-        // rules are used to find the type (i.e. first the nested types within this
-        // message are searched, then within the parent, on up to the root
-        // namespace).
-
-        if let Some(label) = self.label {
-            if label >= 1 && label <= 3 {
-                use prost_types::field_descriptor_proto::Label;
-                match unsafe { std::mem::transmute(label) } {
-                    Label::Optional => {
-                        if gen.syntax == prost_types::Syntax::Proto2 {
-                            write!(gen.buf, "{}", "optional").unwrap();
-                            gen.write(" ");
-                        } else if gen.syntax == prost_types::Syntax::Proto3 {
-                            if let Some(true) = self.proto3_optional {
-                                write!(gen.buf, "{}", "optional").unwrap();
-                                gen.write(" ");
-                            }
-                        }
-                    }
-                    Label::Required => {
-                        write!(gen.buf, "{}", "required").unwrap();
-                        gen.write(" ");
+        let location = gen.begin();
+        gen.write("message ");
+        gen.write(&self.name);
+        gen.open_block();
+        gen.write_options(path::MESSAGE_OPTIONS, &self.options);
+
+        // protoc numbers the entry types of map fields along with the
+        // nested types, in the order they are declared.
+        let (mut fields, mut oneofs, mut enums, mut nested_types) = (0, 0, 0, 0);
+        let mut extensions = 0;
+        // Whether the last item was a nested declaration, which the next
+        // one is separated from as if it was a declaration as well.
+        let mut after_declaration = false;
+        for item in &self.items {
+            let comments = match item {
+                ast::MessageItem::Field(ast::Field { comments, .. })
+                | ast::MessageItem::Oneof(ast::Oneof { comments, .. })
+                | ast::MessageItem::Extensions(ast::Extensions { comments, .. })
+                | ast::MessageItem::Reserved(ast::Reserved { comments, .. }) => Some(comments),
+                _ => None,
+            };
+            if let (Some(comments), true) = (comments, after_declaration) {
+                let commented = comments.leading.is_some() || !comments.detached.is_empty();
+                if !(gen.config.comments && commented) {
+                    gen.write_blank_lines(gen.config.blank_lines_between_declarations);
+                }
+            }
+            after_declaration = matches!(
+                item,
+                ast::MessageItem::Message(_)
+                    | ast::MessageItem::Enum(_)
+                    | ast::MessageItem::Extend(_)
+            );
+            match item {
+                ast::MessageItem::Field(field) => {
+                    gen.print_at(&[path::MESSAGE_FIELD, fields], field);
+                    fields += 1;
+                    if field.typ.starts_with("map<") {
+                        nested_types += 1;
                     }
-                    Label::Repeated => {
-                        write!(gen.buf, "{}", "repeated").unwrap();
-                        gen.write(" ");
+                }
+                ast::MessageItem::Oneof(oneof) => {
+                    gen.path.extend([path::MESSAGE_ONEOF_DECL, oneofs]);
+                    gen.write_leading_comment(
+                        &oneof.comments,
+                        gen.config.blank_lines_between_field_groups,
+                    );
+                    gen.write_indent();
+                    let location = gen.begin();
+                    gen.write("oneof ");
+                    gen.write(&oneof.name);
+                    gen.open_block();
+                    gen.write_options(path::ONEOF_OPTIONS, &oneof.options);
+                    gen.path.truncate(gen.path.len() - 2);
+                    for field in &oneof.fields {
+                        gen.print_at(&[path::MESSAGE_FIELD, fields], field);
+                        fields += 1;
                     }
+                    gen.close_block(location);
+                    oneofs += 1;
+                }
+                ast::MessageItem::Message(msg) => {
+                    gen.print_at(&[path::MESSAGE_NESTED_TYPE, nested_types], msg);
+                    nested_types += 1;
+                }
+                ast::MessageItem::Enum(enum_type) => {
+                    gen.print_at(&[path::MESSAGE_ENUM_TYPE, enums], enum_type);
+                    enums += 1;
+                }
+                ast::MessageItem::Extend(extend) => {
+                    gen.write_extend(path::MESSAGE_EXTENSION, extend, &mut extensions);
+                }
+                ast::MessageItem::Extensions(statement) => {
+                    gen.print_at(&[path::MESSAGE_EXTENSION_RANGE], statement);
+                }
+                ast::MessageItem::Reserved(reserved) => {
+                    let field = match reserved.kind {
+                        ast::ReservedKind::Ranges(_) => path::MESSAGE_RESERVED_RANGE,
+                        ast::ReservedKind::Names(_) => path::MESSAGE_RESERVED_NAME,
+                    };
+                    gen.print_at(&[field], reserved);
                 }
             }
         }
 
-        write_field_type(&mut gen.buf, &self);
-        gen.write(" ");
-        if let Some(ref name) = self.name {
-            gen.write(name);
-        }
-        gen.write(" = ");
-        if let Some(number) = self.number {
-            write!(gen.buf, "{}", number).unwrap();
-        }
-
-        // For extensions, this is the name of the type being extended.  It is
-        // resolved in the same manner as type_name.
-        // TODO: extendee: ::std::option::Option<std::string::String>,
-
-        // For numeric types, contains the original text representation of the value.
-        // For booleans, "true" or "false".
-        // For strings, contains the default text contents (not escaped in any way).
-        // For bytes, contains the C escaped value.  All bytes >= 128 are escaped.
-        // TODO: default_value: ::std::option::Option<std::string::String>,
-
-        // If set, gives the index of a oneof in the containing type's oneof_decl
-        // list.  This field is a member of that oneof.
-        // oneof_index: ::std::option::Option<i32>,
-
-        // JSON name of this field. The value is set by protocol compiler. If the
-        // user has set a "json_name" option on this field, that option's value
-        // will be used. Otherwise, it's deduced from the field's name by converting
-        // it to camelCase.
-        // TODO: json_name: ::std::option::Option<std::string::String>,
+        gen.close_block(location);
+    }
+}
 
-        // TODO: options: ::std::option::Option<FieldOptions>,
+impl Print for ast::Field<'_> {
+    fn print_with_hooks(&self, gen: &mut Generator) {
+        match gen.hooks {
+            Some(hooks) => hooks.field(gen, self),
+            None => self.print(gen),
+        }
+    }
 
-        gen.write(";\n");
+    fn print(&self, gen: &mut Generator) {
+        gen.write_leading_comment(&self.comments, gen.config.blank_lines_between_field_groups);
+        gen.write_row(
+            self.label,
+            &self.typ,
+            &self.name,
+            self.number,
+            &self.options,
+            &self.comments,
+        );
     }
 }
 
-impl ProtobufString for prost_types::EnumDescriptorProto {
-    fn build_protobuf(&self, gen: &mut Generator) {
-        if gen.write_leading_comment().is_none() {
-            gen.write("\n");
+impl Print for ast::Enum<'_> {
+    fn print_with_hooks(&self, gen: &mut Generator) {
+        match gen.hooks {
+            Some(hooks) => hooks.enumeration(gen, self),
+            None => self.print(gen),
         }
+    }
 
+    fn print(&self, gen: &mut Generator) {
+        gen.write_declaration_comment(&self.comments);
         gen.write_indent();
-        gen.write("enum");
-        if let Some(ref name) = self.name {
-            gen.write(" ");
-            gen.write(name);
-        }
+        let location = gen.begin();
+        gen.write("enum ");
+        gen.write(&self.name);
         gen.open_block();
+        gen.write_options(path::ENUM_OPTIONS, &self.options);
 
-        gen.path.push(2);
-        for (i, value) in self.value.iter().enumerate() {
-            gen.path.push(i as i32);
-            value.build_protobuf(gen);
-            gen.path.pop();
+        for (i, value) in self.values.iter().enumerate() {
+            gen.print_at(&[path::ENUM_VALUE, i as i32], value);
+        }
+        for reserved in &self.reserved {
+            let field = match reserved.kind {
+                ast::ReservedKind::Ranges(_) => path::ENUM_RESERVED_RANGE,
+                ast::ReservedKind::Names(_) => path::ENUM_RESERVED_NAME,
+            };
+            gen.print_at(&[field], reserved);
         }
-        gen.path.pop();
 
-        // TODO: options: ::std::option::Option<EnumOptions>,
+        gen.close_block(location);
+    }
+}
 
-        // Range of reserved numeric values. Reserved numeric values may not be used
-        // by enum values in the same enum declaration. Reserved ranges may not
-        // overlap.
-        if self.reserved_name.len() > 0 {
-            gen.write_leading_comment();
-            gen.write_indent();
-            gen.write("reserved ");
-            for (i, range) in self.reserved_range.iter().enumerate() {
-                if i > 0 {
-                    gen.write(", ");
-                }
+impl Print for ast::EnumValue<'_> {
+    fn print_with_hooks(&self, gen: &mut Generator) {
+        match gen.hooks {
+            Some(hooks) => hooks.enum_value(gen, self),
+            None => self.print(gen),
+        }
+    }
 
-                // Inclusive
-                if let Some(ref start) = range.start {
-                    write!(gen.buf, "{}", start).unwrap();
-                }
-                gen.write(" to ");
+    fn print(&self, gen: &mut Generator) {
+        gen.write_leading_comment(&self.comments, gen.config.blank_lines_between_field_groups);
+        gen.write_row(
+            None,
+            "",
+            &self.name,
+            self.number,
+            &self.options,
+            &self.comments,
+        );
+    }
+}
 
-                // Exclusive.
-                if let Some(ref end) = range.end {
-                    write!(gen.buf, "{}", end).unwrap();
-                } else {
-                    gen.write("max");
+// Written at the path of the reserved ranges or names of the enclosing
+// message or enum.
+impl Print for ast::Reserved<'_> {
+    fn print(&self, gen: &mut Generator) {
+        gen.write_leading_comment(&self.comments, gen.config.blank_lines_between_field_groups);
+        gen.write_indent();
+        let location = gen.begin();
+        gen.write("reserved ");
+        match self.kind {
+            ast::ReservedKind::Ranges(ref ranges) => {
+                for (i, range) in ranges.iter().enumerate() {
+                    if i > 0 {
+                        gen.write(", ");
+                    }
+                    write!(gen.buf, "{}", range.start).unwrap();
+                    match range.end {
+                        Some(end) if end == range.start => {}
+                        Some(end) => write!(gen.buf, " to {}", end).unwrap(),
+                        None => gen.write(" to max"),
+                    }
                 }
             }
-        }
-
-        // Reserved enum value names, which may not be reused. A given name may only
-        // be reserved once.
-        if self.reserved_name.len() > 0 {
-            gen.write_leading_comment();
-            gen.write_indent();
-            gen.write("reserved ");
-            for (i, name) in self.reserved_name.iter().enumerate() {
-                if i > 0 {
-                    gen.write(", ");
+            ast::ReservedKind::Names(ref names) => {
+                for (i, name) in names.iter().enumerate() {
+                    if i > 0 {
+                        gen.write(", ");
+                    }
+                    write!(gen.buf, "\"{}\"", name).unwrap();
                 }
-                write!(gen.buf, "\"{}\"", name).unwrap();
             }
-            gen.write(";\n");
         }
-
-        gen.close_block();
+        gen.write(";");
+        gen.end(location);
+        gen.write("\n");
     }
 }
 
-impl ProtobufString for prost_types::EnumValueDescriptorProto {
-    fn build_protobuf(&self, gen: &mut Generator) {
-        gen.write_leading_comment();
-
+// Written at the path of the extension ranges of the enclosing message.
+impl Print for ast::Extensions<'_> {
+    fn print(&self, gen: &mut Generator) {
+        gen.write_leading_comment(&self.comments, gen.config.blank_lines_between_field_groups);
         gen.write_indent();
-        if let Some(ref name) = self.name {
-            gen.write(name);
+        let location = gen.begin();
+        gen.write("extensions ");
+        for (i, range) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                gen.write(", ");
+            }
+            write!(gen.buf, "{}", range.start).unwrap();
+            match range.end {
+                Some(end) if end == range.start => {}
+                Some(end) => write!(gen.buf, " to {}", end).unwrap(),
+                None => gen.write(" to max"),
+            }
         }
-        gen.write(" = ");
-        if let Some(number) = self.number {
-            write!(gen.buf, "{}", number).unwrap();
+        if !self.options.is_empty() {
+            let options = self.options.iter().map(options::doc).collect();
+            gen.render(&Doc::concat(vec![
+                Doc::text(" "),
+                Doc::bracket("[", options, "]"),
+            ]));
         }
-        gen.write(";\n");
+        gen.write(";");
+        gen.end(location);
+        gen.write("\n");
+    }
+}
 
-        // TODO: options: ::std::option::Option<EnumValueOptions>,
+// Written at the path of the option in the options of the enclosing
+// element.
+impl Print for ast::OptionStatement<'_> {
+    fn print(&self, gen: &mut Generator) {
+        gen.write_leading_comment(&self.comments, gen.config.blank_lines_between_field_groups);
+        gen.write_indent();
+        let location = gen.begin();
+        gen.render(&Doc::concat(vec![
+            Doc::text("option "),
+            options::doc(&self.option),
+            Doc::text(";"),
+        ]));
+        let index = location.0;
+        gen.end(location);
+        if let (Some(ref comment), true) = (&self.comments.trailing, gen.config.trailing_comments())
+        {
+            gen.write("  ");
+            let column = gen.buf.len() - gen.buf.rfind('\n').map_or(0, |i| i + 1);
+            let comment = gen.reflow(comment, column + "//".len()).into_owned();
+            for (i, line) in comment.lines().enumerate() {
+                if i > 0 {
+                    gen.buf.push('\n');
+                    gen.buf.extend(std::iter::repeat_n(' ', column));
+                }
+                gen.buf.push_str("//");
+                gen.buf.push_str(line);
+            }
+            if let (Some(spans), Some(index)) = (gen.spans.as_mut(), index) {
+                spans.set_trailing_comment(index, comment);
+            }
+        }
+        gen.write("\n");
     }
 }
 
-impl ProtobufString for prost_types::ServiceDescriptorProto {
-    fn build_protobuf(&self, gen: &mut Generator) {
-        if gen.write_leading_comment().is_none() {
-            gen.write("\n");
+impl Print for ast::Service<'_> {
+    fn print_with_hooks(&self, gen: &mut Generator) {
+        match gen.hooks {
+            Some(hooks) => hooks.service(gen, self),
+            None => self.print(gen),
         }
+    }
 
+    fn print(&self, gen: &mut Generator) {
+        gen.write_declaration_comment(&self.comments);
         gen.write_indent();
-        gen.write("service");
-        if let Some(ref name) = self.name {
-            gen.write(" ");
-            gen.write(name);
-        }
+        let location = gen.begin();
+        gen.write("service ");
+        gen.write(&self.name);
         gen.open_block();
-
-        gen.path.push(2);
-        for (i, method) in self.method.iter().enumerate() {
-            gen.path.push(i as i32);
-            method.build_protobuf(gen);
-            gen.path.pop();
+        gen.write_options(path::SERVICE_OPTIONS, &self.options);
+        for (i, method) in self.methods.iter().enumerate() {
+            gen.print_at(&[path::SERVICE_METHOD, i as i32], method);
         }
-        gen.path.pop();
-
-        // TODO: options: ::std::option::Option<ServiceOptions>,
-
-        gen.close_block();
+        gen.close_block(location);
     }
 }
 
-impl ProtobufString for prost_types::MethodDescriptorProto {
-    fn build_protobuf(&self, gen: &mut Generator) {
-        gen.write_leading_comment();
+impl Print for ast::Method<'_> {
+    fn print_with_hooks(&self, gen: &mut Generator) {
+        match gen.hooks {
+            Some(hooks) => hooks.method(gen, self),
+            None => self.print(gen),
+        }
+    }
 
+    fn print(&self, gen: &mut Generator) {
+        gen.write_leading_comment(&self.comments, gen.config.blank_lines_between_field_groups);
         gen.write_indent();
-        if let Some(ref name) = self.name {
-            gen.write("rpc ");
-            gen.write(name);
-        }
+        let location = gen.begin();
 
-        // Input and output type names.  These are resolved in the same way as
-        // FieldDescriptorProto.type_name, but must refer to a message type.
-        gen.write("(");
-        if let Some(true) = self.client_streaming {
-            gen.write("stream ");
-        }
-        if let Some(ref typ) = self.input_type {
-            gen.write(typ);
-        }
-        gen.write(") returns (");
-        if let Some(true) = self.server_streaming {
-            gen.write("stream ");
-        }
-        if let Some(ref typ) = self.output_type {
-            gen.write(typ);
+        let message_type = |streaming: bool, typ: &str| {
+            let mut text = String::new();
+            if streaming {
+                text.push_str("stream ");
+            }
+            text.push_str(typ);
+            Doc::nest(Doc::concat(vec![Doc::SoftLine, Doc::text(text)]))
+        };
+        let doc = Doc::group(Doc::concat(vec![
+            Doc::text(format!("rpc {}(", self.name)),
+            message_type(self.client_streaming, &self.input_type),
+            Doc::SoftLine,
+            Doc::text(") returns ("),
+            message_type(self.server_streaming, &self.output_type),
+            Doc::SoftLine,
+            Doc::text(")"),
+        ]));
+        gen.render(&doc);
+        if self.options.is_empty() {
+            gen.write(";");
+            gen.end(location);
+            gen.write("\n");
+        } else {
+            gen.open_block();
+            gen.write_options(path::METHOD_OPTIONS, &self.options);
+            gen.close_block(location);
         }
-        gen.write(");\n");
-
-        // TODO: options: ::std::option::Option<MethodOptions>,
     }
 }
 
@@ -617,34 +1191,151 @@ pub fn read_files<P>(protos: &[P], includes: &[P]) -> prost_types::FileDescripto
 where
     P: AsRef<Path>,
 {
-    let tmp = tempfile::Builder::new()
-        .prefix("protobuf-gen")
-        .tempdir()
-        .unwrap();
+    try_read_files(protos, includes).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Like [`read_files`], but returns protoc failures instead of panicking.
+pub fn try_read_files<P>(protos: &[P], includes: &[P]) -> io::Result<prost_types::FileDescriptorSet>
+where
+    P: AsRef<Path>,
+{
+    let tmp = tempfile::Builder::new().prefix("protobuf-gen").tempdir()?;
 
-    let descriptor_set = tmp.path().join("prost-descriptor-set");
+    let protos: Vec<&Path> = protos.iter().map(AsRef::as_ref).collect();
+    let includes: Vec<&Path> = std::iter::once(Path::new("src/"))
+        .chain(includes.iter().map(AsRef::as_ref))
+        .collect();
+    compile(Path::new("protoc"), tmp.path(), &protos, &includes)
+}
 
-    let mut cmd = Command::new("protoc");
+/// Compiles `.proto` sources that only exist in memory.
+///
+/// `sources` maps a virtual path, relative to the import root (e.g.
+/// `"acme/user.proto"`), to the file contents. Every source is compiled, and
+/// they may import each other as well as files found under `includes`. A
+/// virtual file shadows an on-disk file with the same name.
+pub fn read_sources<I, K, V, P>(sources: I, includes: &[P]) -> prost_types::FileDescriptorSet
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<Path>,
+    V: AsRef<str>,
+    P: AsRef<Path>,
+{
+    try_read_sources(sources, includes).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Like [`read_sources`], but returns invalid virtual paths and protoc
+/// failures instead of panicking.
+pub fn try_read_sources<I, K, V, P>(
+    sources: I,
+    includes: &[P],
+) -> io::Result<prost_types::FileDescriptorSet>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<Path>,
+    V: AsRef<str>,
+    P: AsRef<Path>,
+{
+    let tmp = tempfile::Builder::new().prefix("protobuf-gen").tempdir()?;
+
+    let root = tmp.path().join("sources");
+    let mut protos = Vec::new();
+    for (path, source) in sources {
+        let path = path.as_ref();
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            || path.file_name().is_none()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("virtual path must be relative: {}", path.display()),
+            ));
+        }
+
+        let proto = root.join(path);
+        if let Some(parent) = proto.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&proto, source.as_ref())?;
+        protos.push(proto);
+    }
+
+    // The virtual root comes first, so that its files shadow those of the
+    // include paths rather than being reported as shadowed by them.
+    let protos: Vec<&Path> = protos.iter().map(PathBuf::as_path).collect();
+    let includes: Vec<&Path> = [root.as_path(), Path::new("src/")]
+        .iter()
+        .copied()
+        .chain(includes.iter().map(AsRef::as_ref))
+        .collect();
+    compile(Path::new("protoc"), tmp.path(), &protos, &includes)
+}
+
+fn compile(
+    protoc: &Path,
+    tmp: &Path,
+    protos: &[&Path],
+    includes: &[&Path],
+) -> io::Result<prost_types::FileDescriptorSet> {
+    let descriptor_set = tmp.join("prost-descriptor-set");
+
+    let mut cmd = Command::new(protoc);
     cmd.arg("--include_imports")
         .arg("--include_source_info")
         .arg("-o")
-        .arg(&descriptor_set)
-        .arg("-I")
-        .arg("src/");
+        .arg(&descriptor_set);
 
     for include in includes {
-        cmd.arg("-I").arg(include.as_ref());
+        cmd.arg("-I").arg(include);
     }
 
     for proto in protos {
-        cmd.arg(proto.as_ref());
+        cmd.arg(proto);
     }
 
-    let output = cmd.output().unwrap();
+    let output = cmd.output()?;
     if !output.status.success() {
-        panic!("protoc failed: {}", String::from_utf8_lossy(&output.stderr))
+        return Err(io::Error::other(format!(
+            "protoc failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
     }
 
-    let buf = fs::read(descriptor_set).unwrap();
-    prost_types::FileDescriptorSet::decode(&*buf).unwrap()
+    let buf = fs::read(descriptor_set)?;
+    custom_options::decode_set(&buf).map_err(invalid_data)
+}
+
+/// Reads a `FileDescriptorSet` as produced by `protoc -o` or `buf build`.
+///
+/// Gzip-compressed files are detected and decompressed transparently.
+pub fn read_descriptor_set<P>(path: P) -> io::Result<prost_types::FileDescriptorSet>
+where
+    P: AsRef<Path>,
+{
+    decode_descriptor_set(&fs::read(path)?)
+}
+
+/// Decodes a `FileDescriptorSet`, gunzipping `bytes` first if needed.
+///
+/// Buf images are accepted as well, since `Image` is wire compatible with
+/// `FileDescriptorSet`; use [`image::decode_image`] to keep the per-file
+/// metadata.
+pub fn decode_descriptor_set(bytes: &[u8]) -> io::Result<prost_types::FileDescriptorSet> {
+    let bytes = gunzip(bytes)?;
+    custom_options::decode_set(&bytes).map_err(invalid_data)
+}
+
+fn gunzip(bytes: &[u8]) -> io::Result<std::borrow::Cow<'_, [u8]>> {
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut buf = Vec::with_capacity(bytes.len() * 4);
+        flate2::read::GzDecoder::new(bytes).read_to_end(&mut buf)?;
+        Ok(buf.into())
+    } else {
+        Ok(bytes.into())
+    }
+}
+
+fn invalid_data(err: prost::DecodeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
 }
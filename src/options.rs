@@ -0,0 +1,478 @@
+// Compact options, the `[default = 1, deprecated = true]` after a field or
+// enum value, and the `option` statements in the body of a file or
+// declaration.
+//
+// Only the options prost knows about can be rendered, along with custom
+// options in `uninterpreted_option` form: those of descriptors built by
+// hand, and those that custom_options.rs recovers while decoding, which are
+// otherwise extensions prost drops. Options without a name stand for those
+// that could not be recovered, and are left out.
+
+use crate::ast::CompactOption;
+use crate::pretty::Doc;
+use prost_types::field_descriptor_proto::Type;
+use prost_types::{
+    EnumOptions, ExtensionRangeOptions, FieldDescriptorProto, FileOptions, MessageOptions,
+    MethodOptions, OneofOptions, ServiceOptions, UninterpretedOption,
+};
+use std::borrow::Cow;
+
+pub(crate) fn field_options(field: &FieldDescriptorProto) -> Vec<CompactOption<'_>> {
+    let mut docs = Vec::new();
+
+    // For numeric types, contains the original text representation of the value.
+    // For booleans, "true" or "false".
+    // For strings, contains the default text contents (not escaped in any way).
+    // For bytes, contains the C escaped value.  All bytes >= 128 are escaped.
+    if let Some(ref default) = field.default_value {
+        let value = match field.r#type() {
+            Type::String => quote(&c_escape(default.as_bytes())).into(),
+            Type::Bytes => quote(default).into(),
+            _ => Cow::Borrowed(&**default),
+        };
+        docs.push(option("default", value));
+    }
+
+    // protoc fills in json_name for every field, so only one that differs
+    // from the name it derives was set explicitly.
+    if let (Some(ref name), Some(ref json_name)) = (&field.name, &field.json_name) {
+        if !is_default_json_name(name, json_name) {
+            docs.push(option(
+                "json_name",
+                quote(&c_escape(json_name.as_bytes())).into(),
+            ));
+        }
+    }
+
+    if let Some(ref options) = field.options {
+        if options.ctype.is_some() {
+            docs.push(option("ctype", options.ctype().as_str_name().into()));
+        }
+        if let Some(packed) = options.packed {
+            docs.push(option("packed", bool_value(packed)));
+        }
+        if let Some(deprecated) = options.deprecated {
+            docs.push(option("deprecated", bool_value(deprecated)));
+        }
+        if let Some(lazy) = options.lazy {
+            docs.push(option("lazy", bool_value(lazy)));
+        }
+        if options.jstype.is_some() {
+            docs.push(option("jstype", options.jstype().as_str_name().into()));
+        }
+        if let Some(weak) = options.weak {
+            docs.push(option("weak", bool_value(weak)));
+        }
+        docs.extend(named(&options.uninterpreted_option).map(uninterpreted));
+    }
+
+    docs
+}
+
+pub(crate) fn enum_value_options(
+    value: &prost_types::EnumValueDescriptorProto,
+) -> Vec<CompactOption<'_>> {
+    let mut docs = Vec::new();
+    if let Some(ref options) = value.options {
+        if let Some(deprecated) = options.deprecated {
+            docs.push(option("deprecated", bool_value(deprecated)));
+        }
+        docs.extend(named(&options.uninterpreted_option).map(uninterpreted));
+    }
+    docs
+}
+
+// The options of a file, message, oneof, enum, service or method, each
+// along with its field number in the options message, under which protoc
+// records the location of the statement. Custom options have none.
+pub(crate) type Statements<'a> = Vec<(Option<i32>, CompactOption<'a>)>;
+
+// `java_generate_equals_and_hash` is deprecated, but files may still set it.
+#[allow(deprecated)]
+pub(crate) fn file_options(options: &FileOptions) -> Statements<'_> {
+    let mut statements = Vec::new();
+    let mut string = |number, name, value: &Option<String>| {
+        if let Some(ref value) = value {
+            statements.push((Some(number), string_option(name, value)));
+        }
+    };
+    string(1, "java_package", &options.java_package);
+    string(8, "java_outer_classname", &options.java_outer_classname);
+    string(11, "go_package", &options.go_package);
+    string(36, "objc_class_prefix", &options.objc_class_prefix);
+    string(37, "csharp_namespace", &options.csharp_namespace);
+    string(39, "swift_prefix", &options.swift_prefix);
+    string(40, "php_class_prefix", &options.php_class_prefix);
+    string(41, "php_namespace", &options.php_namespace);
+    string(
+        44,
+        "php_metadata_namespace",
+        &options.php_metadata_namespace,
+    );
+    string(45, "ruby_package", &options.ruby_package);
+
+    let mut flag = |number, name, value: Option<bool>| {
+        if let Some(value) = value {
+            statements.push((Some(number), option(name, bool_value(value))));
+        }
+    };
+    flag(10, "java_multiple_files", options.java_multiple_files);
+    flag(
+        20,
+        "java_generate_equals_and_hash",
+        options.java_generate_equals_and_hash,
+    );
+    flag(27, "java_string_check_utf8", options.java_string_check_utf8);
+    flag(16, "cc_generic_services", options.cc_generic_services);
+    flag(17, "java_generic_services", options.java_generic_services);
+    flag(18, "py_generic_services", options.py_generic_services);
+    flag(42, "php_generic_services", options.php_generic_services);
+    flag(23, "deprecated", options.deprecated);
+    flag(31, "cc_enable_arenas", options.cc_enable_arenas);
+
+    if options.optimize_for.is_some() {
+        let value = options.optimize_for().as_str_name();
+        statements.push((Some(9), option("optimize_for", value.into())));
+    }
+    statements.extend(custom(&options.uninterpreted_option));
+    statements
+}
+
+pub(crate) fn message_options(options: &MessageOptions) -> Statements<'_> {
+    let mut statements = Vec::new();
+    let mut flag = |number, name, value: Option<bool>| {
+        if let Some(value) = value {
+            statements.push((Some(number), option(name, bool_value(value))));
+        }
+    };
+    flag(
+        1,
+        "message_set_wire_format",
+        options.message_set_wire_format,
+    );
+    flag(
+        2,
+        "no_standard_descriptor_accessor",
+        options.no_standard_descriptor_accessor,
+    );
+    flag(3, "deprecated", options.deprecated);
+    // `map_entry` is set by protoc on the entry types of map fields, which
+    // are written as `map<K, V>` rather than as messages.
+    statements.extend(custom(&options.uninterpreted_option));
+    statements
+}
+
+pub(crate) fn oneof_options(options: &OneofOptions) -> Statements<'_> {
+    custom(&options.uninterpreted_option).collect()
+}
+
+pub(crate) fn enum_options(options: &EnumOptions) -> Statements<'_> {
+    let mut statements = Vec::new();
+    if let Some(allow_alias) = options.allow_alias {
+        statements.push((Some(2), option("allow_alias", bool_value(allow_alias))));
+    }
+    if let Some(deprecated) = options.deprecated {
+        statements.push((Some(3), option("deprecated", bool_value(deprecated))));
+    }
+    statements.extend(custom(&options.uninterpreted_option));
+    statements
+}
+
+pub(crate) fn service_options(options: &ServiceOptions) -> Statements<'_> {
+    let mut statements = Vec::new();
+    if let Some(deprecated) = options.deprecated {
+        statements.push((Some(33), option("deprecated", bool_value(deprecated))));
+    }
+    statements.extend(custom(&options.uninterpreted_option));
+    statements
+}
+
+pub(crate) fn method_options(options: &MethodOptions) -> Statements<'_> {
+    let mut statements = Vec::new();
+    if let Some(deprecated) = options.deprecated {
+        statements.push((Some(33), option("deprecated", bool_value(deprecated))));
+    }
+    if options.idempotency_level.is_some() {
+        let value = options.idempotency_level().as_str_name();
+        statements.push((Some(34), option("idempotency_level", value.into())));
+    }
+    statements.extend(custom(&options.uninterpreted_option));
+    statements
+}
+
+// The options in the brackets after an `extensions` range.
+pub(crate) fn extension_range_options(options: &ExtensionRangeOptions) -> Vec<CompactOption<'_>> {
+    named(&options.uninterpreted_option)
+        .map(uninterpreted)
+        .collect()
+}
+
+fn custom(
+    options: &[UninterpretedOption],
+) -> impl Iterator<Item = (Option<i32>, CompactOption<'_>)> {
+    named(options).map(|option| (None, uninterpreted(option)))
+}
+
+fn named(options: &[UninterpretedOption]) -> impl Iterator<Item = &UninterpretedOption> {
+    options.iter().filter(|option| !option.name.is_empty())
+}
+
+fn string_option<'a>(name: &'static str, value: &str) -> CompactOption<'a> {
+    option(name, quote(&c_escape(value.as_bytes())).into())
+}
+
+fn option<'a>(name: &'static str, value: Cow<'a, str>) -> CompactOption<'a> {
+    CompactOption {
+        name: name.into(),
+        value,
+    }
+}
+
+fn bool_value(value: bool) -> Cow<'static, str> {
+    if value { "true" } else { "false" }.into()
+}
+
+// The name of an option as written, with extensions in parentheses, e.g.
+// `(acme.sensitive)`.
+pub(crate) fn option_name(option: &UninterpretedOption) -> String {
+    let mut name = String::new();
+    for (i, part) in option.name.iter().enumerate() {
+        if i > 0 {
+            name.push('.');
+        }
+        if part.is_extension {
+            name.push('(');
+            name.push_str(&part.name_part);
+            name.push(')');
+        } else {
+            name.push_str(&part.name_part);
+        }
+    }
+    name
+}
+
+fn uninterpreted(option: &UninterpretedOption) -> CompactOption<'_> {
+    let name = option_name(option);
+    let value = if let Some(ref identifier) = option.identifier_value {
+        Cow::Borrowed(&**identifier)
+    } else if let Some(value) = option.positive_int_value {
+        value.to_string().into()
+    } else if let Some(value) = option.negative_int_value {
+        value.to_string().into()
+    } else if let Some(value) = option.double_value {
+        format!("{:?}", value).into()
+    } else if let Some(ref value) = option.string_value {
+        quote(&c_escape(value)).into()
+    } else if let Some(ref aggregate) = option.aggregate_value {
+        format!("{{{}}}", aggregate).into()
+    } else {
+        "".into()
+    };
+
+    CompactOption {
+        name: name.into(),
+        value,
+    }
+}
+
+pub(crate) fn doc(option: &CompactOption) -> Doc {
+    Doc::concat(vec![
+        Doc::text(option.name.clone().into_owned()),
+        Doc::text(" = "),
+        value_doc(&option.value),
+    ])
+}
+
+// An aggregate value is the text format of a message in braces. It is split
+// into its fields, so that it can be broken one field per line. Anything
+// that doesn't parse is kept as it is.
+fn value_doc(text: &str) -> Doc {
+    let tokens = tokenize(text);
+    if tokens.len() > 1 {
+        let mut pos = 0;
+        let doc = value(&tokens, &mut pos);
+        if pos == tokens.len() {
+            return doc;
+        }
+    }
+    Doc::text(text.to_owned())
+}
+
+fn message(tokens: &[&str], pos: &mut usize, open: &'static str, close: &'static str) -> Doc {
+    let mut fields = Vec::new();
+    while *pos < tokens.len() && !is_close(tokens[*pos]) {
+        if tokens[*pos] == "," || tokens[*pos] == ";" {
+            *pos += 1;
+            continue;
+        }
+
+        let mut field = vec![Doc::text(tokens[*pos].to_owned())];
+        *pos += 1;
+        if tokens.get(*pos) == Some(&":") {
+            *pos += 1;
+        }
+        field.push(Doc::text(": "));
+        field.push(value(tokens, pos));
+        fields.push(Doc::concat(field));
+    }
+    Doc::bracket(open, fields, close)
+}
+
+fn value(tokens: &[&str], pos: &mut usize) -> Doc {
+    let token = match tokens.get(*pos) {
+        Some(token) => *token,
+        None => return Doc::text(""),
+    };
+    *pos += 1;
+
+    match token {
+        "{" | "<" => {
+            let doc = message(tokens, pos, "{", "}");
+            *pos += 1;
+            doc
+        }
+        "[" => {
+            let mut items = Vec::new();
+            while *pos < tokens.len() && tokens[*pos] != "]" {
+                if tokens[*pos] == "," {
+                    *pos += 1;
+                } else {
+                    items.push(value(tokens, pos));
+                }
+            }
+            *pos += 1;
+            Doc::bracket("[", items, "]")
+        }
+        "-" => {
+            let number = value(tokens, pos);
+            Doc::concat(vec![Doc::text("-"), number])
+        }
+        _ => Doc::text(token.to_owned()),
+    }
+}
+
+fn is_close(token: &str) -> bool {
+    token == "}" || token == ">"
+}
+
+// Splits text format into identifiers, numbers, quoted strings and
+// punctuation, dropping whitespace and comments.
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i = (i + 1).min(bytes.len());
+            }
+            b if b.is_ascii_alphanumeric() || b == b'_' || b == b'.' => {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric() || b"_.+".contains(&bytes[i]))
+                {
+                    i += 1;
+                }
+            }
+            b'[' if tokens.last() != Some(&":") => {
+                // An extension or Any field name, e.g. `[foo.bar]`, rather
+                // than a list value.
+                while i < bytes.len() && bytes[i] != b']' {
+                    i += 1;
+                }
+                i = (i + 1).min(bytes.len());
+            }
+            _ => i += 1,
+        }
+        tokens.push(&text[start..i]);
+    }
+    tokens
+}
+
+fn quote(escaped: &str) -> String {
+    let mut quoted = String::with_capacity(escaped.len() + 2);
+    quoted.push('"');
+    quoted.push_str(escaped);
+    quoted.push('"');
+    quoted
+}
+
+// Escapes a string or bytes literal like protoc's CEscape, except that
+// valid UTF-8 is kept as it is rather than escaped byte by byte.
+pub(crate) fn c_escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            for c in text.chars() {
+                if c.is_ascii() {
+                    escape_byte(&mut escaped, c as u8);
+                } else {
+                    escaped.push(c);
+                }
+            }
+        }
+        Err(_) => return c_escape_bytes(bytes),
+    }
+    escaped
+}
+
+// Escapes bytes like protoc's CEscape, which is how it keeps the defaults
+// of bytes fields.
+pub(crate) fn c_escape_bytes(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    bytes.iter().for_each(|&b| escape_byte(&mut escaped, b));
+    escaped
+}
+
+fn escape_byte(escaped: &mut String, b: u8) {
+    match b {
+        b'\n' => escaped.push_str("\\n"),
+        b'\r' => escaped.push_str("\\r"),
+        b'\t' => escaped.push_str("\\t"),
+        b'"' => escaped.push_str("\\\""),
+        b'\'' => escaped.push_str("\\'"),
+        b'\\' => escaped.push_str("\\\\"),
+        0x20..=0x7e => escaped.push(b as char),
+        _ => escaped.push_str(&format!("\\{:03o}", b)),
+    }
+}
+
+// Whether `json_name` is the one protoc derives from a field name: with
+// underscores dropped and the letter after each one capitalized.
+fn is_default_json_name(name: &str, json_name: &str) -> bool {
+    let mut expected = json_name.chars();
+    let mut capitalize_next = false;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize_next = true;
+            continue;
+        }
+        let c = if capitalize_next {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        };
+        capitalize_next = false;
+        if expected.next() != Some(c) {
+            return false;
+        }
+    }
+    expected.next().is_none()
+}
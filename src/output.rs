@@ -0,0 +1,288 @@
+//! Rendering a whole `FileDescriptorSet` into a directory tree.
+//!
+//! [`render_set`] writes each file under an output root at its
+//! `FileDescriptorProto.name`, rendering the files on several threads:
+//!
+//! ```no_run
+//! use protobuf_gen::output::{render_set, RenderSetOptions};
+//!
+//! let set = protobuf_gen::read_descriptor_set("googleapis.binpb")?;
+//! let report = render_set(
+//!     &set,
+//!     "formatted",
+//!     &RenderSetOptions::new()
+//!         .skip_dependencies(true)
+//!         .only_changed(true)
+//!         .remove_stale(true),
+//! )?;
+//! println!("{} written, {} removed", report.written.len(), report.removed.len());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{GeneratorConfig, ProtobufString};
+use prost_types::{FileDescriptorProto, FileDescriptorSet};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderSetOptions {
+    format: GeneratorConfig,
+    files: Option<Vec<String>>,
+    skip_dependencies: bool,
+    only_changed: bool,
+    remove_stale: bool,
+    dry_run: bool,
+    threads: usize,
+}
+
+/// The outputs [`render_set`] wrote, left alone and removed, in the order of
+/// the files in the set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderSetReport {
+    pub written: Vec<PathBuf>,
+    /// Outputs that already had the rendered content, with `only_changed`.
+    pub unchanged: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+impl RenderSetOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn format(mut self, format: GeneratorConfig) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Render only the files with these names instead of the whole set,
+    /// e.g. those of `Image::files_to_generate`.
+    pub fn files<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.files = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Leave out the files that another file in the set imports, such as
+    /// those protoc adds with `--include_imports`. Files selected with
+    /// [`files`](Self::files) are always rendered.
+    pub fn skip_dependencies(mut self, skip: bool) -> Self {
+        self.skip_dependencies = skip;
+        self
+    }
+
+    /// Don't rewrite outputs that already have the rendered content, so
+    /// that their modification times are kept.
+    pub fn only_changed(mut self, only_changed: bool) -> Self {
+        self.only_changed = only_changed;
+        self
+    }
+
+    /// Remove the `.proto` files under the output root that were not
+    /// rendered, and the directories this leaves empty.
+    pub fn remove_stale(mut self, remove_stale: bool) -> Self {
+        self.remove_stale = remove_stale;
+        self
+    }
+
+    /// Report what would be written and removed without touching any file.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// The number of threads to render on; `0`, the default, uses one per
+    /// available CPU.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+}
+
+/// Renders the files of `set` in parallel, writing each to
+/// `out_dir/<name>`, where `name` must be a relative path.
+pub fn render_set<P>(
+    set: &FileDescriptorSet,
+    out_dir: P,
+    options: &RenderSetOptions,
+) -> io::Result<RenderSetReport>
+where
+    P: AsRef<Path>,
+{
+    let out_dir = out_dir.as_ref();
+    let files = select(set, options)?;
+    let outputs = files
+        .iter()
+        .map(|file| output_path(out_dir, file.name()))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let threads = match options.threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, io::Result<bool>)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(files.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= files.len() {
+                            return results;
+                        }
+                        results.push((i, write_file(files[i], &outputs[i], options)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|&(i, _)| i);
+
+    let mut report = RenderSetReport::default();
+    for (i, result) in results {
+        let path = outputs[i].clone();
+        match result {
+            Ok(true) => report.written.push(path),
+            Ok(false) => report.unchanged.push(path),
+            Err(err) => return Err(annotate(&path, err)),
+        }
+    }
+
+    if options.remove_stale && out_dir.is_dir() {
+        let rendered: HashSet<&Path> = outputs.iter().map(PathBuf::as_path).collect();
+        remove_stale(out_dir, &rendered, options.dry_run, &mut report.removed)?;
+    }
+    Ok(report)
+}
+
+// The files to render, in the order of the set.
+fn select<'a>(
+    set: &'a FileDescriptorSet,
+    options: &RenderSetOptions,
+) -> io::Result<Vec<&'a FileDescriptorProto>> {
+    if let Some(ref names) = options.files {
+        for name in names {
+            if !set.file.iter().any(|f| f.name() == name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: file not found in descriptor set", name),
+                ));
+            }
+        }
+        return Ok(set
+            .file
+            .iter()
+            .filter(|f| names.iter().any(|name| name == f.name()))
+            .collect());
+    }
+
+    let imported: HashSet<&str> = match options.skip_dependencies {
+        true => set
+            .file
+            .iter()
+            .flat_map(|f| f.dependency.iter().map(String::as_str))
+            .collect(),
+        false => HashSet::new(),
+    };
+    Ok(set
+        .file
+        .iter()
+        .filter(|f| !imported.contains(f.name()))
+        .collect())
+}
+
+fn output_path(out_dir: &Path, name: &str) -> io::Result<PathBuf> {
+    let relative = Path::new(name);
+    if name.is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?}: file name must be a relative path", name),
+        ));
+    }
+    Ok(out_dir.join(relative))
+}
+
+// Returns whether the output was (or would be) written.
+fn write_file(
+    file: &FileDescriptorProto,
+    path: &Path,
+    options: &RenderSetOptions,
+) -> io::Result<bool> {
+    let content = file.to_protobuf_with(file, &options.format);
+    if options.only_changed {
+        match fs::read(path) {
+            Ok(existing) if existing == content.as_bytes() => return Ok(false),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    if !options.dry_run {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+    }
+    Ok(true)
+}
+
+// Removes the `.proto` files under `dir` that are not in `rendered`,
+// returning whether `dir` is left empty.
+fn remove_stale(
+    dir: &Path,
+    rendered: &HashSet<&Path>,
+    dry_run: bool,
+    removed: &mut Vec<PathBuf>,
+) -> io::Result<bool> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(|err| annotate(dir, err))?;
+    entries.sort();
+
+    let mut empty = true;
+    for path in entries {
+        let file_type = fs::symlink_metadata(&path)
+            .map_err(|err| annotate(&path, err))?
+            .file_type();
+        if file_type.is_dir() {
+            if remove_stale(&path, rendered, dry_run, removed)? && !dry_run {
+                fs::remove_dir(&path).map_err(|err| annotate(&path, err))?;
+            } else {
+                empty = false;
+            }
+        } else if path.extension().is_some_and(|e| e == "proto")
+            && !rendered.contains(path.as_path())
+        {
+            if !dry_run {
+                fs::remove_file(&path).map_err(|err| annotate(&path, err))?;
+            }
+            removed.push(path);
+        } else {
+            empty = false;
+        }
+    }
+    Ok(empty)
+}
+
+fn annotate(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
@@ -0,0 +1,520 @@
+//! Typed `SourceCodeInfo` paths.
+//!
+//! A location in `SourceCodeInfo` identifies its element by a path of field
+//! numbers and indexes through the descriptors, e.g. `[4, 0, 2, 1]` for the
+//! second field of the first message. [`Path`] builds and reads such paths
+//! by element kind instead:
+//!
+//! ```
+//! use protobuf_gen::path::{Kind, Path};
+//!
+//! let path = Path::file().message(0).field(1);
+//! assert_eq!(&[4, 0, 2, 1], path.as_slice());
+//! assert_eq!(Some(path.clone()), Path::parse(&[4, 0, 2, 1]));
+//! assert_eq!(Kind::Field, Path::parse(&[4, 0, 2, 1]).unwrap().kind());
+//! assert_eq!("message[0].field[1]", Path::file().message(0).field(1).to_string());
+//! assert_eq!(path, "message[0].field[1]".parse().unwrap());
+//! ```
+//!
+//! [`Path::find`] looks up the path of a definition by its fully-qualified
+//! name instead. Across the files of a set,
+//! [`DescriptorPool::get`](crate::pool::DescriptorPool::get) gives the same
+//! path as [`Definition::path`](crate::pool::Definition::path).
+
+use prost_types::source_code_info::Location;
+use prost_types::{DescriptorProto, EnumDescriptorProto, FileDescriptorProto};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+// Field numbers of the elements in the descriptors that contain them, as
+// they appear in paths.
+pub const FILE_PACKAGE: i32 = 2;
+pub const FILE_DEPENDENCY: i32 = 3;
+pub const FILE_MESSAGE_TYPE: i32 = 4;
+pub const FILE_ENUM_TYPE: i32 = 5;
+pub const FILE_SERVICE: i32 = 6;
+pub const FILE_EXTENSION: i32 = 7;
+pub const FILE_OPTIONS: i32 = 8;
+pub const FILE_PUBLIC_DEPENDENCY: i32 = 10;
+pub const FILE_WEAK_DEPENDENCY: i32 = 11;
+pub const FILE_SYNTAX: i32 = 12;
+pub const MESSAGE_FIELD: i32 = 2;
+pub const MESSAGE_NESTED_TYPE: i32 = 3;
+pub const MESSAGE_ENUM_TYPE: i32 = 4;
+pub const MESSAGE_EXTENSION_RANGE: i32 = 5;
+pub const MESSAGE_EXTENSION: i32 = 6;
+pub const MESSAGE_OPTIONS: i32 = 7;
+pub const MESSAGE_ONEOF_DECL: i32 = 8;
+pub const MESSAGE_RESERVED_RANGE: i32 = 9;
+pub const MESSAGE_RESERVED_NAME: i32 = 10;
+pub const FIELD_OPTIONS: i32 = 8;
+pub const EXTENSION_RANGE_OPTIONS: i32 = 3;
+pub const ONEOF_OPTIONS: i32 = 2;
+pub const ENUM_VALUE: i32 = 2;
+pub const ENUM_OPTIONS: i32 = 3;
+pub const ENUM_RESERVED_RANGE: i32 = 4;
+pub const ENUM_RESERVED_NAME: i32 = 5;
+pub const ENUM_VALUE_OPTIONS: i32 = 3;
+pub const SERVICE_METHOD: i32 = 2;
+pub const SERVICE_OPTIONS: i32 = 3;
+pub const METHOD_OPTIONS: i32 = 4;
+// Of every options message.
+pub const UNINTERPRETED_OPTION: i32 = 999;
+
+/// The path of an element of a file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Path {
+    numbers: Vec<i32>,
+    kind: Kind,
+}
+
+/// The kind of element a [`Path`] points at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Kind {
+    File,
+    Syntax,
+    Package,
+    Import,
+    Message,
+    Field,
+    /// An extension, declared in a file or a message.
+    Extension,
+    Oneof,
+    Enum,
+    EnumValue,
+    Service,
+    Method,
+    /// All reserved ranges of a message or enum, i.e. the `reserved`
+    /// statement for numbers.
+    ReservedRanges,
+    ReservedRange,
+    /// All reserved names of a message or enum.
+    ReservedNames,
+    ReservedName,
+}
+
+impl Path {
+    /// The path of the file itself, which is empty.
+    pub fn file() -> Path {
+        Path {
+            numbers: Vec::new(),
+            kind: Kind::File,
+        }
+    }
+
+    /// Reads a path of field numbers and indexes, returning `None` if it
+    /// does not point at an element, e.g. because it points at the name of
+    /// one.
+    pub fn parse(numbers: &[i32]) -> Option<Path> {
+        let mut kind = Kind::File;
+        let mut rest = numbers;
+        while let Some((&number, tail)) = rest.split_first() {
+            let (next, indexed) = child(kind, number)?;
+            rest = tail;
+            if indexed {
+                let (&index, tail) = rest.split_first()?;
+                if index < 0 {
+                    return None;
+                }
+                rest = tail;
+            }
+            kind = next;
+        }
+        Some(Path {
+            numbers: numbers.to_vec(),
+            kind,
+        })
+    }
+
+    /// The path of the message, field, oneof, enum, enum value, service,
+    /// method or extension of `file` with the fully-qualified `name`, such
+    /// as `acme.User.name`, with or without a leading dot. As in protoc,
+    /// enum values are named in the scope of their enum, e.g.
+    /// `acme.STATUS_ACTIVE`. The file's package, and each package it is
+    /// in, is at [`Path::file`].
+    pub fn find(file: &FileDescriptorProto, name: &str) -> Option<Path> {
+        let name = name.strip_prefix('.').unwrap_or(name);
+        let name = match file.package() {
+            "" => name,
+            package
+                if package == name
+                    || package
+                        .strip_prefix(name)
+                        .is_some_and(|rest| rest.starts_with('.')) =>
+            {
+                return Some(Path::file())
+            }
+            package => name.strip_prefix(package)?.strip_prefix('.')?,
+        };
+        let path = Path::file();
+        let (first, rest) = split_name(name);
+
+        if let Some(i) = file.message_type.iter().position(|m| m.name() == first) {
+            let path = path.message(i as i32);
+            return match rest {
+                None => Some(path),
+                Some(rest) => find_in_message(&file.message_type[i], path, rest),
+            };
+        }
+        if let Some(i) = file.service.iter().position(|s| s.name() == first) {
+            let path = path.service(i as i32);
+            let service = &file.service[i];
+            return match rest {
+                None => Some(path),
+                Some(rest) => service
+                    .method
+                    .iter()
+                    .position(|method| method.name() == rest)
+                    .map(|j| path.method(j as i32)),
+            };
+        }
+        if rest.is_some() {
+            return None;
+        }
+        if let Some(i) = file.extension.iter().position(|e| e.name() == first) {
+            return Some(path.extension(i as i32));
+        }
+        find_enum(&file.enum_type, path, first)
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn as_slice(&self) -> &[i32] {
+        &self.numbers
+    }
+
+    /// The index of the element among its siblings of the same kind, e.g.
+    /// `1` for `Path::file().message(0).field(1)`.
+    pub fn index(&self) -> Option<i32> {
+        match self.kind {
+            Kind::File
+            | Kind::Syntax
+            | Kind::Package
+            | Kind::ReservedRanges
+            | Kind::ReservedNames => None,
+            _ => self.numbers.last().copied(),
+        }
+    }
+
+    /// The path of the element containing this one.
+    pub fn parent(&self) -> Option<Path> {
+        let len = match self.kind {
+            Kind::File => return None,
+            Kind::Syntax | Kind::Package | Kind::ReservedRanges | Kind::ReservedNames => 1,
+            Kind::ReservedRange | Kind::ReservedName => 1,
+            _ => 2,
+        };
+        Path::parse(&self.numbers[..self.numbers.len() - len])
+    }
+
+    /// The location of the element in `file`, if its `SourceCodeInfo` has
+    /// one. For an element protoc reports more than once, such as one of
+    /// several `reserved` statements, this is the first.
+    pub fn location<'a>(&self, file: &'a FileDescriptorProto) -> Option<&'a Location> {
+        file.source_code_info
+            .as_ref()?
+            .location
+            .iter()
+            .find(|location| location.path == self.numbers)
+    }
+
+    pub fn syntax(self) -> Path {
+        self.push(Kind::Syntax, &[FILE_SYNTAX])
+    }
+
+    pub fn package(self) -> Path {
+        self.push(Kind::Package, &[FILE_PACKAGE])
+    }
+
+    pub fn import(self, index: i32) -> Path {
+        self.push(Kind::Import, &[FILE_DEPENDENCY, index])
+    }
+
+    /// A message of the file, or a nested message of a message.
+    pub fn message(self, index: i32) -> Path {
+        let number = match self.kind {
+            Kind::Message => MESSAGE_NESTED_TYPE,
+            _ => FILE_MESSAGE_TYPE,
+        };
+        self.push(Kind::Message, &[number, index])
+    }
+
+    /// A field of a message, including those in a oneof, which are indexed
+    /// among all fields of the message.
+    pub fn field(self, index: i32) -> Path {
+        self.push(Kind::Field, &[MESSAGE_FIELD, index])
+    }
+
+    /// An extension of the file, or one declared in a message.
+    pub fn extension(self, index: i32) -> Path {
+        let number = match self.kind {
+            Kind::Message => MESSAGE_EXTENSION,
+            _ => FILE_EXTENSION,
+        };
+        self.push(Kind::Extension, &[number, index])
+    }
+
+    pub fn oneof(self, index: i32) -> Path {
+        self.push(Kind::Oneof, &[MESSAGE_ONEOF_DECL, index])
+    }
+
+    /// An enum of the file, or a nested enum of a message.
+    pub fn enumeration(self, index: i32) -> Path {
+        let number = match self.kind {
+            Kind::Message => MESSAGE_ENUM_TYPE,
+            _ => FILE_ENUM_TYPE,
+        };
+        self.push(Kind::Enum, &[number, index])
+    }
+
+    pub fn value(self, index: i32) -> Path {
+        self.push(Kind::EnumValue, &[ENUM_VALUE, index])
+    }
+
+    pub fn service(self, index: i32) -> Path {
+        self.push(Kind::Service, &[FILE_SERVICE, index])
+    }
+
+    pub fn method(self, index: i32) -> Path {
+        self.push(Kind::Method, &[SERVICE_METHOD, index])
+    }
+
+    pub fn reserved_ranges(self) -> Path {
+        let number = match self.kind {
+            Kind::Enum => ENUM_RESERVED_RANGE,
+            _ => MESSAGE_RESERVED_RANGE,
+        };
+        self.push(Kind::ReservedRanges, &[number])
+    }
+
+    /// A range of a `reserved` statement of a message or enum, or of the
+    /// statement at [`reserved_ranges`](Self::reserved_ranges).
+    pub fn reserved_range(self, index: i32) -> Path {
+        match self.kind {
+            Kind::ReservedRanges => self.push(Kind::ReservedRange, &[index]),
+            _ => self.reserved_ranges().push(Kind::ReservedRange, &[index]),
+        }
+    }
+
+    pub fn reserved_names(self) -> Path {
+        let number = match self.kind {
+            Kind::Enum => ENUM_RESERVED_NAME,
+            _ => MESSAGE_RESERVED_NAME,
+        };
+        self.push(Kind::ReservedNames, &[number])
+    }
+
+    pub fn reserved_name(self, index: i32) -> Path {
+        match self.kind {
+            Kind::ReservedNames => self.push(Kind::ReservedName, &[index]),
+            _ => self.reserved_names().push(Kind::ReservedName, &[index]),
+        }
+    }
+
+    // Appends a child of `kind`, which must be one this element can contain.
+    fn push(mut self, kind: Kind, numbers: &[i32]) -> Path {
+        match child(self.kind, numbers[0]) {
+            Some((child, _)) if child == kind => {}
+            _ => panic!("{:?} cannot contain {:?}", self.kind, kind),
+        }
+        assert!(
+            numbers.len() < 2 || numbers[1] >= 0,
+            "negative index in path"
+        );
+        self.numbers.extend_from_slice(numbers);
+        self.kind = kind;
+        self
+    }
+}
+
+// The kind of the element at field `number` of a `parent` element, and
+// whether an index follows the number in paths.
+fn child(parent: Kind, number: i32) -> Option<(Kind, bool)> {
+    Some(match (parent, number) {
+        (Kind::File, FILE_SYNTAX) => (Kind::Syntax, false),
+        (Kind::File, FILE_PACKAGE) => (Kind::Package, false),
+        (Kind::File, FILE_DEPENDENCY) => (Kind::Import, true),
+        (Kind::File, FILE_MESSAGE_TYPE) => (Kind::Message, true),
+        (Kind::File, FILE_ENUM_TYPE) => (Kind::Enum, true),
+        (Kind::File, FILE_SERVICE) => (Kind::Service, true),
+        (Kind::File, FILE_EXTENSION) => (Kind::Extension, true),
+        (Kind::Message, MESSAGE_FIELD) => (Kind::Field, true),
+        (Kind::Message, MESSAGE_NESTED_TYPE) => (Kind::Message, true),
+        (Kind::Message, MESSAGE_ENUM_TYPE) => (Kind::Enum, true),
+        (Kind::Message, MESSAGE_EXTENSION) => (Kind::Extension, true),
+        (Kind::Message, MESSAGE_ONEOF_DECL) => (Kind::Oneof, true),
+        (Kind::Message, MESSAGE_RESERVED_RANGE) => (Kind::ReservedRanges, false),
+        (Kind::Message, MESSAGE_RESERVED_NAME) => (Kind::ReservedNames, false),
+        (Kind::Enum, ENUM_VALUE) => (Kind::EnumValue, true),
+        (Kind::Enum, ENUM_RESERVED_RANGE) => (Kind::ReservedRanges, false),
+        (Kind::Enum, ENUM_RESERVED_NAME) => (Kind::ReservedNames, false),
+        (Kind::Service, SERVICE_METHOD) => (Kind::Method, true),
+        // The index of a single range or name follows directly.
+        (Kind::ReservedRanges, index) if index >= 0 => (Kind::ReservedRange, false),
+        (Kind::ReservedNames, index) if index >= 0 => (Kind::ReservedName, false),
+        _ => return None,
+    })
+}
+
+// Splits the first component off a dotted name.
+fn split_name(name: &str) -> (&str, Option<&str>) {
+    match name.find('.') {
+        Some(i) => (&name[..i], Some(&name[i + 1..])),
+        None => (name, None),
+    }
+}
+
+fn find_in_message(message: &DescriptorProto, path: Path, name: &str) -> Option<Path> {
+    let (first, rest) = split_name(name);
+    if let Some(i) = message.nested_type.iter().position(|m| m.name() == first) {
+        let path = path.message(i as i32);
+        return match rest {
+            None => Some(path),
+            Some(rest) => find_in_message(&message.nested_type[i], path, rest),
+        };
+    }
+    if rest.is_some() {
+        return None;
+    }
+    if let Some(i) = message.field.iter().position(|f| f.name() == first) {
+        return Some(path.field(i as i32));
+    }
+    if let Some(i) = message.oneof_decl.iter().position(|o| o.name() == first) {
+        return Some(path.oneof(i as i32));
+    }
+    if let Some(i) = message.extension.iter().position(|e| e.name() == first) {
+        return Some(path.extension(i as i32));
+    }
+    find_enum(&message.enum_type, path, name)
+}
+
+// Finds an enum, or a value of one, among the enums of a file or message
+// at `path`.
+fn find_enum(enums: &[EnumDescriptorProto], path: Path, name: &str) -> Option<Path> {
+    if let Some(i) = enums.iter().position(|e| e.name() == name) {
+        return Some(path.enumeration(i as i32));
+    }
+    enums.iter().enumerate().find_map(|(i, enumeration)| {
+        let j = enumeration.value.iter().position(|v| v.name() == name)?;
+        Some(path.clone().enumeration(i as i32).value(j as i32))
+    })
+}
+
+impl AsRef<[i32]> for Path {
+    fn as_ref(&self) -> &[i32] {
+        &self.numbers
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Kind::File => "file",
+            Kind::Syntax => "syntax",
+            Kind::Package => "package",
+            Kind::Import => "import",
+            Kind::Message => "message",
+            Kind::Field => "field",
+            Kind::Extension => "extension",
+            Kind::Oneof => "oneof",
+            Kind::Enum => "enum",
+            Kind::EnumValue => "value",
+            Kind::Service => "service",
+            Kind::Method => "method",
+            Kind::ReservedRanges | Kind::ReservedRange => "reserved_range",
+            Kind::ReservedNames | Kind::ReservedName => "reserved_name",
+        })
+    }
+}
+
+/// Writes the path as its elements, e.g. `message[0].field[1]`, or `file`
+/// for the empty path.
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.numbers.is_empty() {
+            return f.write_str("file");
+        }
+
+        let mut kind = Kind::File;
+        let mut rest = &self.numbers[..];
+        while let Some((&number, tail)) = rest.split_first() {
+            let (next, indexed) = child(kind, number).ok_or(fmt::Error)?;
+            rest = tail;
+            match next {
+                Kind::ReservedRange | Kind::ReservedName => write!(f, "[{}]", number)?,
+                _ if kind != Kind::File => f.write_str(".")?,
+                _ => {}
+            }
+            match next {
+                Kind::ReservedRange | Kind::ReservedName => {}
+                _ if indexed => {
+                    write!(f, "{}[{}]", next, rest[0])?;
+                    rest = &rest[1..];
+                }
+                _ => write!(f, "{}", next)?,
+            }
+            kind = next;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a path written as by its `Display` implementation, e.g.
+/// `message[0].field[1]` or `file`.
+impl FromStr for Path {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Path> {
+        let invalid =
+            || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid path: {}", s));
+        if s == "file" {
+            return Ok(Path::file());
+        }
+
+        let mut numbers = Vec::new();
+        let mut kind = Kind::File;
+        for element in s.split('.') {
+            let (name, index) = match element.find('[') {
+                Some(i) => {
+                    let index = element[i + 1..]
+                        .strip_suffix(']')
+                        .and_then(|index| index.parse::<i32>().ok())
+                        .filter(|&index| index >= 0)
+                        .ok_or_else(invalid)?;
+                    (&element[..i], Some(index))
+                }
+                None => (element, None),
+            };
+            // The number of the elements named `name` in the one at `kind`.
+            let (number, next, indexed) = (0..=FILE_SYNTAX)
+                .filter_map(|number| {
+                    let (next, indexed) = child(kind, number)?;
+                    Some((number, next, indexed))
+                })
+                .find(|(_, next, _)| {
+                    !matches!(next, Kind::ReservedRange | Kind::ReservedName)
+                        && next.to_string() == name
+                })
+                .ok_or_else(invalid)?;
+            numbers.push(number);
+            kind = next;
+            match (kind, index) {
+                (Kind::ReservedRanges, Some(index)) => {
+                    numbers.push(index);
+                    kind = Kind::ReservedRange;
+                }
+                (Kind::ReservedNames, Some(index)) => {
+                    numbers.push(index);
+                    kind = Kind::ReservedName;
+                }
+                (_, Some(index)) if indexed => numbers.push(index),
+                (_, None) if !indexed => {}
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Path { numbers, kind })
+    }
+}
@@ -0,0 +1,77 @@
+//! protoc plugin support.
+//!
+//! `protoc-gen-protobuf-gen` reads a `CodeGeneratorRequest` from stdin,
+//! renders every file in `file_to_generate` and writes the result back as a
+//! `CodeGeneratorResponse`, so normalizing `.proto` files can run as an
+//! ordinary `protoc --protobuf-gen_out=...` or `buf generate` step.
+
+use crate::{GeneratorConfig, ProtobufString};
+use prost_types::compiler::{code_generator_response, CodeGeneratorRequest, CodeGeneratorResponse};
+
+/// Decodes a `CodeGeneratorRequest` as protoc sends it, keeping the custom
+/// options of its files, which decoding it with prost alone drops.
+pub fn decode_request(bytes: &[u8]) -> std::io::Result<CodeGeneratorRequest> {
+    crate::custom_options::decode_request(bytes).map_err(crate::invalid_data)
+}
+
+pub fn generate(request: &CodeGeneratorRequest) -> CodeGeneratorResponse {
+    let mut response = CodeGeneratorResponse {
+        supported_features: Some(code_generator_response::Feature::Proto3Optional as u64),
+        ..Default::default()
+    };
+
+    let config = match parse_parameter(request.parameter()) {
+        Ok(config) => config,
+        Err(err) => {
+            response.error = Some(err);
+            return response;
+        }
+    };
+
+    for name in &request.file_to_generate {
+        let file = match request.proto_file.iter().find(|f| f.name() == name) {
+            Some(file) => file,
+            None => {
+                response.error = Some(format!("{}: file not found in request", name));
+                return response;
+            }
+        };
+        // Editions files have no syntax to write them back in.
+        match file.syntax() {
+            "" | "proto2" | "proto3" => {}
+            syntax => {
+                response.error = Some(format!(
+                    "{}: syntax \"{}\" cannot be rendered",
+                    name, syntax
+                ));
+                return response;
+            }
+        }
+
+        response.file.push(code_generator_response::File {
+            name: Some(name.clone()),
+            content: Some(file.to_protobuf_with(file, &config)),
+            ..Default::default()
+        });
+    }
+
+    response
+}
+
+// The parameter is a comma separated list of `key=value` pairs, as passed
+// with `--protobuf-gen_opt=indent=4` or `opt:` in `buf.gen.yaml`. The keys
+// are the ones of the `[format]` table in `protobuf-gen.toml`.
+fn parse_parameter(parameter: &str) -> Result<GeneratorConfig, String> {
+    let mut config = GeneratorConfig::default();
+    for param in parameter
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+    {
+        let (key, value) = param
+            .split_once('=')
+            .ok_or_else(|| format!("missing value for {}", param))?;
+        config.set(key.trim(), value.trim())?;
+    }
+    Ok(config)
+}
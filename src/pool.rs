@@ -0,0 +1,488 @@
+//! A symbol table over a `FileDescriptorSet`.
+//!
+//! A [`DescriptorPool`] indexes every package, message, field, oneof,
+//! extension, enum, enum value, service and method of a set by its
+//! fully-qualified name, along with the file and path of its definition,
+//! and every place a type is referred to:
+//!
+//! ```
+//! use protobuf_gen::builder::FieldType;
+//! use protobuf_gen::pool::DescriptorPool;
+//!
+//! let set = prost_types::FileDescriptorSet {
+//!     file: vec![protobuf_gen::FileBuilder::new("user.proto")
+//!         .package("acme")
+//!         .message("User", |m| m.field("name", FieldType::message("Name"), 1))
+//!         .message("Name", |m| m.field("first", FieldType::String, 1))
+//!         .build()],
+//! };
+//! let pool = DescriptorPool::new(&set)?;
+//!
+//! assert_eq!("user.proto", pool.file_of(".acme.Name").unwrap().name());
+//! assert_eq!("first", pool.field("acme.Name.first").unwrap().name());
+//! assert_eq!(".acme.User.name", pool.references(".acme.Name")[0].name);
+//! // `Name` as written in the message `User`.
+//! assert_eq!(".acme.Name", pool.resolve_type(".acme.User", "Name").unwrap().name);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Names are written with a leading dot, as in the `type_name` of fields;
+//! lookups accept them with or without it. Like protoc, enum values are
+//! scoped alongside their enum rather than in it.
+
+use crate::path::Path;
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+    FileDescriptorProto, FileDescriptorSet, MethodDescriptorProto, OneofDescriptorProto,
+    ServiceDescriptorProto,
+};
+use std::collections::HashMap;
+use std::io;
+
+/// An indexed view of a `FileDescriptorSet`.
+pub struct DescriptorPool<'a> {
+    set: &'a FileDescriptorSet,
+    files: HashMap<&'a str, &'a FileDescriptorProto>,
+    definitions: HashMap<String, Definition<'a>>,
+    references: HashMap<String, Vec<Reference<'a>>>,
+}
+
+/// A named element.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symbol<'a> {
+    Package,
+    Message(&'a DescriptorProto),
+    Field(&'a FieldDescriptorProto),
+    Oneof(&'a OneofDescriptorProto),
+    Extension(&'a FieldDescriptorProto),
+    Enum(&'a EnumDescriptorProto),
+    EnumValue(&'a EnumValueDescriptorProto),
+    Service(&'a ServiceDescriptorProto),
+    Method(&'a MethodDescriptorProto),
+}
+
+impl Symbol<'_> {
+    /// Whether the symbol is a message or an enum, which fields can have as
+    /// their type.
+    pub fn is_type(&self) -> bool {
+        matches!(self, Symbol::Message(_) | Symbol::Enum(_))
+    }
+
+    /// Whether other symbols can be defined in it.
+    pub fn is_aggregate(&self) -> bool {
+        matches!(
+            self,
+            Symbol::Package | Symbol::Message(_) | Symbol::Enum(_) | Symbol::Service(_)
+        )
+    }
+}
+
+/// Where a symbol is defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Definition<'a> {
+    /// The fully-qualified name, with a leading dot.
+    pub name: String,
+    pub symbol: Symbol<'a>,
+    /// The file defining the symbol; for a package, the first file of the
+    /// set declaring it or a package inside it.
+    pub file: &'a FileDescriptorProto,
+    /// The path of the definition in the file's `SourceCodeInfo`; for a
+    /// package, that of the file.
+    pub path: Path,
+}
+
+/// A place referring to a message or enum by name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference<'a> {
+    /// The fully-qualified name of the referring field, extension or
+    /// method.
+    pub name: String,
+    pub kind: ReferenceKind,
+    pub file: &'a FileDescriptorProto,
+    pub path: Path,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReferenceKind {
+    /// The type of a field or extension.
+    FieldType,
+    /// The message an extension extends.
+    Extendee,
+    MethodInput,
+    MethodOutput,
+}
+
+impl<'a> DescriptorPool<'a> {
+    /// Indexes `set`. Fails if a name is defined twice, as protoc would.
+    pub fn new(set: &'a FileDescriptorSet) -> io::Result<DescriptorPool<'a>> {
+        let mut pool = DescriptorPool {
+            set,
+            files: HashMap::new(),
+            definitions: HashMap::new(),
+            references: HashMap::new(),
+        };
+        let mut references = Vec::new();
+        for file in &set.file {
+            pool.files.insert(file.name(), file);
+            let mut indexer = Indexer {
+                pool: &mut pool,
+                file,
+                references: &mut references,
+            };
+            indexer.file()?;
+        }
+
+        // Names protoc leaves relative, as in descriptors built by hand,
+        // are resolved in the scope of the element referring to them.
+        for (target, reference) in references {
+            let target = if target.starts_with('.') {
+                target
+            } else {
+                let scope = reference.scope();
+                let resolved = match reference.kind {
+                    ReferenceKind::FieldType => pool.resolve_type(scope, &target),
+                    _ => pool.resolve_with(scope, &target, |s| matches!(s, Symbol::Message(_))),
+                };
+                match resolved {
+                    Some(definition) => definition.name.clone(),
+                    None => format!(".{}", target),
+                }
+            };
+            pool.references.entry(target).or_default().push(reference);
+        }
+        Ok(pool)
+    }
+
+    pub fn set(&self) -> &'a FileDescriptorSet {
+        self.set
+    }
+
+    /// The file of the set named `name`.
+    pub fn file(&self, name: &str) -> Option<&'a FileDescriptorProto> {
+        self.files.get(name).copied()
+    }
+
+    /// The definition of the symbol with the fully-qualified `name`.
+    pub fn get(&self, name: &str) -> Option<&Definition<'a>> {
+        if name.starts_with('.') {
+            self.definitions.get(name)
+        } else {
+            self.definitions.get(&format!(".{}", name))
+        }
+    }
+
+    /// The file defining the symbol `name`.
+    pub fn file_of(&self, name: &str) -> Option<&'a FileDescriptorProto> {
+        self.get(name).map(|definition| definition.file)
+    }
+
+    pub fn message(&self, name: &str) -> Option<&'a DescriptorProto> {
+        match self.get(name)?.symbol {
+            Symbol::Message(message) => Some(message),
+            _ => None,
+        }
+    }
+
+    pub fn field(&self, name: &str) -> Option<&'a FieldDescriptorProto> {
+        match self.get(name)?.symbol {
+            Symbol::Field(field) => Some(field),
+            _ => None,
+        }
+    }
+
+    pub fn oneof(&self, name: &str) -> Option<&'a OneofDescriptorProto> {
+        match self.get(name)?.symbol {
+            Symbol::Oneof(oneof) => Some(oneof),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self, name: &str) -> Option<&'a FieldDescriptorProto> {
+        match self.get(name)?.symbol {
+            Symbol::Extension(extension) => Some(extension),
+            _ => None,
+        }
+    }
+
+    pub fn enumeration(&self, name: &str) -> Option<&'a EnumDescriptorProto> {
+        match self.get(name)?.symbol {
+            Symbol::Enum(enumeration) => Some(enumeration),
+            _ => None,
+        }
+    }
+
+    pub fn enum_value(&self, name: &str) -> Option<&'a EnumValueDescriptorProto> {
+        match self.get(name)?.symbol {
+            Symbol::EnumValue(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn service(&self, name: &str) -> Option<&'a ServiceDescriptorProto> {
+        match self.get(name)?.symbol {
+            Symbol::Service(service) => Some(service),
+            _ => None,
+        }
+    }
+
+    pub fn method(&self, name: &str) -> Option<&'a MethodDescriptorProto> {
+        match self.get(name)?.symbol {
+            Symbol::Method(method) => Some(method),
+            _ => None,
+        }
+    }
+
+    /// The definitions of all symbols, in no particular order.
+    pub fn definitions(&self) -> impl Iterator<Item = &Definition<'a>> {
+        self.definitions.values()
+    }
+
+    /// The fields, extensions and methods referring to the message or enum
+    /// `name`, in the order of the files of the set.
+    pub fn references(&self, name: &str) -> &[Reference<'a>] {
+        let refs = if name.starts_with('.') {
+            self.references.get(name)
+        } else {
+            self.references.get(&format!(".{}", name))
+        };
+        refs.map_or(&[], Vec::as_slice)
+    }
+
+    /// Resolves `name` as written in `scope`, the fully-qualified name of
+    /// a package, message, enum or service, the way protoc does.
+    ///
+    /// A name with a leading dot is fully qualified. Otherwise its first
+    /// component is looked up in `scope`, then in each enclosing scope up to
+    /// the root, and the rest of the name in the first symbol found that can
+    /// contain others. So in a message `.acme.User`, `Name` is found as
+    /// `.acme.User.Name` or else `.acme.Name` or `.Name`.
+    pub fn resolve(&self, scope: &str, name: &str) -> Option<&Definition<'a>> {
+        self.resolve_with(scope, name, |_| true)
+    }
+
+    /// Resolves `name` as the type of a field declared in `scope`, like
+    /// [`resolve`](Self::resolve) but skipping symbols that are not messages
+    /// or enums, as protoc does.
+    pub fn resolve_type(&self, scope: &str, name: &str) -> Option<&Definition<'a>> {
+        self.resolve_with(scope, name, |symbol| symbol.is_type())
+    }
+
+    fn resolve_with<F>(&self, scope: &str, name: &str, accept: F) -> Option<&Definition<'a>>
+    where
+        F: Fn(&Symbol) -> bool,
+    {
+        if name.starts_with('.') {
+            return self.get(name).filter(|d| accept(&d.symbol));
+        }
+
+        let (first, rest) = match name.find('.') {
+            Some(i) => (&name[..i], Some(&name[i + 1..])),
+            None => (name, None),
+        };
+        let mut scope = match scope {
+            "" | "." => "",
+            scope if scope.starts_with('.') => scope,
+            scope => return self.resolve_with(&format!(".{}", scope), name, accept),
+        };
+        loop {
+            let candidate = format!("{}.{}", scope, first);
+            if let Some(definition) = self.definitions.get(&candidate) {
+                match rest {
+                    None if accept(&definition.symbol) => return Some(definition),
+                    // Like protoc, a name whose first component is found
+                    // in a scope is not looked for further out.
+                    Some(rest) if definition.symbol.is_aggregate() => {
+                        return self
+                            .definitions
+                            .get(&format!("{}.{}", candidate, rest))
+                            .filter(|d| accept(&d.symbol));
+                    }
+                    _ => {}
+                }
+            }
+            match scope.rfind('.') {
+                Some(i) => scope = &scope[..i],
+                None => return None,
+            }
+        }
+    }
+}
+
+impl Reference<'_> {
+    // The scope names in the referring element are resolved in: the message
+    // of a field, or the scope of an extension or service.
+    fn scope(&self) -> &str {
+        let end = self.name.rfind('.').unwrap_or(0);
+        let scope = &self.name[..end];
+        match self.kind {
+            ReferenceKind::MethodInput | ReferenceKind::MethodOutput => {
+                &scope[..scope.rfind('.').unwrap_or(0)]
+            }
+            _ => scope,
+        }
+    }
+}
+
+struct Indexer<'p, 'a> {
+    pool: &'p mut DescriptorPool<'a>,
+    file: &'a FileDescriptorProto,
+    // The name each reference is written with, in order.
+    references: &'p mut Vec<(String, Reference<'a>)>,
+}
+
+impl<'a> Indexer<'_, 'a> {
+    fn file(&mut self) -> io::Result<()> {
+        let file = self.file;
+        let path = Path::file();
+
+        // A package defines each of its parents too, so that `acme.users`
+        // can be found from `acme`.
+        let mut scope = String::new();
+        if !file.package().is_empty() {
+            for part in file.package().split('.') {
+                scope.push('.');
+                scope.push_str(part);
+                match self.pool.definitions.get(&scope) {
+                    Some(d) if d.symbol == Symbol::Package => {}
+                    _ => self.define(&scope, Symbol::Package, &path)?,
+                }
+            }
+        }
+
+        for (i, message) in file.message_type.iter().enumerate() {
+            self.message(&scope, message, path.clone().message(i as i32))?;
+        }
+        for (i, enumeration) in file.enum_type.iter().enumerate() {
+            self.enumeration(&scope, enumeration, path.clone().enumeration(i as i32))?;
+        }
+        for (i, service) in file.service.iter().enumerate() {
+            let name = format!("{}.{}", scope, service.name());
+            let path = path.clone().service(i as i32);
+            self.define(&name, Symbol::Service(service), &path)?;
+            for (i, method) in service.method.iter().enumerate() {
+                let method_name = format!("{}.{}", name, method.name());
+                let path = path.clone().method(i as i32);
+                self.define(&method_name, Symbol::Method(method), &path)?;
+                self.refer(
+                    &method.input_type,
+                    &method_name,
+                    ReferenceKind::MethodInput,
+                    &path,
+                );
+                self.refer(
+                    &method.output_type,
+                    &method_name,
+                    ReferenceKind::MethodOutput,
+                    &path,
+                );
+            }
+        }
+        for (i, extension) in file.extension.iter().enumerate() {
+            self.extension(&scope, extension, path.clone().extension(i as i32))?;
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, scope: &str, message: &'a DescriptorProto, path: Path) -> io::Result<()> {
+        let name = format!("{}.{}", scope, message.name());
+        self.define(&name, Symbol::Message(message), &path)?;
+
+        for (i, field) in message.field.iter().enumerate() {
+            let field_name = format!("{}.{}", name, field.name());
+            let path = path.clone().field(i as i32);
+            self.define(&field_name, Symbol::Field(field), &path)?;
+            self.refer(
+                &field.type_name,
+                &field_name,
+                ReferenceKind::FieldType,
+                &path,
+            );
+        }
+        for (i, oneof) in message.oneof_decl.iter().enumerate() {
+            let oneof_name = format!("{}.{}", name, oneof.name());
+            self.define(
+                &oneof_name,
+                Symbol::Oneof(oneof),
+                &path.clone().oneof(i as i32),
+            )?;
+        }
+        for (i, nested) in message.nested_type.iter().enumerate() {
+            self.message(&name, nested, path.clone().message(i as i32))?;
+        }
+        for (i, enumeration) in message.enum_type.iter().enumerate() {
+            self.enumeration(&name, enumeration, path.clone().enumeration(i as i32))?;
+        }
+        for (i, extension) in message.extension.iter().enumerate() {
+            self.extension(&name, extension, path.clone().extension(i as i32))?;
+        }
+        Ok(())
+    }
+
+    fn enumeration(
+        &mut self,
+        scope: &str,
+        enumeration: &'a EnumDescriptorProto,
+        path: Path,
+    ) -> io::Result<()> {
+        let name = format!("{}.{}", scope, enumeration.name());
+        self.define(&name, Symbol::Enum(enumeration), &path)?;
+        for (i, value) in enumeration.value.iter().enumerate() {
+            let value_name = format!("{}.{}", scope, value.name());
+            self.define(
+                &value_name,
+                Symbol::EnumValue(value),
+                &path.clone().value(i as i32),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn extension(
+        &mut self,
+        scope: &str,
+        extension: &'a FieldDescriptorProto,
+        path: Path,
+    ) -> io::Result<()> {
+        let name = format!("{}.{}", scope, extension.name());
+        self.define(&name, Symbol::Extension(extension), &path)?;
+        self.refer(&extension.type_name, &name, ReferenceKind::FieldType, &path);
+        self.refer(&extension.extendee, &name, ReferenceKind::Extendee, &path);
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol<'a>, path: &Path) -> io::Result<()> {
+        if let Some(existing) = self.pool.definitions.get(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: \"{}\" is already defined in file \"{}\"",
+                    self.file.name(),
+                    &name[1..],
+                    existing.file.name()
+                ),
+            ));
+        }
+        self.pool.definitions.insert(
+            name.to_owned(),
+            Definition {
+                name: name.to_owned(),
+                symbol,
+                file: self.file,
+                path: path.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    fn refer(&mut self, target: &Option<String>, name: &str, kind: ReferenceKind, path: &Path) {
+        if let Some(target) = target {
+            let reference = Reference {
+                name: name.to_owned(),
+                kind,
+                file: self.file,
+                path: path.clone(),
+            };
+            self.references.push((target.clone(), reference));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! A width-aware layout engine, after Wadler's "A prettier printer".
+//!
+//! A [`Doc`] describes text together with the places where it may be broken
+//! onto a new line. [`Doc::group`] marks a unit that is laid out on one line
+//! if it fits in the remaining width, and otherwise has all of its own line
+//! breaks taken, nested content being indented one level deeper.
+
+use std::borrow::Cow;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Doc {
+    Text(Cow<'static, str>),
+    // A space, or a line break when the enclosing group does not fit.
+    Line,
+    // Nothing, or a line break when the enclosing group does not fit.
+    SoftLine,
+    Nest(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+impl Doc {
+    pub fn text<S>(text: S) -> Doc
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        Doc::Text(text.into())
+    }
+
+    pub fn nest(doc: Doc) -> Doc {
+        Doc::Nest(Box::new(doc))
+    }
+
+    pub fn group(doc: Doc) -> Doc {
+        Doc::Group(Box::new(doc))
+    }
+
+    pub fn concat(docs: Vec<Doc>) -> Doc {
+        Doc::Concat(docs)
+    }
+
+    /// `items` separated by `separator` followed by a [`Doc::Line`].
+    pub fn join<I>(items: I, separator: &'static str) -> Doc
+    where
+        I: IntoIterator<Item = Doc>,
+    {
+        let mut docs = Vec::new();
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                docs.push(Doc::text(separator));
+                docs.push(Doc::Line);
+            }
+            docs.push(item);
+        }
+        Doc::Concat(docs)
+    }
+
+    /// `open`, the items one per line and indented if they don't fit on
+    /// one, and `close`; e.g. `[a, b]` or `{ a: 1 }`.
+    pub fn bracket(open: &'static str, items: Vec<Doc>, close: &'static str) -> Doc {
+        Doc::group(Doc::concat(vec![
+            Doc::text(open),
+            Doc::nest(Doc::concat(vec![Doc::SoftLine, Doc::join(items, ",")])),
+            Doc::SoftLine,
+            Doc::text(close),
+        ]))
+    }
+
+    /// The width of the document laid out on a single line.
+    pub fn flat_width(&self) -> usize {
+        match self {
+            Doc::Text(text) => text.chars().count(),
+            Doc::Line => 1,
+            Doc::SoftLine => 0,
+            Doc::Nest(doc) | Doc::Group(doc) => doc.flat_width(),
+            Doc::Concat(docs) => docs.iter().map(Doc::flat_width).sum(),
+        }
+    }
+
+    /// Lays the document out into `out`, which must end at the column the
+    /// document starts at. Broken lines start with `indent`, plus `unit` for
+    /// each level of nesting, and are kept within `width` columns where
+    /// possible.
+    pub fn render(&self, out: &mut String, indent: &str, unit: &str, width: usize) {
+        let line_start = out.rfind('\n').map_or(0, |i| i + 1);
+        let mut column = out[line_start..].chars().count();
+        let mut stack = vec![(0, Mode::Break, self)];
+
+        while let Some((level, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(text) => {
+                    out.push_str(text);
+                    column += text.chars().count();
+                }
+                Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                    if *doc == Doc::Line {
+                        out.push(' ');
+                        column += 1;
+                    }
+                }
+                Doc::Line | Doc::SoftLine => {
+                    out.push('\n');
+                    out.push_str(indent);
+                    for _ in 0..level {
+                        out.push_str(unit);
+                    }
+                    column = indent.chars().count() + level * unit.chars().count();
+                }
+                Doc::Nest(doc) => stack.push((level + 1, mode, doc)),
+                Doc::Group(doc) => {
+                    let mode = if mode == Mode::Flat
+                        || fits(width as isize - column as isize, doc, &stack)
+                    {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((level, mode, doc));
+                }
+                Doc::Concat(docs) => {
+                    for doc in docs.iter().rev() {
+                        stack.push((level, mode, doc));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+// Whether `doc` laid out flat, together with whatever follows it up to the
+// next line break, fits in `remaining` columns.
+fn fits(mut remaining: isize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+
+    loop {
+        if remaining < 0 {
+            return false;
+        }
+
+        let (mode, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(&(_, mode, doc)) => (mode, doc),
+                None => return true,
+            },
+        };
+
+        match doc {
+            Doc::Text(text) => remaining -= text.chars().count() as isize,
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+            Doc::Line => remaining -= 1,
+            Doc::SoftLine => {}
+            Doc::Nest(doc) | Doc::Group(doc) => stack.push((mode, doc)),
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((mode, doc));
+                }
+            }
+        }
+    }
+}
+
+/// Re-wraps the paragraphs of a comment so that no line is longer than
+/// `width`, starting each line with the spaces the line its first word
+/// comes from started with, usually the one after `//`.
+///
+/// Blank lines separate paragraphs. Lines indented by more than one space or
+/// starting a list item are left as they are, since they are likely to be
+/// preformatted.
+pub fn reflow(comment: &str, width: usize) -> String {
+    let mut out = String::with_capacity(comment.len());
+    let mut line = String::new();
+
+    let flush = |out: &mut String, line: &mut String| {
+        if !line.is_empty() {
+            out.push_str(line);
+            out.push('\n');
+            line.clear();
+        }
+    };
+
+    for text in comment.lines() {
+        let words = text.trim();
+        let preformatted = text.starts_with("  ")
+            || words.starts_with("- ")
+            || words.starts_with("* ")
+            || words.is_empty();
+        if preformatted {
+            flush(&mut out, &mut line);
+            out.push_str(text);
+            out.push('\n');
+            continue;
+        }
+
+        let prefix = &text[..text.len() - text.trim_start().len()];
+        for word in words.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                flush(&mut out, &mut line);
+            }
+            match line.is_empty() {
+                true => line.push_str(prefix),
+                false => line.push(' '),
+            }
+            line.push_str(word);
+        }
+    }
+    flush(&mut out, &mut line);
+
+    if !comment.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
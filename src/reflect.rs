@@ -0,0 +1,114 @@
+//! Rendering the descriptors of a prost-reflect `DescriptorPool`.
+//!
+//! Files, messages, enums and services held at runtime, e.g. by a pool
+//! decoded from the descriptor bytes embedded in a binary, can be printed
+//! as `.proto` with [`ReflectProtobufString`], along with their comments
+//! if the pool has source info:
+//!
+//! ```
+//! use prost::Message;
+//! use protobuf_gen::reflect::ReflectProtobufString;
+//!
+//! let file = protobuf_gen::FileBuilder::new("user.proto")
+//!     .package("acme")
+//!     .message("User", |m| m.field("name", protobuf_gen::builder::FieldType::String, 1))
+//!     .build();
+//! let bytes = prost_types::FileDescriptorSet { file: vec![file] }.encode_to_vec();
+//!
+//! let pool = prost_reflect::DescriptorPool::decode(&*bytes).unwrap();
+//! let user = pool.get_message_by_name("acme.User").unwrap();
+//! assert_eq!("message User {\n  string name = 1;\n}\n", user.to_protobuf());
+//! ```
+//!
+//! This needs the `reflect` feature.
+
+use crate::{ast, custom_options, Generator, GeneratorConfig, ProtobufString};
+use prost_reflect::{EnumDescriptor, FileDescriptor, MessageDescriptor, ServiceDescriptor};
+
+/// The counterpart of [`ProtobufString`] for prost-reflect descriptors,
+/// which know the file they belong to.
+pub trait ReflectProtobufString {
+    /// Renders `self` with the default [`GeneratorConfig`].
+    fn to_protobuf(&self) -> String {
+        self.to_protobuf_with(&GeneratorConfig::default())
+    }
+
+    fn to_protobuf_with(&self, config: &GeneratorConfig) -> String;
+}
+
+impl ReflectProtobufString for FileDescriptor {
+    fn to_protobuf_with(&self, config: &GeneratorConfig) -> String {
+        let file = file_descriptor_proto(self);
+        file.to_protobuf_with(&file, config)
+    }
+}
+
+impl ReflectProtobufString for MessageDescriptor {
+    fn to_protobuf_with(&self, config: &GeneratorConfig) -> String {
+        let file = file_descriptor_proto(&self.parent_file());
+        render(message_at(&file, self.path()), &file, self.path(), config)
+    }
+}
+
+impl ReflectProtobufString for EnumDescriptor {
+    fn to_protobuf_with(&self, config: &GeneratorConfig) -> String {
+        let file = file_descriptor_proto(&self.parent_file());
+        let (scope, index) = self.path().split_at(self.path().len() - 1);
+        let enumeration = match scope {
+            [_] => &file.enum_type[index[0] as usize],
+            [message @ .., _] => &message_at(&file, message).enum_type[index[0] as usize],
+            [] => unreachable!("enums are in a file or a message"),
+        };
+        render(enumeration, &file, self.path(), config)
+    }
+}
+
+impl ReflectProtobufString for ServiceDescriptor {
+    fn to_protobuf_with(&self, config: &GeneratorConfig) -> String {
+        let file = file_descriptor_proto(&self.parent_file());
+        let service = &file.service[self.index()];
+        render(service, &file, self.path(), config)
+    }
+}
+
+// Renders `element`, found at `path` in `file`.
+fn render<T: ProtobufString>(
+    element: &T,
+    file: &prost_types::FileDescriptorProto,
+    path: &[i32],
+    config: &GeneratorConfig,
+) -> String {
+    let mut gen = Generator::new(config);
+    gen.source = ast::Source::new(file).at(path);
+    gen.source.lossless = config.lossless;
+    element.build_protobuf(&mut gen);
+    // Without anything before it, the element needs no blank lines to
+    // separate it.
+    let out = gen.finish();
+    out.trim_start_matches(['\r', '\n']).to_owned()
+}
+
+// prost-reflect is built on another version of prost, so its descriptors
+// are converted through their encoding. The whole pool is decoded, so that
+// the custom options of the file are found among the extensions it defines.
+fn file_descriptor_proto(file: &FileDescriptor) -> prost_types::FileDescriptorProto {
+    let bytes = file.parent_pool().encode_to_vec();
+    let set = custom_options::decode_set(&bytes)
+        .expect("prost-reflect encodes a valid FileDescriptorSet");
+    set.file
+        .into_iter()
+        .find(|f| f.name() == file.name())
+        .expect("a file is in its pool")
+}
+
+// The message at `path`, e.g. `[4, 0, 3, 1]`.
+fn message_at<'a>(
+    file: &'a prost_types::FileDescriptorProto,
+    path: &[i32],
+) -> &'a prost_types::DescriptorProto {
+    let mut message = &file.message_type[path[1] as usize];
+    for nested in path[2..].chunks(2) {
+        message = &message.nested_type[nested[1] as usize];
+    }
+    message
+}
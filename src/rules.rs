@@ -0,0 +1,504 @@
+//! Declarative rewrite rules.
+//!
+//! A rules file lists simple refactors to apply to descriptors before they
+//! are rendered, for the common cases that would otherwise need a
+//! [`VisitMut`]. Each rule has selectors, all of which must match a field
+//! or enum value, and the actions to take on it:
+//!
+//! ```toml
+//! # Give every singular string field in the acme packages presence.
+//! [[rule]]
+//! package = "acme.*"
+//! type = "string"
+//! optional = true
+//!
+//! [[rule]]
+//! field = "legacy_*"
+//! deprecated = true
+//!
+//! [[rule]]
+//! enum = "Status"
+//! value = "*"
+//! add_prefix = "STATUS_"
+//! ```
+//!
+//! The same rules can be written in YAML, as a list under `rule`.
+//!
+//! Selectors:
+//!
+//! - `package`: the package name.
+//! - `message`: the name of a message, relative to the package, e.g.
+//!   `User.Address` for a message nested in `User`.
+//! - `field`: the name of a field of a message.
+//! - `type`: the type of a field, as written in a .proto file for scalars,
+//!   or `message` or `enum`.
+//! - `label`: `optional`, `required` or `repeated` for fields written with
+//!   that label, or `none` for proto3 fields without `optional` and fields
+//!   of oneofs.
+//! - `enum`: the name of an enum, relative to the package.
+//! - `value`: the name of an enum value.
+//! - `option`: the name of a custom option set on the field or value, e.g.
+//!   `(acme.sensitive)`, with or without the leading dot of a
+//!   fully-qualified name. Options protoc has interpreted are matched too,
+//!   as long as their extension is in the same descriptor set.
+//!
+//! Names are matched as globs, where `*` matches any run of characters,
+//! including dots, and `?` any one character. `package = "acme.*"` thus
+//! matches the packages inside `acme`, but not `acme` itself. A rule with an `enum` or
+//! `value` selector applies to enum values, and any other rule to fields.
+//! Messages and enums themselves are not rewritten.
+//!
+//! Actions:
+//!
+//! - `deprecated`: sets the `deprecated` option.
+//! - `optional` (fields): gives singular fields outside of oneofs explicit
+//!   presence, turning `required` into `optional` in proto2.
+//! - `add_prefix` (fields and enum values): renames the element by adding
+//!   a prefix, unless it already has it. Fields whose default is a renamed
+//!   enum value default to its new name, in the files the rules are
+//!   applied to.
+//!
+//! Rules are applied in order, so a rule sees the changes made by the ones
+//! before it.
+
+use crate::builder::{make_proto3_optional, rename_field};
+use crate::transform::Pass;
+use crate::visit::{visit_file_mut, visit_set_mut, Context, VisitMut};
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    FileDescriptorSet, Syntax, UninterpretedOption,
+};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    pub rule: Vec<Rule>,
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rule {
+    pub package: Option<String>,
+    pub message: Option<String>,
+    pub field: Option<String>,
+    #[serde(rename = "type")]
+    pub field_type: Option<FieldTypeSelector>,
+    pub label: Option<LabelSelector>,
+    #[serde(rename = "enum")]
+    pub enumeration: Option<String>,
+    pub value: Option<String>,
+    pub option: Option<String>,
+
+    pub deprecated: Option<bool>,
+    pub optional: bool,
+    pub add_prefix: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldTypeSelector {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Group,
+    Message,
+    Bytes,
+    Uint32,
+    Enum,
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelSelector {
+    Optional,
+    Required,
+    Repeated,
+    None,
+}
+
+/// The kind of element a rule applies to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Target {
+    Field,
+    EnumValue,
+}
+
+impl Rules {
+    /// Reads a rules file, as YAML if its extension is `.yaml` or `.yml`
+    /// and as TOML otherwise.
+    pub fn from_file<P>(path: P) -> io::Result<Rules>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let rules = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => Rules::from_yaml(&text),
+            _ => Rules::from_toml(&text),
+        };
+        rules.map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn from_toml(text: &str) -> io::Result<Rules> {
+        let rules: Rules = toml::from_str(text).map_err(invalid_data)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(text: &str) -> io::Result<Rules> {
+        let rules: Rules = serde_yaml::from_str(text).map_err(invalid_data)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    #[cfg(not(feature = "yaml"))]
+    pub fn from_yaml(_: &str) -> io::Result<Rules> {
+        Err(invalid_data(
+            "YAML rules need the `yaml` feature of protobuf-gen",
+        ))
+    }
+
+    /// Checks that each rule has selectors and actions that make sense for
+    /// the elements it applies to.
+    pub fn validate(&self) -> io::Result<()> {
+        for (i, rule) in self.rule.iter().enumerate() {
+            rule.validate()
+                .map_err(|err| invalid_data(format!("rule {}: {}", i + 1, err)))?;
+        }
+        Ok(())
+    }
+
+    /// Applies the rules to every file of `set`.
+    pub fn apply(&self, set: &mut FileDescriptorSet) -> io::Result<()> {
+        self.validate()?;
+        let mut apply = Apply::new(self);
+        visit_set_mut(&mut apply, set);
+        visit_set_mut(&mut RenameDefaults(apply.renamed), set);
+        Ok(())
+    }
+
+    pub fn apply_file(&self, file: &mut FileDescriptorProto) -> io::Result<()> {
+        self.validate()?;
+        let mut apply = Apply::new(self);
+        visit_file_mut(&mut apply, file);
+        visit_file_mut(&mut RenameDefaults(apply.renamed), file);
+        Ok(())
+    }
+
+    /// Appends the rules of `other` after these.
+    pub fn extend(&mut self, other: Rules) {
+        self.rule.extend(other.rule);
+    }
+}
+
+impl Pass for Rules {
+    fn run(&mut self, set: &mut FileDescriptorSet) -> io::Result<()> {
+        self.apply(set)
+    }
+}
+
+impl Rule {
+    pub fn target(&self) -> Target {
+        if self.enumeration.is_some() || self.value.is_some() {
+            Target::EnumValue
+        } else {
+            Target::Field
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let Rule {
+            package,
+            message,
+            field,
+            field_type,
+            label,
+            enumeration,
+            value,
+            option,
+            ..
+        } = self;
+        let selects = package.is_some()
+            || message.is_some()
+            || field.is_some()
+            || field_type.is_some()
+            || label.is_some()
+            || enumeration.is_some()
+            || value.is_some()
+            || option.is_some();
+        if !selects {
+            return Err("no selectors".into());
+        }
+        if self.deprecated.is_none() && !self.optional && self.add_prefix.is_none() {
+            return Err("no actions".into());
+        }
+        if self.target() == Target::EnumValue {
+            if message.is_some() || field.is_some() || field_type.is_some() || label.is_some() {
+                return Err(
+                    "enum value rules select nested enums by name, e.g. `enum = \"Outer.Inner\"`"
+                        .into(),
+                );
+            }
+            if self.optional {
+                return Err("`optional` only applies to fields".into());
+            }
+        }
+        Ok(())
+    }
+
+    fn matches_package(&self, cx: &Context) -> bool {
+        matches(&self.package, cx.package())
+    }
+
+    fn matches_field(&self, field: &FieldDescriptorProto, syntax: Syntax) -> bool {
+        matches(&self.field, field.name())
+            && self
+                .field_type
+                .is_none_or(|t| t.to_type() == field.r#type())
+            && self.label.is_none_or(|l| l == label(field, syntax))
+            && has_option(
+                &self.option,
+                field.options.as_ref().map(|o| &o.uninterpreted_option),
+            )
+    }
+
+    fn apply_field(&self, message: &mut DescriptorProto, index: usize, syntax: Syntax) {
+        let field = &mut message.field[index];
+        if let Some(deprecated) = self.deprecated {
+            field
+                .options
+                .get_or_insert_with(Default::default)
+                .deprecated = Some(deprecated);
+        }
+        if let Some(ref prefix) = self.add_prefix {
+            if !field.name().starts_with(&**prefix) {
+                let name = format!("{}{}", prefix, field.name());
+                rename_field(field, name);
+            }
+        }
+        if self.optional {
+            match (syntax, label(field, syntax)) {
+                (Syntax::Proto2, LabelSelector::Required) => {
+                    field.set_label(Label::Optional);
+                }
+                (Syntax::Proto3, LabelSelector::None) if field.oneof_index.is_none() => {
+                    make_proto3_optional(message, index);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl FieldTypeSelector {
+    fn to_type(self) -> Type {
+        match self {
+            FieldTypeSelector::Double => Type::Double,
+            FieldTypeSelector::Float => Type::Float,
+            FieldTypeSelector::Int64 => Type::Int64,
+            FieldTypeSelector::Uint64 => Type::Uint64,
+            FieldTypeSelector::Int32 => Type::Int32,
+            FieldTypeSelector::Fixed64 => Type::Fixed64,
+            FieldTypeSelector::Fixed32 => Type::Fixed32,
+            FieldTypeSelector::Bool => Type::Bool,
+            FieldTypeSelector::String => Type::String,
+            FieldTypeSelector::Group => Type::Group,
+            FieldTypeSelector::Message => Type::Message,
+            FieldTypeSelector::Bytes => Type::Bytes,
+            FieldTypeSelector::Uint32 => Type::Uint32,
+            FieldTypeSelector::Enum => Type::Enum,
+            FieldTypeSelector::Sfixed32 => Type::Sfixed32,
+            FieldTypeSelector::Sfixed64 => Type::Sfixed64,
+            FieldTypeSelector::Sint32 => Type::Sint32,
+            FieldTypeSelector::Sint64 => Type::Sint64,
+        }
+    }
+}
+
+// The label a field is written with.
+fn label(field: &FieldDescriptorProto, syntax: Syntax) -> LabelSelector {
+    match field.label() {
+        Label::Repeated => LabelSelector::Repeated,
+        Label::Required => LabelSelector::Required,
+        Label::Optional if field.proto3_optional() => LabelSelector::Optional,
+        Label::Optional if field.oneof_index.is_some() => LabelSelector::None,
+        Label::Optional if syntax == Syntax::Proto3 => LabelSelector::None,
+        Label::Optional => LabelSelector::Optional,
+    }
+}
+
+struct Apply<'a> {
+    rules: &'a Rules,
+    // The enum values renamed so far, in order, as the full name of their
+    // enum, their old name and their new one.
+    renamed: Vec<(String, String, String)>,
+}
+
+impl<'a> Apply<'a> {
+    fn new(rules: &'a Rules) -> Self {
+        Apply {
+            rules,
+            renamed: Vec::new(),
+        }
+    }
+}
+
+impl VisitMut for Apply<'_> {
+    fn message(&mut self, message: &mut DescriptorProto, cx: &Context) {
+        // The entries of map fields are not written in .proto files.
+        if message.options.as_ref().and_then(|o| o.map_entry) == Some(true) {
+            return;
+        }
+        let name = relative_name(cx);
+        for rule in &self.rules.rule {
+            if rule.target() != Target::Field
+                || !rule.matches_package(cx)
+                || !matches(&rule.message, name)
+            {
+                continue;
+            }
+            for i in 0..message.field.len() {
+                if rule.matches_field(&message.field[i], cx.syntax()) {
+                    rule.apply_field(message, i, cx.syntax());
+                }
+            }
+        }
+    }
+
+    fn enumeration(&mut self, enumeration: &mut EnumDescriptorProto, cx: &Context) {
+        let name = relative_name(cx);
+        for rule in &self.rules.rule {
+            if rule.target() != Target::EnumValue
+                || !rule.matches_package(cx)
+                || !matches(&rule.enumeration, name)
+            {
+                continue;
+            }
+            for value in &mut enumeration.value {
+                let options = value.options.as_ref().map(|o| &o.uninterpreted_option);
+                if !matches(&rule.value, value.name()) || !has_option(&rule.option, options) {
+                    continue;
+                }
+                if let Some(deprecated) = rule.deprecated {
+                    value
+                        .options
+                        .get_or_insert_with(Default::default)
+                        .deprecated = Some(deprecated);
+                }
+                if let Some(ref prefix) = rule.add_prefix {
+                    if !value.name().starts_with(&**prefix) {
+                        let name = format!("{}{}", prefix, value.name());
+                        let old = value.name.replace(name.clone()).unwrap_or_default();
+                        self.renamed.push((cx.full_name().to_owned(), old, name));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Points the defaults of enum fields at the new names of renamed values.
+struct RenameDefaults(Vec<(String, String, String)>);
+
+impl RenameDefaults {
+    fn rename(&self, field: &mut FieldDescriptorProto) {
+        if field.r#type() != Type::Enum || field.default_value.is_none() {
+            return;
+        }
+        for (enumeration, old, new) in &self.0 {
+            if field.type_name() == enumeration && field.default_value() == old {
+                field.default_value = Some(new.clone());
+            }
+        }
+    }
+}
+
+impl VisitMut for RenameDefaults {
+    fn field(&mut self, field: &mut FieldDescriptorProto, _: &Context) {
+        self.rename(field);
+    }
+
+    fn extension(&mut self, extension: &mut FieldDescriptorProto, _: &Context) {
+        self.rename(extension);
+    }
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+// The name of the element being visited relative to its package.
+fn relative_name<'a>(cx: &Context<'a>) -> &'a str {
+    let full_name = cx.full_name().trim_start_matches('.');
+    match cx.package() {
+        "" => full_name,
+        package => full_name
+            .strip_prefix(package)
+            .and_then(|name| name.strip_prefix('.'))
+            .unwrap_or(full_name),
+    }
+}
+
+fn matches(pattern: &Option<String>, name: &str) -> bool {
+    pattern.as_deref().is_none_or(|pattern| glob(pattern, name))
+}
+
+fn has_option(name: &Option<String>, options: Option<&Vec<UninterpretedOption>>) -> bool {
+    match name {
+        None => true,
+        Some(name) => options.into_iter().flatten().any(|option| {
+            let option = crate::options::option_name(option);
+            option.replace("(.", "(") == name.replace("(.", "(")
+        }),
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of
+/// characters and `?` any one character.
+pub fn glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
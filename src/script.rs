@@ -0,0 +1,671 @@
+//! Transforming descriptors with Rhai scripts.
+//!
+//! For one-off migrations, a [Rhai](https://rhai.rs) script can do what a
+//! [`VisitMut`] does without compiling a Rust program. The script defines
+//! functions named like the methods of `VisitMut`, each taking the context
+//! of the element, which is bound to `this`:
+//!
+//! ```rhai
+//! // Gives singular string fields presence, except those in oneofs.
+//! fn field(cx) {
+//!     if cx.syntax == "proto3" && this.type == "string"
+//!         && this.label == "optional" && !this.in_oneof {
+//!         this.optional = true;
+//!         this.leading_comments = "Optional since v2.";
+//!     }
+//! }
+//!
+//! fn enum_value(cx) {
+//!     if !this.name.starts_with("STATUS_") {
+//!         this.name = "STATUS_" + this.name;
+//!     }
+//! }
+//! ```
+//!
+//! The functions are `file`, `message`, `field`, `extension`, `oneof`,
+//! `enumeration`, `enum_value`, `service` and `method`. The context has the
+//! `file_name`, `package`, `syntax`, `scope`, `full_name` and `path` of the
+//! element, as in [`Context`]. Every element has `leading_comments`,
+//! `trailing_comments` and `detached_comments`, without `//` markers, and
+//! these properties:
+//!
+//! - file: `name`, `package`, `syntax`, `imports`
+//! - message, oneof, enumeration, service: `name`
+//! - field, extension: `name`, `number`, `type` (e.g. `"string"` or
+//!   `"message"`), `type_name`, `label`, `json_name`, `default_value`,
+//!   `deprecated`, `optional` (proto3 `optional`) and the read-only
+//!   `in_oneof`
+//! - enum value: `name`, `number`, `deprecated`
+//! - method: `name`, `input_type`, `output_type`, `client_streaming`,
+//!   `server_streaming`
+//!
+//! Renaming a message or enum does not update the fields referring to it.
+//! `print` writes to stderr. This needs the `script` feature.
+
+use crate::edit::Editor;
+use crate::path::Path;
+use crate::transform::Pass;
+use crate::visit::{visit_file_mut, Context, VisitMut};
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+    FileDescriptorProto, FileDescriptorSet, MethodDescriptorProto, OneofDescriptorProto,
+    ServiceDescriptorProto,
+};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, ImmutableString, Scope, AST};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+
+// The `VisitMut` methods a script can define.
+const FUNCTIONS: &[&str] = &[
+    "file",
+    "message",
+    "field",
+    "extension",
+    "oneof",
+    "enumeration",
+    "enum_value",
+    "service",
+    "method",
+];
+
+/// A compiled script.
+pub struct Script {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    functions: HashSet<String>,
+}
+
+impl Script {
+    pub fn from_file<P>(path: P) -> io::Result<Script>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        Script::compile(&path.display().to_string(), &source)
+    }
+
+    /// Compiles `source`, naming it `name` in errors.
+    pub fn compile(name: &str, source: &str) -> io::Result<Script> {
+        let engine = engine();
+        let ast = engine
+            .compile(source)
+            .map_err(|err| other(format!("{}: {}", name, err)))?;
+
+        let mut functions = HashSet::new();
+        for function in ast.iter_functions() {
+            if !FUNCTIONS.contains(&function.name) {
+                continue;
+            }
+            if function.params.len() != 1 {
+                return Err(other(format!(
+                    "{}: `{}` must take one parameter, the context",
+                    name, function.name
+                )));
+            }
+            functions.insert(function.name.to_owned());
+        }
+
+        Ok(Script {
+            name: name.to_owned(),
+            engine,
+            ast,
+            functions,
+        })
+    }
+
+    /// Runs the script over every file of `set`, stopping at the first
+    /// error.
+    pub fn run(&self, set: &mut FileDescriptorSet) -> io::Result<()> {
+        for file in &mut set.file {
+            self.run_file(file)?;
+        }
+        Ok(())
+    }
+
+    pub fn run_file(&self, file: &mut FileDescriptorProto) -> io::Result<()> {
+        let comments = file
+            .source_code_info
+            .iter()
+            .flat_map(|info| &info.location)
+            .map(|location| {
+                let comments = Comments {
+                    leading: location
+                        .leading_comments
+                        .as_deref()
+                        .map(comment_text)
+                        .unwrap_or_default(),
+                    trailing: location
+                        .trailing_comments
+                        .as_deref()
+                        .map(comment_text)
+                        .unwrap_or_default(),
+                    detached: location
+                        .leading_detached_comments
+                        .iter()
+                        .map(|c| comment_text(c))
+                        .collect(),
+                };
+                (location.path.clone(), comments)
+            })
+            .collect();
+
+        let mut visitor = Visitor {
+            script: self,
+            comments,
+            edits: Vec::new(),
+            error: None,
+        };
+        visit_file_mut(&mut visitor, file);
+        if let Some(err) = visitor.error {
+            return Err(err);
+        }
+
+        let mut editor = Editor::new(file);
+        for (path, comments) in visitor.edits {
+            editor.clear_comments(&path);
+            if !comments.leading.is_empty() {
+                editor.set_leading_comment(&path, &comments.leading);
+            }
+            if !comments.trailing.is_empty() {
+                editor.set_trailing_comment(&path, &comments.trailing);
+            }
+            for detached in &comments.detached {
+                editor.add_detached_comment(&path, detached);
+            }
+        }
+
+        for message in &mut file.message_type {
+            fix_synthetic_oneofs(message);
+        }
+        Ok(())
+    }
+}
+
+impl Pass for Script {
+    fn run(&mut self, set: &mut FileDescriptorSet) -> io::Result<()> {
+        Script::run(self, set)
+    }
+}
+
+/// An element along with its comments, which is `this` in a script.
+#[derive(Clone)]
+struct Element<T> {
+    descriptor: T,
+    comments: Comments,
+}
+
+#[derive(Clone, Default, PartialEq)]
+struct Comments {
+    leading: String,
+    trailing: String,
+    detached: Vec<String>,
+}
+
+/// What a script gets of the [`Context`].
+#[derive(Clone)]
+struct ScriptContext {
+    file_name: String,
+    package: String,
+    syntax: String,
+    scope: String,
+    full_name: String,
+    path: String,
+}
+
+struct Visitor<'s> {
+    script: &'s Script,
+    comments: HashMap<Vec<i32>, Comments>,
+    edits: Vec<(Path, Comments)>,
+    error: Option<io::Error>,
+}
+
+impl Visitor<'_> {
+    // Calls the script function `name` with `descriptor` as `this`, if the
+    // script defines it.
+    fn call<T: Clone + Send + Sync + 'static>(
+        &mut self,
+        name: &str,
+        descriptor: &mut T,
+        cx: &Context,
+    ) {
+        if self.error.is_some() || !self.script.functions.contains(name) {
+            return;
+        }
+
+        let comments = self
+            .comments
+            .get(cx.path().as_slice())
+            .cloned()
+            .unwrap_or_default();
+        let mut this = Dynamic::from(Element {
+            descriptor: descriptor.clone(),
+            comments: comments.clone(),
+        });
+        let script_cx = ScriptContext {
+            file_name: cx.file_name().to_owned(),
+            package: cx.package().to_owned(),
+            syntax: match cx.syntax() {
+                prost_types::Syntax::Proto3 => "proto3".to_owned(),
+                prost_types::Syntax::Proto2 => "proto2".to_owned(),
+            },
+            scope: cx.scope().to_owned(),
+            full_name: cx.full_name().to_owned(),
+            path: cx.path().to_string(),
+        };
+
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut this);
+        let result = self.script.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.script.ast,
+            name,
+            (script_cx,),
+        );
+        if let Err(err) = result {
+            self.error = Some(other(format!(
+                "{}: {} {}: {}",
+                self.script.name,
+                name,
+                cx.full_name(),
+                err
+            )));
+            return;
+        }
+
+        // The script may have assigned something else to `this`.
+        match this.try_cast::<Element<T>>() {
+            Some(element) => {
+                *descriptor = element.descriptor;
+                if element.comments != comments {
+                    self.edits.push((cx.path().clone(), element.comments));
+                }
+            }
+            None => {
+                self.error = Some(other(format!(
+                    "{}: {} {}: `this` was replaced",
+                    self.script.name,
+                    name,
+                    cx.full_name()
+                )));
+            }
+        }
+    }
+}
+
+impl VisitMut for Visitor<'_> {
+    fn file(&mut self, file: &mut FileDescriptorProto, cx: &Context) {
+        self.call("file", file, cx);
+    }
+
+    fn message(&mut self, message: &mut DescriptorProto, cx: &Context) {
+        self.call("message", message, cx);
+    }
+
+    fn field(&mut self, field: &mut FieldDescriptorProto, cx: &Context) {
+        self.call("field", field, cx);
+    }
+
+    fn extension(&mut self, extension: &mut FieldDescriptorProto, cx: &Context) {
+        self.call("extension", extension, cx);
+    }
+
+    fn oneof(&mut self, oneof: &mut OneofDescriptorProto, cx: &Context) {
+        self.call("oneof", oneof, cx);
+    }
+
+    fn enumeration(&mut self, enumeration: &mut EnumDescriptorProto, cx: &Context) {
+        self.call("enumeration", enumeration, cx);
+    }
+
+    fn enum_value(&mut self, value: &mut EnumValueDescriptorProto, cx: &Context) {
+        self.call("enum_value", value, cx);
+    }
+
+    fn service(&mut self, service: &mut ServiceDescriptorProto, cx: &Context) {
+        self.call("service", service, cx);
+    }
+
+    fn method(&mut self, method: &mut MethodDescriptorProto, cx: &Context) {
+        self.call("method", method, cx);
+    }
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|text| eprintln!("{}", text));
+
+    engine
+        .register_type_with_name::<ScriptContext>("Context")
+        .register_get("file_name", |cx: &mut ScriptContext| cx.file_name.clone())
+        .register_get("package", |cx: &mut ScriptContext| cx.package.clone())
+        .register_get("syntax", |cx: &mut ScriptContext| cx.syntax.clone())
+        .register_get("scope", |cx: &mut ScriptContext| cx.scope.clone())
+        .register_get("full_name", |cx: &mut ScriptContext| cx.full_name.clone())
+        .register_get("path", |cx: &mut ScriptContext| cx.path.clone());
+
+    element::<FileDescriptorProto>(&mut engine, "File");
+    string::<FileDescriptorProto>(&mut engine, "name", |f| f.name(), |f, v| f.name = v);
+    string::<FileDescriptorProto>(
+        &mut engine,
+        "package",
+        |f| f.package(),
+        |f, v| f.package = v,
+    );
+    string::<FileDescriptorProto>(&mut engine, "syntax", |f| f.syntax(), |f, v| f.syntax = v);
+    engine.register_get_set(
+        "imports",
+        |f: &mut Element<FileDescriptorProto>| -> Array {
+            f.descriptor
+                .dependency
+                .iter()
+                .map(|d| Dynamic::from(d.clone()))
+                .collect()
+        },
+        |f: &mut Element<FileDescriptorProto>, imports: Array| -> ScriptResult<()> {
+            f.descriptor.dependency = strings(imports)?;
+            Ok(())
+        },
+    );
+
+    element::<DescriptorProto>(&mut engine, "Message");
+    string::<DescriptorProto>(&mut engine, "name", |m| m.name(), |m, v| m.name = v);
+
+    element::<FieldDescriptorProto>(&mut engine, "Field");
+    engine.register_get_set(
+        "name",
+        |f: &mut Element<FieldDescriptorProto>| -> ImmutableString { f.descriptor.name().into() },
+        |f: &mut Element<FieldDescriptorProto>, name: ImmutableString| {
+            crate::builder::rename_field(&mut f.descriptor, name.into());
+        },
+    );
+    number::<FieldDescriptorProto>(&mut engine, |f| f.number(), |f, v| f.number = Some(v));
+    engine.register_get_set(
+        "type",
+        |f: &mut Element<FieldDescriptorProto>| -> String {
+            lowercase_name(f.descriptor.r#type().as_str_name(), "TYPE_")
+        },
+        |f: &mut Element<FieldDescriptorProto>, name: String| -> ScriptResult<()> {
+            let typ = Type::from_str_name(&format!("TYPE_{}", name.to_ascii_uppercase()))
+                .ok_or_else(|| format!("unknown field type `{}`", name))?;
+            f.descriptor.set_type(typ);
+            if !matches!(typ, Type::Message | Type::Enum | Type::Group) {
+                f.descriptor.type_name = None;
+            }
+            Ok(())
+        },
+    );
+    string::<FieldDescriptorProto>(
+        &mut engine,
+        "type_name",
+        |f| f.type_name(),
+        |f, v| f.type_name = v,
+    );
+    engine.register_get_set(
+        "label",
+        |f: &mut Element<FieldDescriptorProto>| -> String {
+            lowercase_name(f.descriptor.label().as_str_name(), "LABEL_")
+        },
+        |f: &mut Element<FieldDescriptorProto>, name: String| -> ScriptResult<()> {
+            let label = Label::from_str_name(&format!("LABEL_{}", name.to_ascii_uppercase()))
+                .ok_or_else(|| format!("unknown label `{}`", name))?;
+            f.descriptor.set_label(label);
+            Ok(())
+        },
+    );
+    string::<FieldDescriptorProto>(
+        &mut engine,
+        "json_name",
+        |f| f.json_name(),
+        |f, v| f.json_name = v,
+    );
+    string::<FieldDescriptorProto>(
+        &mut engine,
+        "default_value",
+        |f| f.default_value(),
+        |f, v| f.default_value = v,
+    );
+    engine.register_get_set(
+        "deprecated",
+        |f: &mut Element<FieldDescriptorProto>| {
+            f.descriptor
+                .options
+                .as_ref()
+                .is_some_and(|o| o.deprecated())
+        },
+        |f: &mut Element<FieldDescriptorProto>, deprecated: bool| {
+            f.descriptor
+                .options
+                .get_or_insert_with(Default::default)
+                .deprecated = Some(deprecated);
+        },
+    );
+    engine.register_get_set(
+        "optional",
+        |f: &mut Element<FieldDescriptorProto>| f.descriptor.proto3_optional(),
+        |f: &mut Element<FieldDescriptorProto>, optional: bool| -> ScriptResult<()> {
+            let field = &mut f.descriptor;
+            if optional == field.proto3_optional() {
+                return Ok(());
+            }
+            if field.label() == Label::Repeated {
+                return Err("repeated fields cannot be optional".into());
+            }
+            if field.oneof_index.is_some() && !field.proto3_optional() {
+                return Err("fields of oneofs cannot be optional".into());
+            }
+            // The synthetic oneof is added or removed afterwards.
+            field.proto3_optional = if optional { Some(true) } else { None };
+            field.oneof_index = None;
+            Ok(())
+        },
+    );
+    engine.register_get("in_oneof", |f: &mut Element<FieldDescriptorProto>| {
+        f.descriptor.oneof_index.is_some() && !f.descriptor.proto3_optional()
+    });
+
+    element::<OneofDescriptorProto>(&mut engine, "Oneof");
+    string::<OneofDescriptorProto>(&mut engine, "name", |o| o.name(), |o, v| o.name = v);
+
+    element::<EnumDescriptorProto>(&mut engine, "Enum");
+    string::<EnumDescriptorProto>(&mut engine, "name", |e| e.name(), |e, v| e.name = v);
+
+    element::<EnumValueDescriptorProto>(&mut engine, "EnumValue");
+    string::<EnumValueDescriptorProto>(&mut engine, "name", |v| v.name(), |v, name| v.name = name);
+    number::<EnumValueDescriptorProto>(&mut engine, |v| v.number(), |v, n| v.number = Some(n));
+    engine.register_get_set(
+        "deprecated",
+        |v: &mut Element<EnumValueDescriptorProto>| {
+            v.descriptor
+                .options
+                .as_ref()
+                .is_some_and(|o| o.deprecated())
+        },
+        |v: &mut Element<EnumValueDescriptorProto>, deprecated: bool| {
+            v.descriptor
+                .options
+                .get_or_insert_with(Default::default)
+                .deprecated = Some(deprecated);
+        },
+    );
+
+    element::<ServiceDescriptorProto>(&mut engine, "Service");
+    string::<ServiceDescriptorProto>(&mut engine, "name", |s| s.name(), |s, v| s.name = v);
+
+    element::<MethodDescriptorProto>(&mut engine, "Method");
+    string::<MethodDescriptorProto>(&mut engine, "name", |m| m.name(), |m, v| m.name = v);
+    string::<MethodDescriptorProto>(
+        &mut engine,
+        "input_type",
+        |m| m.input_type(),
+        |m, v| m.input_type = v,
+    );
+    string::<MethodDescriptorProto>(
+        &mut engine,
+        "output_type",
+        |m| m.output_type(),
+        |m, v| m.output_type = v,
+    );
+    engine.register_get_set(
+        "client_streaming",
+        |m: &mut Element<MethodDescriptorProto>| m.descriptor.client_streaming(),
+        |m: &mut Element<MethodDescriptorProto>, streaming: bool| {
+            m.descriptor.client_streaming = Some(streaming)
+        },
+    );
+    engine.register_get_set(
+        "server_streaming",
+        |m: &mut Element<MethodDescriptorProto>| m.descriptor.server_streaming(),
+        |m: &mut Element<MethodDescriptorProto>, streaming: bool| {
+            m.descriptor.server_streaming = Some(streaming)
+        },
+    );
+
+    engine
+}
+
+// Registers `Element<T>` as `name`, with its comment properties.
+fn element<T: Clone + Send + Sync + 'static>(engine: &mut Engine, name: &str) {
+    engine
+        .register_type_with_name::<Element<T>>(name)
+        .register_get_set(
+            "leading_comments",
+            |e: &mut Element<T>| e.comments.leading.clone(),
+            |e: &mut Element<T>, text: String| e.comments.leading = text,
+        )
+        .register_get_set(
+            "trailing_comments",
+            |e: &mut Element<T>| e.comments.trailing.clone(),
+            |e: &mut Element<T>, text: String| e.comments.trailing = text,
+        )
+        .register_get_set(
+            "detached_comments",
+            |e: &mut Element<T>| -> Array {
+                e.comments
+                    .detached
+                    .iter()
+                    .map(|c| Dynamic::from(c.clone()))
+                    .collect()
+            },
+            |e: &mut Element<T>, comments: Array| -> ScriptResult<()> {
+                e.comments.detached = strings(comments)?;
+                Ok(())
+            },
+        );
+}
+
+// Registers a string property of `T`. Setting it to the empty string
+// clears it.
+fn string<T: Clone + Send + Sync + 'static>(
+    engine: &mut Engine,
+    name: &str,
+    get: fn(&T) -> &str,
+    set: fn(&mut T, Option<String>),
+) {
+    engine.register_get_set(
+        name,
+        move |e: &mut Element<T>| -> ImmutableString { get(&e.descriptor).into() },
+        move |e: &mut Element<T>, value: ImmutableString| {
+            let value = if value.is_empty() {
+                None
+            } else {
+                Some(value.into())
+            };
+            set(&mut e.descriptor, value)
+        },
+    );
+}
+
+// Registers the `number` property of `T`.
+fn number<T: Clone + Send + Sync + 'static>(
+    engine: &mut Engine,
+    get: fn(&T) -> i32,
+    set: fn(&mut T, i32),
+) {
+    engine.register_get_set(
+        "number",
+        move |e: &mut Element<T>| get(&e.descriptor) as rhai::INT,
+        move |e: &mut Element<T>, number: rhai::INT| -> ScriptResult<()> {
+            let number =
+                i32::try_from(number).map_err(|_| format!("{} is out of range", number))?;
+            set(&mut e.descriptor, number);
+            Ok(())
+        },
+    );
+}
+
+fn strings(array: Array) -> ScriptResult<Vec<String>> {
+    array
+        .into_iter()
+        .map(|value| {
+            value
+                .into_string()
+                .map_err(|typ| format!("expected a string, not {}", typ).into())
+        })
+        .collect()
+}
+
+// `TYPE_STRING` as `string`.
+fn lowercase_name(name: &str, prefix: &str) -> String {
+    name.trim_start_matches(prefix).to_ascii_lowercase()
+}
+
+// A comment from `SourceCodeInfo` without the space after each `//`, as
+// it is written.
+fn comment_text(comment: &str) -> String {
+    comment
+        .lines()
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Removes the oneofs no field is in, left by fields that are no longer
+// `optional`, and adds a synthetic oneof for each new `optional` field.
+fn fix_synthetic_oneofs(message: &mut DescriptorProto) {
+    let mut used = vec![false; message.oneof_decl.len()];
+    for index in message.field.iter().filter_map(|f| f.oneof_index) {
+        if let Some(used) = used.get_mut(index as usize) {
+            *used = true;
+        }
+    }
+    if used.contains(&false) {
+        let mut renumbered = Vec::with_capacity(used.len());
+        let mut next = 0;
+        for &used in &used {
+            renumbered.push(next);
+            if used {
+                next += 1;
+            }
+        }
+        for field in &mut message.field {
+            if let Some(ref mut index) = field.oneof_index {
+                *index = renumbered[*index as usize];
+            }
+        }
+        let mut used = used.into_iter();
+        message.oneof_decl.retain(|_| used.next().unwrap_or(true));
+    }
+
+    for i in 0..message.field.len() {
+        if message.field[i].proto3_optional() {
+            crate::builder::make_proto3_optional(message, i);
+        }
+    }
+
+    for nested in &mut message.nested_type {
+        fix_synthetic_oneofs(nested);
+    }
+}
+
+fn other(msg: String) -> io::Error {
+    io::Error::other(msg)
+}
@@ -0,0 +1,96 @@
+// Records a `SourceCodeInfo` for the text being generated.
+//
+// Locations are pushed when an element starts, so that they end up in the
+// order protoc emits them, and their span is filled in when it ends. Lines
+// and columns are zero-based, and tabs advance to the next multiple of 8
+// columns, as protoc counts them.
+
+use prost_types::source_code_info::Location;
+
+#[derive(Default)]
+pub(crate) struct Spans {
+    locations: Vec<Location>,
+    // The part of the output already scanned for line breaks.
+    scanned: usize,
+    line: i32,
+    line_start: usize,
+    // The comments just written, for the element that follows them.
+    leading_comment: Option<String>,
+    detached_comments: Vec<String>,
+}
+
+impl Spans {
+    // Starts a location for the element at `path`, returning its index.
+    pub(crate) fn begin(&mut self, buf: &str, path: &[i32]) -> usize {
+        let (line, column) = self.position(buf);
+        self.locations.push(Location {
+            path: path.to_vec(),
+            span: vec![line, column],
+            leading_comments: self.leading_comment.take(),
+            leading_detached_comments: std::mem::take(&mut self.detached_comments),
+            ..Default::default()
+        });
+        self.locations.len() - 1
+    }
+
+    // Moves the start of the location at `index` to the end of `buf`, past
+    // what was written since it began.
+    pub(crate) fn restart(&mut self, buf: &str, index: usize) {
+        let (line, column) = self.position(buf);
+        self.locations[index].span = vec![line, column];
+    }
+
+    pub(crate) fn end(&mut self, buf: &str, index: usize) {
+        let (line, column) = self.position(buf);
+        let span = &mut self.locations[index].span;
+        if span[0] != line {
+            span.push(line);
+        }
+        span.push(column);
+    }
+
+    pub(crate) fn set_leading_comment(&mut self, comment: String) {
+        self.leading_comment = Some(comment);
+    }
+
+    pub(crate) fn add_detached_comment(&mut self, comment: String) {
+        self.detached_comments.push(comment);
+    }
+
+    pub(crate) fn set_trailing_comment(&mut self, index: usize, comment: String) {
+        self.locations[index].trailing_comments = Some(comment);
+    }
+
+    // Accounts for `buf[..len]` being removed from the output buffer.
+    pub(crate) fn drain(&mut self, buf: &str, len: usize) {
+        self.position(buf);
+        self.scanned -= len;
+        self.line_start -= len;
+    }
+
+    pub(crate) fn finish(self) -> prost_types::SourceCodeInfo {
+        prost_types::SourceCodeInfo {
+            location: self.locations,
+        }
+    }
+
+    // The line and column at the end of `buf`.
+    fn position(&mut self, buf: &str) -> (i32, i32) {
+        for (i, b) in buf[self.scanned..].bytes().enumerate() {
+            if b == b'\n' {
+                self.line += 1;
+                self.line_start = self.scanned + i + 1;
+            }
+        }
+        self.scanned = buf.len();
+
+        let mut column = 0;
+        for c in buf[self.line_start..].chars() {
+            column = match c {
+                '\t' => column + 8 - column % 8,
+                _ => column + 1,
+            };
+        }
+        (self.line, column)
+    }
+}
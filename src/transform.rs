@@ -0,0 +1,116 @@
+//! Ordered transformation passes over descriptor sets.
+//!
+//! A [`Pass`] changes a `FileDescriptorSet` in place; [`Passes`] runs
+//! several in the order they were added, e.g. before rendering:
+//!
+//! ```
+//! use prost_types::FileDescriptorSet;
+//! use protobuf_gen::transform::Passes;
+//! use protobuf_gen::visit::{Context, VisitMut};
+//!
+//! struct Deprecate;
+//!
+//! impl VisitMut for Deprecate {
+//!     fn message(&mut self, message: &mut prost_types::DescriptorProto, _: &Context) {
+//!         message.options.get_or_insert_with(Default::default).deprecated = Some(true);
+//!     }
+//! }
+//!
+//! let mut set = FileDescriptorSet {
+//!     file: vec![protobuf_gen::FileBuilder::new("user.proto").message("User", |m| m).build()],
+//! };
+//! Passes::new()
+//!     .visit(Deprecate)
+//!     .then(|set: &mut FileDescriptorSet| {
+//!         set.file.retain(|file| !file.message_type.is_empty());
+//!         Ok(())
+//!     })
+//!     .run(&mut set)?;
+//! assert_eq!(Some(true), set.file[0].message_type[0].options.as_ref().unwrap().deprecated);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::visit::{visit_set_mut, VisitMut};
+use prost_types::{FileDescriptorProto, FileDescriptorSet};
+use std::io;
+
+/// A transformation of a descriptor set.
+pub trait Pass {
+    fn run(&mut self, set: &mut FileDescriptorSet) -> io::Result<()>;
+}
+
+impl<F> Pass for F
+where
+    F: FnMut(&mut FileDescriptorSet) -> io::Result<()>,
+{
+    fn run(&mut self, set: &mut FileDescriptorSet) -> io::Result<()> {
+        self(set)
+    }
+}
+
+/// Runs a [`VisitMut`] over every file of the set as a pass.
+pub struct Visit<V>(pub V);
+
+impl<V: VisitMut> Pass for Visit<V> {
+    fn run(&mut self, set: &mut FileDescriptorSet) -> io::Result<()> {
+        visit_set_mut(&mut self.0, set);
+        Ok(())
+    }
+}
+
+/// Passes run one after the other, which is itself a pass.
+#[derive(Default)]
+pub struct Passes {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Passes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `pass` to run after those added before it.
+    pub fn then<P: Pass + 'static>(mut self, pass: P) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Adds a pass visiting every file with `visitor`.
+    pub fn visit<V: VisitMut + 'static>(self, visitor: V) -> Self {
+        self.then(Visit(visitor))
+    }
+
+    pub fn len(&self) -> usize {
+        self.passes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// Runs the passes in order, stopping at the first that fails.
+    pub fn run(&mut self, set: &mut FileDescriptorSet) -> io::Result<()> {
+        for pass in &mut self.passes {
+            pass.run(set)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the passes over a set of just `file`.
+    pub fn run_file(&mut self, file: &mut FileDescriptorProto) -> io::Result<()> {
+        let mut set = FileDescriptorSet {
+            file: vec![std::mem::take(file)],
+        };
+        let result = self.run(&mut set);
+        if let Some(transformed) = set.file.pop() {
+            *file = transformed;
+        }
+        result
+    }
+}
+
+impl Pass for Passes {
+    fn run(&mut self, set: &mut FileDescriptorSet) -> io::Result<()> {
+        Passes::run(self, set)
+    }
+}
@@ -0,0 +1,255 @@
+//! Visiting every element of descriptors mutably.
+//!
+//! A [`VisitMut`] has a method for each kind of element, which
+//! [`visit_file_mut`] and [`visit_set_mut`] call for every element of that
+//! kind, wherever it is nested: fields in oneofs and nested messages,
+//! extensions declared in files and messages, enums nested in messages.
+//! Each call gets a [`Context`] with the element's fully-qualified name, its
+//! scope, its path and the syntax of its file:
+//!
+//! ```
+//! use prost_types::field_descriptor_proto::{Label, Type};
+//! use prost_types::DescriptorProto;
+//! use protobuf_gen::builder::make_proto3_optional;
+//! use protobuf_gen::visit::{visit_file_mut, Context, VisitMut};
+//!
+//! // Gives singular string fields presence, except those in oneofs, which
+//! // already have it. Each needs a synthetic oneof in its message.
+//! struct StringsToOptional;
+//!
+//! impl VisitMut for StringsToOptional {
+//!     fn message(&mut self, message: &mut DescriptorProto, cx: &Context) {
+//!         if cx.syntax() != prost_types::Syntax::Proto3 {
+//!             return;
+//!         }
+//!         for i in 0..message.field.len() {
+//!             let field = &message.field[i];
+//!             if field.r#type() == Type::String
+//!                 && field.label() != Label::Repeated
+//!                 && field.oneof_index.is_none()
+//!             {
+//!                 make_proto3_optional(message, i);
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! let mut file = protobuf_gen::FileBuilder::new("user.proto")
+//!     .message("User", |m| m.message("Name", |m| m.field("first", protobuf_gen::builder::FieldType::String, 1)))
+//!     .build();
+//! visit_file_mut(&mut StringsToOptional, &mut file);
+//! let name = &file.message_type[0].nested_type[0];
+//! assert_eq!(Some(true), name.field[0].proto3_optional);
+//! assert_eq!(Some("_first"), name.oneof_decl[0].name.as_deref());
+//! ```
+//!
+//! The methods are called for an element before the elements in it, so
+//! that elements added to it are visited too.
+
+use crate::path::Path;
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+    FileDescriptorProto, FileDescriptorSet, MethodDescriptorProto, OneofDescriptorProto,
+    ServiceDescriptorProto, Syntax,
+};
+
+/// Callbacks for each kind of element, which do nothing by default.
+#[allow(unused_variables)]
+pub trait VisitMut {
+    fn file(&mut self, file: &mut FileDescriptorProto, cx: &Context) {}
+
+    fn message(&mut self, message: &mut DescriptorProto, cx: &Context) {}
+
+    /// A field of a message, including those in oneofs.
+    fn field(&mut self, field: &mut FieldDescriptorProto, cx: &Context) {}
+
+    /// An extension, declared in a file or in a message, which is its scope.
+    fn extension(&mut self, extension: &mut FieldDescriptorProto, cx: &Context) {}
+
+    fn oneof(&mut self, oneof: &mut OneofDescriptorProto, cx: &Context) {}
+
+    fn enumeration(&mut self, enumeration: &mut EnumDescriptorProto, cx: &Context) {}
+
+    fn enum_value(&mut self, value: &mut EnumValueDescriptorProto, cx: &Context) {}
+
+    fn service(&mut self, service: &mut ServiceDescriptorProto, cx: &Context) {}
+
+    fn method(&mut self, method: &mut MethodDescriptorProto, cx: &Context) {}
+}
+
+/// Where the element being visited is.
+///
+/// Names are fully qualified with a leading dot, as in the `type_name` of
+/// fields, e.g. `.acme.User.name` for a field `name` of a message `User` in
+/// the package `acme`. Like protoc, enum values are scoped alongside their
+/// enum rather than in it, so a value `ACTIVE` of `acme.Status` is
+/// `.acme.ACTIVE`.
+pub struct Context<'a> {
+    file_name: &'a str,
+    package: &'a str,
+    syntax: Syntax,
+    scope: &'a str,
+    full_name: &'a str,
+    path: &'a Path,
+}
+
+impl<'a> Context<'a> {
+    /// The name of the file the element is in.
+    pub fn file_name(&self) -> &'a str {
+        self.file_name
+    }
+
+    pub fn package(&self) -> &'a str {
+        self.package
+    }
+
+    /// The syntax of the file, which is proto2 unless it says otherwise.
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    /// The fully-qualified name of the package, message, enum or service
+    /// the element is declared in, or the empty string for a file or an
+    /// element of a file without a package.
+    pub fn scope(&self) -> &'a str {
+        self.scope
+    }
+
+    /// The fully-qualified name of the element; for a file, that of its
+    /// package.
+    pub fn full_name(&self) -> &'a str {
+        self.full_name
+    }
+
+    /// The path of the element in its file's `SourceCodeInfo`.
+    pub fn path(&self) -> &'a Path {
+        self.path
+    }
+}
+
+/// Visits every file of `set`, in order.
+pub fn visit_set_mut<V: VisitMut + ?Sized>(visitor: &mut V, set: &mut FileDescriptorSet) {
+    for file in &mut set.file {
+        visit_file_mut(visitor, file);
+    }
+}
+
+/// Visits `file` and every element in it.
+pub fn visit_file_mut<V: VisitMut + ?Sized>(visitor: &mut V, file: &mut FileDescriptorProto) {
+    let file_name = file.name().to_owned();
+    let package = file.package().to_owned();
+    let syntax = match file.syntax() {
+        "proto3" => Syntax::Proto3,
+        _ => Syntax::Proto2,
+    };
+    let scope = match package.as_str() {
+        "" => String::new(),
+        package => format!(".{}", package),
+    };
+    let mut walker = Walker {
+        visitor,
+        file_name: &file_name,
+        package: &package,
+        syntax,
+    };
+
+    let path = Path::file();
+    walker.visit(|v, cx| v.file(file, cx), "", &scope, &path);
+    for (i, message) in file.message_type.iter_mut().enumerate() {
+        walker.message(message, &scope, path.clone().message(i as i32));
+    }
+    for (i, enumeration) in file.enum_type.iter_mut().enumerate() {
+        walker.enumeration(enumeration, &scope, path.clone().enumeration(i as i32));
+    }
+    for (i, service) in file.service.iter_mut().enumerate() {
+        walker.service(service, &scope, path.clone().service(i as i32));
+    }
+    for (i, extension) in file.extension.iter_mut().enumerate() {
+        let full_name = format!("{}.{}", scope, extension.name());
+        let path = path.clone().extension(i as i32);
+        walker.visit(
+            |v, cx| v.extension(extension, cx),
+            &scope,
+            &full_name,
+            &path,
+        );
+    }
+}
+
+struct Walker<'v, 'a, V: ?Sized> {
+    visitor: &'v mut V,
+    file_name: &'a str,
+    package: &'a str,
+    syntax: Syntax,
+}
+
+impl<V: VisitMut + ?Sized> Walker<'_, '_, V> {
+    fn visit<F>(&mut self, f: F, scope: &str, full_name: &str, path: &Path)
+    where
+        F: FnOnce(&mut V, &Context),
+    {
+        let cx = Context {
+            file_name: self.file_name,
+            package: self.package,
+            syntax: self.syntax,
+            scope,
+            full_name,
+            path,
+        };
+        f(self.visitor, &cx);
+    }
+
+    fn message(&mut self, message: &mut DescriptorProto, scope: &str, path: Path) {
+        let full_name = format!("{}.{}", scope, message.name());
+        self.visit(|v, cx| v.message(message, cx), scope, &full_name, &path);
+
+        for (i, field) in message.field.iter_mut().enumerate() {
+            let name = format!("{}.{}", full_name, field.name());
+            let path = path.clone().field(i as i32);
+            self.visit(|v, cx| v.field(field, cx), &full_name, &name, &path);
+        }
+        for (i, extension) in message.extension.iter_mut().enumerate() {
+            let name = format!("{}.{}", full_name, extension.name());
+            let path = path.clone().extension(i as i32);
+            self.visit(|v, cx| v.extension(extension, cx), &full_name, &name, &path);
+        }
+        for (i, oneof) in message.oneof_decl.iter_mut().enumerate() {
+            let name = format!("{}.{}", full_name, oneof.name());
+            let path = path.clone().oneof(i as i32);
+            self.visit(|v, cx| v.oneof(oneof, cx), &full_name, &name, &path);
+        }
+        for (i, nested) in message.nested_type.iter_mut().enumerate() {
+            self.message(nested, &full_name, path.clone().message(i as i32));
+        }
+        for (i, enumeration) in message.enum_type.iter_mut().enumerate() {
+            self.enumeration(enumeration, &full_name, path.clone().enumeration(i as i32));
+        }
+    }
+
+    fn enumeration(&mut self, enumeration: &mut EnumDescriptorProto, scope: &str, path: Path) {
+        let full_name = format!("{}.{}", scope, enumeration.name());
+        self.visit(
+            |v, cx| v.enumeration(enumeration, cx),
+            scope,
+            &full_name,
+            &path,
+        );
+
+        for (i, value) in enumeration.value.iter_mut().enumerate() {
+            let name = format!("{}.{}", scope, value.name());
+            let path = path.clone().value(i as i32);
+            self.visit(|v, cx| v.enum_value(value, cx), scope, &name, &path);
+        }
+    }
+
+    fn service(&mut self, service: &mut ServiceDescriptorProto, scope: &str, path: Path) {
+        let full_name = format!("{}.{}", scope, service.name());
+        self.visit(|v, cx| v.service(service, cx), scope, &full_name, &path);
+
+        for (i, method) in service.method.iter_mut().enumerate() {
+            let name = format!("{}.{}", full_name, method.name());
+            let path = path.clone().method(i as i32);
+            self.visit(|v, cx| v.method(method, cx), &full_name, &name, &path);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// This file is @generated by prost-build.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct User {
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+    #[prost(string, repeated, tag = "2")]
+    pub tags: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(map = "string, message", tag = "3")]
+    pub addresses: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        user::Address,
+    >,
+    #[prost(enumeration = "Status", optional, tag = "4")]
+    pub status: ::core::option::Option<i32>,
+    #[deprecated]
+    #[prost(message, optional, tag = "7")]
+    pub home: ::core::option::Option<user::Address>,
+    #[prost(enumeration = "user::Kind", tag = "8")]
+    pub kind: i32,
+    #[prost(map = "string, enumeration(user::Kind)", tag = "9")]
+    pub kinds: ::std::collections::HashMap<::prost::alloc::string::String, i32>,
+    #[prost(bytes = "vec", optional, tag = "10")]
+    pub avatar: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    #[prost(message, optional, tag = "11")]
+    pub created_at: ::core::option::Option<::prost_types::Timestamp>,
+    #[prost(message, repeated, tag = "12")]
+    pub friends: ::prost::alloc::vec::Vec<User>,
+    #[prost(message, optional, boxed, tag = "13")]
+    pub manager: ::core::option::Option<::prost::alloc::boxed::Box<User>>,
+    #[prost(string, tag = "14")]
+    pub r#type: ::prost::alloc::string::String,
+    #[prost(oneof = "user::Contact", tags = "5, 6")]
+    pub contact: ::core::option::Option<user::Contact>,
+}
+/// Nested message and enum types in `User`.
+pub mod user {
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Address {
+        #[prost(string, tag = "1")]
+        pub city: ::prost::alloc::string::String,
+        #[prost(int32, repeated, packed = "false", tag = "2")]
+        pub lines: ::prost::alloc::vec::Vec<i32>,
+    }
+    #[derive(
+        Clone,
+        Copy,
+        Debug,
+        PartialEq,
+        Eq,
+        Hash,
+        PartialOrd,
+        Ord,
+        ::prost::Enumeration
+    )]
+    #[repr(i32)]
+    pub enum Kind {
+        Unknown = 0,
+        Admin = 1,
+    }
+    impl Kind {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Self::Unknown => "KIND_UNKNOWN",
+                Self::Admin => "KIND_ADMIN",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "KIND_UNKNOWN" => Some(Self::Unknown),
+                "KIND_ADMIN" => Some(Self::Admin),
+                _ => None,
+            }
+        }
+    }
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Contact {
+        #[prost(string, tag = "5")]
+        Email(::prost::alloc::string::String),
+        #[prost(string, tag = "6")]
+        PhoneNumber(::prost::alloc::string::String),
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Status {
+    Unknown = 0,
+    Active = 1,
+    Deleted = -1,
+}
+impl Status {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unknown => "STATUS_UNKNOWN",
+            Self::Active => "STATUS_ACTIVE",
+            Self::Deleted => "STATUS_DELETED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "STATUS_UNKNOWN" => Some(Self::Unknown),
+            "STATUS_ACTIVE" => Some(Self::Active),
+            "STATUS_DELETED" => Some(Self::Deleted),
+            _ => None,
+        }
+    }
+}
@@ -1,11 +1,13 @@
-use protobuf_gen::{read_files, ProtobufString};
 use pretty_assertions::assert_eq;
+use protobuf_gen::{
+    decode_descriptor_set, read_files, read_sources, try_read_sources, ProtobufString,
+};
 
 #[test]
 fn it_works() {
     let descriptor_set = read_files(&["tests/source.proto"], &["tests/"]);
     for file in descriptor_set.file.iter() {
-        let actual = file.to_protobuf(file.clone());
+        let actual = file.to_protobuf(file);
         let expected = std::fs::read_to_string("tests/expected.proto").unwrap();
         assert_eq!(expected, actual);
     }
@@ -15,8 +17,1844 @@ fn it_works() {
 fn it_is_idempotent() {
     let descriptor_set = read_files(&["tests/expected.proto"], &["tests/"]);
     for file in descriptor_set.file.iter() {
-        let actual = file.to_protobuf(file.clone());
+        let actual = file.to_protobuf(file);
         let expected = std::fs::read_to_string("tests/expected.proto").unwrap();
         assert_eq!(expected, actual);
     }
 }
+
+#[test]
+fn it_renders_losslessly() {
+    let config = protobuf_gen::GeneratorConfig::new().lossless(true);
+    let expected = std::fs::read_to_string("tests/expected_lossless.proto").unwrap();
+    for source in ["tests/source.proto", "tests/expected_lossless.proto"] {
+        let descriptor_set = read_files(&[source], &["tests/"]);
+        let file = &descriptor_set.file[0];
+        assert_eq!(expected, file.to_protobuf_with(file, &config));
+    }
+}
+
+#[test]
+fn it_reads_sources_from_memory() {
+    let mut sources = std::collections::HashMap::new();
+    sources.insert(
+        "acme/user.proto",
+        "syntax = \"proto3\";\npackage acme;\nimport \"source.proto\";\nimport \"acme/types.proto\";\nmessage User {\n  Id id = 1;\n  google.protobuf.FileDescriptorProto file = 2;\n}\n",
+    );
+    sources.insert(
+        "acme/types.proto",
+        "syntax = \"proto3\";\npackage acme;\nmessage Id {\n  int64 value = 1;\n}\n",
+    );
+
+    let descriptor_set = read_sources(&sources, &["tests/"]);
+    let file = descriptor_set
+        .file
+        .iter()
+        .find(|f| f.name() == "acme/user.proto")
+        .unwrap();
+    let actual = file.to_protobuf(file);
+    let expected = "syntax = \"proto3\";\n\npackage acme;\n\nimport \"source.proto\";\nimport \"acme/types.proto\";\n\nmessage User {\n  .acme.Id id = 1;\n  .google.protobuf.FileDescriptorProto file = 2;\n}\n";
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn it_reports_source_errors() {
+    let err = try_read_sources(vec![("user.proto", "message User {")], &["tests/"]).unwrap_err();
+    assert!(err.to_string().contains("protoc failed"), "{}", err);
+
+    let err = try_read_sources(vec![("/tmp/user.proto", "")], &["tests/"]).unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+    let err = try_read_sources(vec![("../user.proto", "")], &["tests/"]).unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+}
+
+#[test]
+fn it_shadows_files_on_disk() {
+    let source = "syntax = \"proto3\";\nmessage Shadow {}\n";
+    let descriptor_set = read_sources(vec![("source.proto", source)], &["tests/"]);
+    assert_eq!("Shadow", descriptor_set.file[0].message_type[0].name());
+}
+
+fn user_proto() -> prost_types::FileDescriptorProto {
+    prost_types::FileDescriptorProto {
+        name: Some("user.proto".to_owned()),
+        package: Some("acme".to_owned()),
+        syntax: Some("proto3".to_owned()),
+        message_type: vec![prost_types::DescriptorProto {
+            name: Some("User".to_owned()),
+            field: vec![prost_types::FieldDescriptorProto {
+                name: Some("name".to_owned()),
+                number: Some(1),
+                label: Some(prost_types::field_descriptor_proto::Label::Optional as i32),
+                r#type: Some(prost_types::field_descriptor_proto::Type::String as i32),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn it_decodes_descriptor_sets() {
+    use prost::Message;
+    use std::io::Write;
+
+    let descriptor_set = prost_types::FileDescriptorSet {
+        file: vec![user_proto()],
+    };
+    let bytes = descriptor_set.encode_to_vec();
+    assert_eq!(descriptor_set, decode_descriptor_set(&bytes).unwrap());
+
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(&bytes).unwrap();
+    let gzipped = gz.finish().unwrap();
+    assert_eq!(descriptor_set, decode_descriptor_set(&gzipped).unwrap());
+
+    assert!(decode_descriptor_set(b"\x0a\xff").is_err());
+
+    let file = &decode_descriptor_set(&gzipped).unwrap().file[0];
+    let expected =
+        "syntax = \"proto3\";\n\npackage acme;\n\n\nmessage User {\n  string name = 1;\n}\n";
+    assert_eq!(expected, file.to_protobuf(file));
+}
+
+#[test]
+fn it_decodes_buf_images() {
+    use prost::Message;
+    use protobuf_gen::image::{decode_image, ImageFileExtension};
+
+    let mut dependency = user_proto();
+    dependency.name = Some("dependency.proto".to_owned());
+
+    let mut image = Vec::new();
+    for (file, is_import) in [(dependency.clone(), true), (user_proto(), false)] {
+        let mut image_file = file.encode_to_vec();
+        let extension = ImageFileExtension {
+            is_import: Some(is_import),
+            ..Default::default()
+        };
+        prost::encoding::message::encode(8042, &extension, &mut image_file);
+        prost::encoding::bytes::encode(1, &image_file, &mut image);
+    }
+
+    let image = decode_image(&image).unwrap();
+    assert_eq!(2, image.file.len());
+    assert_eq!(dependency, image.file[0].descriptor);
+    assert!(image.file[0].is_import());
+    assert!(!image.file[1].is_import());
+    assert_eq!(
+        vec![&user_proto()],
+        image.files_to_generate().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        decode_descriptor_set(&image.clone().into_descriptor_set().encode_to_vec()).unwrap(),
+        image.into_descriptor_set()
+    );
+}
+
+#[test]
+fn it_runs_as_protoc_plugin() {
+    use prost_types::compiler::CodeGeneratorRequest;
+
+    let mut dependency = user_proto();
+    dependency.name = Some("dependency.proto".to_owned());
+    let mut request = CodeGeneratorRequest {
+        file_to_generate: vec!["user.proto".to_owned()],
+        proto_file: vec![dependency, user_proto()],
+        ..Default::default()
+    };
+
+    let response = protobuf_gen::plugin::generate(&request);
+    assert_eq!(None, response.error);
+    assert_eq!(Some(1), response.supported_features);
+    assert_eq!(1, response.file.len());
+    assert_eq!(Some("user.proto"), response.file[0].name.as_deref());
+    assert_eq!(
+        user_proto().to_protobuf(&user_proto()),
+        response.file[0].content()
+    );
+
+    request.parameter = Some("indent=tab,comments=false".to_owned());
+    let response = protobuf_gen::plugin::generate(&request);
+    assert_eq!(None, response.error);
+    assert!(response.file[0]
+        .content()
+        .contains("\n\tstring name = 1;\n"));
+
+    request.parameter = Some("bogus=1".to_owned());
+    let response = protobuf_gen::plugin::generate(&request);
+    assert_eq!(Some("unknown option: bogus"), response.error.as_deref());
+
+    request.parameter = None;
+    request.proto_file[1].syntax = Some("editions".to_owned());
+    let response = protobuf_gen::plugin::generate(&request);
+    assert_eq!(
+        Some("user.proto: syntax \"editions\" cannot be rendered"),
+        response.error.as_deref()
+    );
+    assert!(response.file.is_empty());
+}
+
+#[test]
+fn it_keeps_custom_options_as_protoc_plugin() {
+    let tmp = tempfile::tempdir().unwrap();
+    std::fs::write(
+        tmp.path().join("user.proto"),
+        "syntax = \"proto3\";\nimport \"source.proto\";\nextend google.protobuf.FieldOptions {\n  bool sensitive = 50000;\n}\nmessage User {\n  string name = 1 [(sensitive) = true];\n}\n",
+    )
+    .unwrap();
+    std::fs::create_dir(tmp.path().join("out")).unwrap();
+    let output = std::process::Command::new("protoc")
+        .arg(format!(
+            "--plugin=protoc-gen-protobuf-gen={}",
+            env!("CARGO_BIN_EXE_protoc-gen-protobuf-gen")
+        ))
+        .arg("--protobuf-gen_out")
+        .arg(tmp.path().join("out"))
+        .arg("-I")
+        .arg(tmp.path())
+        .arg("-I")
+        .arg("tests/")
+        .arg(tmp.path().join("user.proto"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let rendered = std::fs::read_to_string(tmp.path().join("out/user.proto")).unwrap();
+    assert!(rendered.contains("  string name = 1 [(.sensitive) = true];\n"));
+}
+
+#[test]
+fn it_renders_sets_into_directories() {
+    use protobuf_gen::{render_set, RenderSetOptions};
+
+    let mut dependency = user_proto();
+    dependency.name = Some("acme/dependency.proto".to_owned());
+    let mut user = user_proto();
+    user.name = Some("acme/user.proto".to_owned());
+    user.dependency = vec!["acme/dependency.proto".to_owned()];
+    let set = prost_types::FileDescriptorSet {
+        file: vec![dependency.clone(), user.clone()],
+    };
+
+    let tmp = tempfile::tempdir().unwrap();
+    let out = tmp.path();
+    std::fs::create_dir_all(out.join("stale/nested")).unwrap();
+    std::fs::write(out.join("stale/nested/old.proto"), "").unwrap();
+    std::fs::write(out.join("README.md"), "").unwrap();
+
+    let options = RenderSetOptions::new()
+        .skip_dependencies(true)
+        .only_changed(true)
+        .remove_stale(true)
+        .threads(2);
+    let report = render_set(&set, out, &options).unwrap();
+    assert_eq!(vec![out.join("acme/user.proto")], report.written);
+    assert_eq!(vec![out.join("stale/nested/old.proto")], report.removed);
+    assert_eq!(
+        user.to_protobuf(&user),
+        std::fs::read_to_string(out.join("acme/user.proto")).unwrap()
+    );
+    assert!(!out.join("acme/dependency.proto").exists());
+    assert!(!out.join("stale").exists());
+    assert!(out.join("README.md").exists());
+
+    let report = render_set(
+        &set,
+        out,
+        &options
+            .clone()
+            .files(["acme/dependency.proto", "acme/user.proto"]),
+    )
+    .unwrap();
+    assert_eq!(vec![out.join("acme/dependency.proto")], report.written);
+    assert_eq!(vec![out.join("acme/user.proto")], report.unchanged);
+
+    let report = render_set(&set, out, &RenderSetOptions::new().files(["missing.proto"]));
+    assert_eq!(std::io::ErrorKind::NotFound, report.unwrap_err().kind());
+}
+
+#[test]
+fn it_discovers_project_config() {
+    use protobuf_gen::config::Config;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let root = tmp.path().canonicalize().unwrap();
+    std::fs::create_dir_all(root.join("protos/acme")).unwrap();
+    std::fs::write(
+        root.join("protobuf-gen.toml"),
+        "includes = [\"protos\"]\nprotoc = \"protoc\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("protos/acme/user.proto"),
+        "syntax = \"proto3\";\npackage acme;\nmessage User { string name = 1; }\n",
+    )
+    .unwrap();
+
+    let (path, config) = Config::discover(root.join("protos/acme/user.proto"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(root.join("protobuf-gen.toml"), path);
+    assert_eq!(vec![root.join("protos")], config.includes);
+    assert_eq!(std::path::Path::new("protoc"), config.protoc());
+
+    let descriptor_set = config
+        .read_files(&[root.join("protos/acme/user.proto")], &[])
+        .unwrap();
+    assert_eq!("acme/user.proto", descriptor_set.file[0].name());
+
+    assert!(Config::from_toml("include = []").is_err());
+    assert_eq!(None, Config::discover("/").unwrap());
+}
+
+fn commented_user_proto() -> prost_types::FileDescriptorProto {
+    use prost_types::source_code_info::Location;
+
+    let mut file = user_proto();
+    file.message_type[0]
+        .field
+        .push(prost_types::FieldDescriptorProto {
+            name: Some("id".to_owned()),
+            number: Some(2),
+            label: Some(prost_types::field_descriptor_proto::Label::Optional as i32),
+            r#type: Some(prost_types::field_descriptor_proto::Type::Int64 as i32),
+            ..Default::default()
+        });
+    file.enum_type.push(prost_types::EnumDescriptorProto {
+        name: Some("Kind".to_owned()),
+        value: vec![prost_types::EnumValueDescriptorProto {
+            name: Some("KIND_UNSPECIFIED".to_owned()),
+            number: Some(0),
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+    file.source_code_info = Some(prost_types::SourceCodeInfo {
+        location: vec![
+            Location {
+                path: vec![4, 0],
+                leading_comments: Some(" A user.\n".to_owned()),
+                ..Default::default()
+            },
+            Location {
+                path: vec![4, 0, 2, 1],
+                leading_comments: Some(" Primary key.\n".to_owned()),
+                ..Default::default()
+            },
+        ],
+    });
+    file
+}
+
+#[test]
+fn it_applies_generator_config() {
+    use protobuf_gen::{GeneratorConfig, Indent, LineEnding};
+
+    let file = commented_user_proto();
+    assert_eq!(
+        "syntax = \"proto3\";\n\npackage acme;\n\n\n// A user.\nmessage User {\n  string name = 1;\n\n  // Primary key.\n  int64 id = 2;\n}\n\nenum Kind {\n  KIND_UNSPECIFIED = 0;\n}\n",
+        file.to_protobuf(&file)
+    );
+
+    let config = GeneratorConfig::new()
+        .indent(Indent::Spaces(4))
+        .blank_lines_between_declarations(2)
+        .blank_lines_between_field_groups(0);
+    assert_eq!(
+        "syntax = \"proto3\";\n\npackage acme;\n\n\n\n// A user.\nmessage User {\n    string name = 1;\n    // Primary key.\n    int64 id = 2;\n}\n\n\nenum Kind {\n    KIND_UNSPECIFIED = 0;\n}\n",
+        file.to_protobuf_with(&file, &config)
+    );
+
+    let config = GeneratorConfig::new()
+        .indent(Indent::Tabs)
+        .line_ending(LineEnding::CrLf)
+        .final_newline(false)
+        .comments(false);
+    assert_eq!(
+        "syntax = \"proto3\";\r\n\r\npackage acme;\r\n\r\n\r\nmessage User {\r\n\tstring name = 1;\r\n\tint64 id = 2;\r\n}\r\n\r\nenum Kind {\r\n\tKIND_UNSPECIFIED = 0;\r\n}",
+        file.to_protobuf_with(&file, &config)
+    );
+
+    let config: protobuf_gen::config::Config = protobuf_gen::config::Config::from_toml(
+        "[format]\nindent = \"tab\"\nline_ending = \"crlf\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        GeneratorConfig::new()
+            .indent(Indent::Tabs)
+            .line_ending(LineEnding::CrLf),
+        config.format
+    );
+}
+
+#[test]
+fn it_aligns_columns() {
+    use protobuf_gen::GeneratorConfig;
+
+    let config = GeneratorConfig::new().align(true);
+    let descriptor_set = read_files(&["tests/source.proto"], &["tests/"]);
+    for file in descriptor_set.file.iter() {
+        let actual = file.to_protobuf_with(file, &config);
+        let expected = std::fs::read_to_string("tests/expected_aligned.proto").unwrap();
+        assert_eq!(expected, actual);
+    }
+}
+
+#[test]
+fn it_aligns_oneofs_on_their_own() {
+    use protobuf_gen::GeneratorConfig;
+
+    let source = "syntax = \"proto3\";\npackage acme;\nmessage Event {\n  string id = 1;\n  int64 created_at = 2; // ms\n  oneof payload {\n    string text = 3;\n    bytes binary_data = 4;\n  }\n}\n";
+    let descriptor_set = read_sources(vec![("event.proto", source)], &["tests/"]);
+    let file = &descriptor_set.file[0];
+    let expected = "syntax = \"proto3\";\n\npackage acme;\n\n\nmessage Event {\n  string id         = 1;\n  int64  created_at = 2;  // ms\n  oneof payload {\n    string text        = 3;\n    bytes  binary_data = 4;\n  }\n}\n";
+    assert_eq!(
+        expected,
+        file.to_protobuf_with(file, &GeneratorConfig::new().align(true))
+    );
+}
+
+#[test]
+fn it_breaks_long_option_lists() {
+    use protobuf_gen::GeneratorConfig;
+
+    let source = "syntax = \"proto2\";\npackage acme;\nmessage User {\n  optional string name = 1 [default = \"anonymous\", json_name = \"userName\", deprecated = true];\n  optional int32 age = 2 [deprecated = true];\n}\n";
+    let descriptor_set = read_sources(vec![("user.proto", source)], &["tests/"]);
+    let file = &descriptor_set.file[0];
+    let expected = "package acme;\n\n\nmessage User {\n  optional string name = 1 [\n    default = \"anonymous\",\n    json_name = \"userName\",\n    deprecated = true\n  ];\n  optional int32 age = 2 [deprecated = true];\n}\n";
+    assert_eq!(
+        expected,
+        file.to_protobuf_with(file, &GeneratorConfig::new().max_width(60))
+    );
+}
+
+#[test]
+fn it_renders_custom_options() {
+    let source = "syntax = \"proto3\";\npackage acme;\nimport \"source.proto\";\nmessage Rule {\n  int32 min = 1;\n  string pattern = 2;\n  repeated int32 lengths = 3;\n}\nenum Level {\n  LEVEL_LOW = 0;\n  LEVEL_HIGH = 1;\n}\nextend google.protobuf.FieldOptions {\n  bool sensitive = 50000;\n  Rule rule = 50001;\n  repeated string tags = 50002;\n  Level level = 50003;\n  float weight = 50004;\n  sint32 delta = 50005;\n}\nextend google.protobuf.MessageOptions {\n  string table = 50000;\n}\nmessage User {\n  // The table.\n  option (table) = \"users\";  // Plural.\n  option deprecated = true;\n  string name = 1 [(sensitive) = true, (rule) = {min: 1 pattern: \"a\\\"b\" lengths: [1, 2]}, (tags) = \"a\", (tags) = \"b\", (level) = LEVEL_HIGH, (weight) = 0.1, (delta) = -3];\n  string other = 2 [(rule).min = 5];\n}\n";
+    let descriptor_set = read_sources(vec![("user.proto", source)], &["tests/"]);
+    let file = descriptor_set
+        .file
+        .iter()
+        .find(|f| f.name() == "user.proto")
+        .unwrap();
+    protobuf_gen::ast::check_lossless(file).unwrap();
+
+    let config = protobuf_gen::GeneratorConfig::new().lossless(true);
+    let rendered = file.to_protobuf_with(file, &config);
+    let user = &rendered[rendered.find("message User").unwrap()..];
+    assert_eq!(
+        "message User {\n\n  // The table.\n  option (.acme.table) = \"users\";  // Plural.\n  option deprecated = true;\n  string name = 1 [\n    (.acme.sensitive) = true,\n    (.acme.rule) = {min: 1, pattern: \"a\\\"b\", lengths: 1, lengths: 2},\n    (.acme.tags) = \"a\",\n    (.acme.tags) = \"b\",\n    (.acme.level) = LEVEL_HIGH,\n    (.acme.weight) = 0.1,\n    (.acme.delta) = -3\n  ];\n  string other = 2 [(.acme.rule) = {min: 5}];\n}\n",
+        user
+    );
+
+    let descriptor_set = read_sources(vec![("user.proto", &*rendered)], &["tests/"]);
+    let file = descriptor_set
+        .file
+        .iter()
+        .find(|f| f.name() == "user.proto")
+        .unwrap();
+    assert_eq!(rendered, file.to_protobuf_with(file, &config));
+}
+
+#[test]
+fn it_renders_standard_options_prost_does_not_know() {
+    let source = "syntax = \"proto2\";\npackage acme;\nmessage User {\n  option deprecated_legacy_json_field_conflicts = true;\n  optional string token = 1 [debug_redact = true, retention = RETENTION_SOURCE, targets = TARGET_TYPE_FIELD, targets = TARGET_TYPE_ONEOF];\n}\nenum Level {\n  LEVEL_LOW = 0 [debug_redact = true];\n}\n";
+    let descriptor_set = read_sources(vec![("user.proto", source)], &["tests/"]);
+    let file = &descriptor_set.file[0];
+    protobuf_gen::ast::check_lossless(file).unwrap();
+
+    let config = protobuf_gen::GeneratorConfig::new().lossless(true);
+    let rendered = file.to_protobuf_with(file, &config);
+    assert!(rendered.contains("  option deprecated_legacy_json_field_conflicts = true;\n"));
+    assert!(rendered.contains("  optional string token = 1 [\n    debug_redact = true,\n    retention = RETENTION_SOURCE,\n    targets = TARGET_TYPE_FIELD,\n    targets = TARGET_TYPE_ONEOF\n  ];\n"));
+    assert!(rendered.contains("  LEVEL_LOW = 0 [debug_redact = true];\n"));
+
+    let descriptor_set = read_sources(vec![("user.proto", &*rendered)], &["tests/"]);
+    let file = &descriptor_set.file[0];
+    assert_eq!(rendered, file.to_protobuf_with(file, &config));
+}
+
+#[test]
+fn it_rejects_options_it_cannot_recover() {
+    use prost::Message;
+    use prost_types::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto};
+
+    // A field with an option whose extension is not in the set.
+    let mut options = Vec::new();
+    prost::encoding::bool::encode(60000, &true, &mut options);
+    let mut field = FieldDescriptorProto {
+        name: Some("name".to_owned()),
+        number: Some(1),
+        r#type: Some(9),
+        ..Default::default()
+    }
+    .encode_to_vec();
+    prost::encoding::bytes::encode(8, &options, &mut field);
+    let mut message = DescriptorProto {
+        name: Some("User".to_owned()),
+        ..Default::default()
+    }
+    .encode_to_vec();
+    prost::encoding::bytes::encode(2, &field, &mut message);
+    let mut file = FileDescriptorProto {
+        name: Some("user.proto".to_owned()),
+        package: Some("acme".to_owned()),
+        syntax: Some("proto3".to_owned()),
+        ..Default::default()
+    }
+    .encode_to_vec();
+    prost::encoding::bytes::encode(4, &message, &mut file);
+    let mut set = Vec::new();
+    prost::encoding::bytes::encode(1, &file, &mut set);
+
+    let descriptor_set = decode_descriptor_set(&set).unwrap();
+    let file = &descriptor_set.file[0];
+    let error = protobuf_gen::ast::check_lossless(file).unwrap_err();
+    assert_eq!(
+        "user.proto: option number 60000 of acme.User.name is unknown and cannot be rendered",
+        error.to_string()
+    );
+    assert!(file
+        .to_protobuf(file)
+        .contains("message User {\n  string name = 1;\n}"));
+}
+
+#[test]
+fn it_breaks_aggregate_option_values() {
+    use prost_types::uninterpreted_option::NamePart;
+    use prost_types::{
+        DescriptorProto, FieldDescriptorProto, FieldOptions, FileDescriptorProto,
+        UninterpretedOption,
+    };
+    use protobuf_gen::GeneratorConfig;
+
+    let option = UninterpretedOption {
+        name: vec![NamePart {
+            name_part: "acme.rules".to_owned(),
+            is_extension: true,
+        }],
+        aggregate_value: Some("min_len: 1 max_len: 64 pattern: \"^[a-z]+$\"".to_owned()),
+        ..Default::default()
+    };
+    let file = FileDescriptorProto {
+        name: Some("user.proto".to_owned()),
+        syntax: Some("proto3".to_owned()),
+        message_type: vec![DescriptorProto {
+            name: Some("User".to_owned()),
+            field: vec![FieldDescriptorProto {
+                name: Some("name".to_owned()),
+                number: Some(1),
+                r#type: Some(9),
+                options: Some(FieldOptions {
+                    uninterpreted_option: vec![option],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let wide = "syntax = \"proto3\";\n\n\nmessage User {\n  string name = 1 [(acme.rules) = {min_len: 1, max_len: 64, pattern: \"^[a-z]+$\"}];\n}\n";
+    assert_eq!(wide, file.to_protobuf(&file));
+
+    let narrow = "syntax = \"proto3\";\n\n\nmessage User {\n  string name = 1 [\n    (acme.rules) = {\n      min_len: 1,\n      max_len: 64,\n      pattern: \"^[a-z]+$\"\n    }\n  ];\n}\n";
+    assert_eq!(
+        narrow,
+        file.to_protobuf_with(&file, &GeneratorConfig::new().max_width(40))
+    );
+}
+
+#[test]
+fn it_wraps_long_rpc_signatures() {
+    use protobuf_gen::GeneratorConfig;
+
+    let source = "syntax = \"proto3\";\npackage acme;\nmessage GetUserRequest {}\nmessage GetUserResponse {}\nservice Users {\n  rpc GetUser(GetUserRequest) returns (stream GetUserResponse);\n}\n";
+    let descriptor_set = read_sources(vec![("users.proto", source)], &["tests/"]);
+    let file = &descriptor_set.file[0];
+    let expected = "syntax = \"proto3\";\n\npackage acme;\n\n\nmessage GetUserRequest {\n}\n\nmessage GetUserResponse {\n}\n\nservice Users {\n  rpc GetUser(\n    .acme.GetUserRequest\n  ) returns (\n    stream .acme.GetUserResponse\n  );\n}\n";
+    assert_eq!(
+        expected,
+        file.to_protobuf_with(file, &GeneratorConfig::new().max_width(60))
+    );
+}
+
+#[test]
+fn it_reflows_comments() {
+    use protobuf_gen::GeneratorConfig;
+
+    let source = "syntax = \"proto3\";\npackage acme;\n// The users of the service,\n// one per account, keyed by id.\n//\n//   id -> User\nmessage User {\n  string name = 1; // as given when signing up, not necessarily unique\n}\n";
+    let descriptor_set = read_sources(vec![("user.proto", source)], &["tests/"]);
+    let file = &descriptor_set.file[0];
+    let expected = "syntax = \"proto3\";\n\npackage acme;\n\n\n// The users of the service, one per account,\n// keyed by id.\n//\n//   id -> User\nmessage User {\n  string name = 1;  // as given when signing up,\n                    // not necessarily unique\n}\n";
+    let config = GeneratorConfig::new()
+        .max_width(50)
+        .reflow_comments(true)
+        .align(true);
+    assert_eq!(expected, file.to_protobuf_with(file, &config));
+}
+
+#[test]
+fn it_lays_out_text_by_character() {
+    use protobuf_gen::pretty::{reflow, Doc};
+
+    assert_eq!(
+        "first words\nsecond line\nwords\n",
+        reflow("first words\nsecond line words\n", 12)
+    );
+    assert_eq!(" one two\n three", reflow(" one\n two three", 8));
+
+    let mut out = "ééééé = ".to_owned();
+    let doc = Doc::bracket("[", vec![Doc::text("a"), Doc::text("b")], "]");
+    doc.render(&mut out, "", "  ", 14);
+    assert_eq!("ééééé = [a, b]", out);
+}
+
+#[test]
+fn it_builds_a_syntax_tree() {
+    use protobuf_gen::ast::{Comments, Field, Item, Label, MessageItem, Oneof};
+
+    let source = "syntax = \"proto3\";\npackage acme;\n// A user.\nmessage User {\n  string id = 1;\n  oneof contact {\n    string email = 2; // verified\n    string phone = 3;\n  }\n  map<string, int64> scores = 4;\n  optional string nickname = 5;\n}\n";
+    let descriptor_set = read_sources(vec![("user.proto", source)], &["tests/"]);
+    let file = protobuf_gen::ast::File::from_descriptor(&descriptor_set.file[0]);
+
+    assert_eq!(Some("acme"), file.package.as_ref().map(|p| &*p.name));
+    let user = match file.items[..] {
+        [Item::Message(ref user)] => user,
+        _ => panic!("expected a single message: {:?}", file.items),
+    };
+    assert_eq!(Some(" A user.\n"), user.comments.leading.as_deref());
+
+    let field =
+        |label, typ: &'static str, name: &'static str, number, trailing: Option<&'static str>| {
+            Field {
+                comments: Comments {
+                    trailing: trailing.map(Into::into),
+                    ..Default::default()
+                },
+                label,
+                typ: typ.into(),
+                name: name.into(),
+                number,
+                options: Vec::new(),
+            }
+        };
+    assert_eq!(
+        vec![
+            MessageItem::Field(field(None, "string", "id", 1, None)),
+            MessageItem::Oneof(Oneof {
+                comments: Comments::default(),
+                name: "contact".into(),
+                options: Vec::new(),
+                fields: vec![
+                    field(None, "string", "email", 2, Some(" verified\n")),
+                    field(None, "string", "phone", 3, None),
+                ],
+            }),
+            MessageItem::Field(field(None, "map<string, int64>", "scores", 4, None)),
+            MessageItem::Field(field(Some(Label::Optional), "string", "nickname", 5, None)),
+        ],
+        user.items
+    );
+
+    assert_eq!(file.to_string(), file.print(&Default::default()));
+    assert_eq!(
+        read_sources(vec![("user.proto", file.to_string())], &["tests/"]).file[0].message_type,
+        descriptor_set.file[0].message_type
+    );
+}
+
+#[test]
+fn it_prints_a_syntax_tree() {
+    use protobuf_gen::ast::*;
+
+    let file = File {
+        comments: Comments::default(),
+        syntax: Some("proto3".into()),
+        package: Some(Package {
+            comments: Comments::default(),
+            name: "acme".into(),
+        }),
+        imports: Vec::new(),
+        options: Vec::new(),
+        items: vec![Item::Enum(Enum {
+            comments: Comments {
+                leading: Some(" Where a user signed up.\n".into()),
+                ..Default::default()
+            },
+            name: "Origin".into(),
+            options: Vec::new(),
+            values: vec![
+                EnumValue {
+                    name: "ORIGIN_UNSPECIFIED".into(),
+                    ..Default::default()
+                },
+                EnumValue {
+                    name: "ORIGIN_WEB".into(),
+                    number: 1,
+                    options: vec![CompactOption {
+                        name: "deprecated".into(),
+                        value: "true".into(),
+                    }],
+                    ..Default::default()
+                },
+            ],
+            reserved: vec![
+                Reserved {
+                    comments: Comments::default(),
+                    kind: ReservedKind::Ranges(vec![
+                        ReservedRange {
+                            start: 2,
+                            end: Some(2),
+                        },
+                        ReservedRange {
+                            start: 5,
+                            end: Some(9),
+                        },
+                        ReservedRange {
+                            start: 100,
+                            end: None,
+                        },
+                    ]),
+                },
+                Reserved {
+                    comments: Comments::default(),
+                    kind: ReservedKind::Names(vec!["ORIGIN_APP".into()]),
+                },
+            ],
+        })],
+    };
+
+    let expected = "syntax = \"proto3\";\n\npackage acme;\n\n\n// Where a user signed up.\nenum Origin {\n  ORIGIN_UNSPECIFIED = 0;\n  ORIGIN_WEB = 1 [deprecated = true];\n  reserved 2, 5 to 9, 100 to max;\n  reserved \"ORIGIN_APP\";\n}\n";
+    assert_eq!(expected, file.to_string());
+
+    let descriptor_set = read_sources(vec![("origin.proto", expected)], &["tests/"]);
+    assert_eq!(
+        file,
+        protobuf_gen::ast::File::from_descriptor(&descriptor_set.file[0])
+    );
+}
+
+#[test]
+fn it_emits_source_info_matching_protoc() {
+    use protobuf_gen::{GeneratorConfig, Indent};
+
+    let source = "syntax = \"proto3\";\npackage acme;\nimport \"source.proto\";\n// A user.\nmessage User {\n  message Profile {}\n  map<string, int64> scores = 1; // by game\n  // How to reach them.\n  oneof contact {\n    string email = 2;\n    string phone = 3;\n  }\n  Profile profile = 4;\n  reserved 5 to 9, 100 to max;\n  reserved \"legacy\";\n  enum Role {\n    ROLE_UNSPECIFIED = 0;\n    reserved 1;\n  }\n}\nservice Users {\n  // Looks a user up.\n  rpc GetUser(User) returns (stream User);\n}\n";
+    let mut files = read_files(&["tests/source.proto"], &["tests/"]).file;
+    files.push(
+        read_sources(vec![("user.proto", source)], &["tests/"])
+            .file
+            .remove(1),
+    );
+
+    for file in &files {
+        for config in [
+            GeneratorConfig::new(),
+            GeneratorConfig::new().align(true).max_width(60),
+            GeneratorConfig::new().indent(Indent::Tabs),
+        ] {
+            let (text, source_info) = file.to_protobuf_with_source_info(file, &config);
+            assert_eq!(text, file.to_protobuf_with(file, &config));
+
+            let compiled = read_sources(vec![(file.name(), &text)], &["tests/"]);
+            let compiled = compiled.file.iter().find(|f| f.name == file.name).unwrap();
+            let expected = compiled.source_code_info.as_ref().unwrap();
+            for location in &source_info.location {
+                let protoc = expected
+                    .location
+                    .iter()
+                    .find(|l| l.path == location.path)
+                    .unwrap_or_else(|| panic!("no location at {:?}", location.path));
+                assert_eq!(protoc.span, location.span, "span of {:?}", location.path);
+                assert_eq!(
+                    protoc.leading_comments, location.leading_comments,
+                    "leading comments of {:?}",
+                    location.path
+                );
+                assert_eq!(
+                    protoc.trailing_comments, location.trailing_comments,
+                    "trailing comments of {:?}",
+                    location.path
+                );
+                assert_eq!(
+                    protoc.leading_detached_comments, location.leading_detached_comments,
+                    "detached comments of {:?}",
+                    location.path
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn it_builds_and_parses_paths() {
+    use protobuf_gen::path::{Kind, Path};
+
+    let field = Path::file().message(0).message(1).field(2);
+    assert_eq!(&[4, 0, 3, 1, 2, 2], field.as_slice());
+    assert_eq!(Kind::Field, field.kind());
+    assert_eq!(Some(2), field.index());
+    assert_eq!(Some(Path::file().message(0).message(1)), field.parent());
+    assert_eq!("message[0].message[1].field[2]", field.to_string());
+
+    let range = Path::file().enumeration(0).reserved_range(1);
+    assert_eq!(&[5, 0, 4, 1], range.as_slice());
+    assert_eq!("enum[0].reserved_range[1]", range.to_string());
+    assert_eq!(
+        Some(Path::file().enumeration(0).reserved_ranges()),
+        range.parent()
+    );
+    assert_eq!(Some(range), Path::parse(&[5, 0, 4, 1]));
+    assert_eq!(
+        Some(Path::file().service(0).method(3)),
+        Path::parse(&[6, 0, 2, 3])
+    );
+    assert_eq!(Some(Path::file().syntax()), Path::parse(&[12]));
+
+    assert_eq!(field, "message[0].message[1].field[2]".parse().unwrap());
+    assert_eq!(Path::file(), "file".parse().unwrap());
+    for invalid in [
+        "",
+        "field[0]",
+        "message",
+        "message[-1]",
+        "message[0].value[0]",
+    ] {
+        assert!(invalid.parse::<Path>().is_err(), "{}", invalid);
+    }
+
+    // The name of a field, and fields of a service.
+    assert_eq!(None, Path::parse(&[4, 0, 2, 1, 1]));
+    assert_eq!(None, Path::parse(&[6, 0, 4, 0]));
+    assert_eq!(None, Path::parse(&[4, 0, 2]));
+
+    let commented = commented_user_proto();
+    let location = Path::file().message(0).field(1).location(&commented);
+    assert_eq!(
+        Some(" Primary key.\n"),
+        location.and_then(|l| l.leading_comments.as_deref())
+    );
+
+    let file = read_files(&["tests/source.proto"], &["tests/"])
+        .file
+        .remove(0);
+    let info = file.source_code_info.as_ref().unwrap();
+    for location in &info.location {
+        if let Some(path) = Path::parse(&location.path) {
+            assert_eq!(location.path, path.as_slice());
+            assert_eq!(path, path.to_string().parse().unwrap());
+            let found = path.location(&file).unwrap();
+            assert_eq!(location.path, found.path);
+        }
+    }
+    let elements = info
+        .location
+        .iter()
+        .filter_map(|location| Path::parse(&location.path))
+        .filter(|path| path.kind() == Kind::Field)
+        .count();
+    assert!(elements > 0);
+}
+
+#[test]
+fn it_finds_paths_by_name() {
+    use protobuf_gen::path::Path;
+    use protobuf_gen::pool::DescriptorPool;
+
+    let user = commented_user_proto();
+    assert_eq!(
+        Some(Path::file().message(0).field(0)),
+        Path::find(&user, "acme.User.name")
+    );
+    assert_eq!(
+        Some(Path::file().message(0).field(1)),
+        Path::find(&user, ".acme.User.id")
+    );
+    assert_eq!(
+        Some(Path::file().enumeration(0).value(0)),
+        Path::find(&user, "acme.KIND_UNSPECIFIED")
+    );
+    assert_eq!(Some(Path::file()), Path::find(&user, "acme"));
+    assert_eq!(None, Path::find(&user, "User.name"));
+
+    // Every definition of descriptor.proto is where the pool has it.
+    let set = read_files(&["tests/source.proto"], &["tests/"]);
+    let pool = DescriptorPool::new(&set).unwrap();
+    for definition in pool.definitions() {
+        assert_eq!(
+            Some(&definition.path),
+            Path::find(&set.file[0], &definition.name).as_ref(),
+            "{}",
+            definition.name
+        );
+    }
+    assert_eq!(None, Path::find(&set.file[0], "google.protobuf.Missing"));
+}
+
+#[test]
+fn it_keeps_comments_through_edits() {
+    use protobuf_gen::edit::Editor;
+    use protobuf_gen::path::Path;
+
+    let source = "syntax = \"proto3\";\n// First.\nmessage A {\n  // a1\n  string a1 = 1;\n  // choice\n  oneof choice {\n    // c1\n    string c1 = 2;\n  }\n  // other\n  oneof other {\n    string o1 = 3;\n  }\n}\n// Second.\nmessage B {\n  // b1\n  string b1 = 4;\n}\n// Kind.\nenum Kind {\n  // zero\n  KIND_ZERO = 0;\n  // one\n  KIND_ONE = 1;\n}\n";
+    let mut file = read_sources(vec![("edit.proto", source)], &["tests/"])
+        .file
+        .remove(0);
+
+    let a = Path::file().message(0);
+    let mut editor = Editor::new(&mut file);
+    editor.insert(
+        &a.clone().field(0),
+        prost_types::FieldDescriptorProto {
+            name: Some("id".to_owned()),
+            number: Some(5),
+            r#type: Some(prost_types::field_descriptor_proto::Type::Int64 as i32),
+            ..Default::default()
+        },
+    );
+    editor.move_to(&a.clone().oneof(1), &a.clone().oneof(0));
+    editor.move_to(&Path::file().message(1).field(0), &a.clone().field(1));
+    let b: prost_types::DescriptorProto = editor.remove(&Path::file().message(1));
+    assert_eq!("B", b.name());
+    let kind = Path::file().enumeration(0);
+    editor.move_to(&kind.clone().value(1), &kind.value(0));
+
+    assert_eq!(
+        "syntax = \"proto3\";\n\n\n// First.\nmessage A {\n  int64 id = 5;\n\n  // b1\n  string b1 = 4;\n\n  // a1\n  string a1 = 1;\n\n  // choice\n  oneof choice {\n\n    // c1\n    string c1 = 2;\n  }\n\n  // other\n  oneof other {\n    string o1 = 3;\n  }\n}\n\n// Kind.\nenum Kind {\n\n  // one\n  KIND_ONE = 1;\n\n  // zero\n  KIND_ZERO = 0;\n}\n",
+        file.to_protobuf(&file)
+    );
+    assert_eq!(Some(1), file.message_type[0].field[3].oneof_index);
+    assert_eq!(Some(0), file.message_type[0].field[4].oneof_index);
+}
+
+#[test]
+fn it_moves_fields_between_messages() {
+    use protobuf_gen::edit::Editor;
+    use protobuf_gen::path::Path;
+
+    let source = "syntax = \"proto3\";\nmessage A {\n  oneof choice {\n    string c1 = 1;\n  }\n  optional string nick = 2;\n  string keep = 3;\n}\nmessage B {\n  oneof kind {\n    string k1 = 4;\n  }\n}\n";
+    let mut file = read_sources(vec![("move.proto", source)], &["tests/"])
+        .file
+        .remove(0);
+
+    let (a, b) = (Path::file().message(0), Path::file().message(1));
+    let mut editor = Editor::new(&mut file);
+    editor.move_to(&a.clone().field(0), &b.clone().field(1));
+    editor.move_to(&a.field(0), &b.field(2));
+
+    let (a, b) = (&file.message_type[0], &file.message_type[1]);
+    assert!(a.oneof_decl.is_empty());
+    assert_eq!(None, a.field[0].oneof_index);
+    assert_eq!(None, b.field[1].oneof_index);
+    assert_eq!(Some(1), b.field[2].oneof_index);
+    assert_eq!(Some(true), b.field[2].proto3_optional);
+    assert_eq!(Some("_nick"), b.oneof_decl[1].name.as_deref());
+
+    let rendered = file.to_protobuf(&file);
+    assert_eq!(
+        "syntax = \"proto3\";\n\n\nmessage A {\n  string keep = 3;\n}\n\nmessage B {\n  oneof kind {\n    string k1 = 4;\n  }\n  string c1 = 1;\n  optional string nick = 2;\n}\n",
+        rendered
+    );
+    let mut compiled = read_sources(vec![("move.proto", &*rendered)], &["tests/"])
+        .file
+        .remove(0);
+    compiled.source_code_info = None;
+    file.source_code_info = None;
+    assert_eq!(file, compiled);
+}
+
+#[test]
+fn it_edits_extensions_and_emptied_oneofs() {
+    use protobuf_gen::edit::Editor;
+    use protobuf_gen::path::Path;
+
+    let source = "syntax = \"proto2\";\nmessage A {\n  oneof choice {\n    string c1 = 1;\n  }\n  optional string keep = 2;\n  extensions 100 to 200;\n}\nmessage B {\n}\nextend A {\n  // tag\n  optional string tag = 100;\n}\n";
+    let mut file = read_sources(vec![("extend.proto", source)], &["tests/"])
+        .file
+        .remove(0);
+
+    let (a, b) = (Path::file().message(0), Path::file().message(1));
+    let mut editor = Editor::new(&mut file);
+    let c1: prost_types::FieldDescriptorProto = editor.remove(&a.field(0));
+    assert_eq!("c1", c1.name());
+    editor.move_to(&Path::file().extension(0), &b.clone().extension(0));
+
+    assert!(file.message_type[0].oneof_decl.is_empty());
+    assert!(file.extension.is_empty());
+    // Extensions are only written when rendering losslessly.
+    let config = protobuf_gen::GeneratorConfig::new().lossless(true);
+    let rendered = file.to_protobuf_with(&file, &config);
+    assert_eq!("syntax = \"proto2\";\n\n\nmessage A {\n  optional string keep = 2;\n  extensions 100 to 200;\n}\n\nmessage B {\n\n  extend .A {\n\n    // tag\n    optional string tag = 100;\n  }\n}\n", rendered);
+    let mut compiled = read_sources(vec![("extend.proto", &*rendered)], &["tests/"])
+        .file
+        .remove(0);
+    compiled.source_code_info = None;
+    file.source_code_info = None;
+    assert_eq!(file, compiled);
+
+    let mut editor = Editor::new(&mut file);
+    let tag: prost_types::FieldDescriptorProto = editor.remove(&b.extension(0));
+    assert_eq!("tag", tag.name());
+    assert!(file.message_type[1].extension.is_empty());
+}
+
+#[test]
+fn it_sets_comments_by_path() {
+    use protobuf_gen::edit::Editor;
+    use protobuf_gen::path::Path;
+
+    let mut file = user_proto();
+    file.source_code_info = None;
+    let user = Path::file().message(0);
+    let mut editor = Editor::new(&mut file);
+    editor.add_detached_comment(&user, "Users");
+    editor.set_leading_comment(&user, "A user.\n\nKnown by name.");
+    editor.set_trailing_comment(&user.clone().field(0), "unique");
+    editor.set_leading_comment(&user, "A registered user.");
+
+    // Trailing comments are written along with aligned fields.
+    let config = protobuf_gen::GeneratorConfig::new().align(true);
+    assert_eq!(
+        "syntax = \"proto3\";\n\npackage acme;\n\n\n// Users\n\n// A registered user.\nmessage User {\n  string name = 1;  // unique\n}\n",
+        file.to_protobuf_with(&file, &config)
+    );
+    assert_eq!(2, file.source_code_info.as_ref().unwrap().location.len());
+
+    let mut editor = Editor::new(&mut file);
+    editor.set_leading_comment(&user, "");
+    assert!(file
+        .to_protobuf(&file)
+        .contains("\n// Users\n\nmessage User {\n"));
+
+    Editor::new(&mut file).clear_comments(&user);
+    assert!(file.to_protobuf(&file).contains("\n\nmessage User {\n"));
+}
+
+#[test]
+fn it_builds_descriptors_like_protoc() {
+    use protobuf_gen::builder::FieldType;
+    use protobuf_gen::FileBuilder;
+
+    let source = r#"syntax = "proto3";
+
+package acme.users;
+
+import "common/time.proto";
+
+message User {
+  int64 id = 1;
+  repeated string tags = 2;
+  map<string, Address> postal_addresses = 3;
+  optional Status status = 4;
+  oneof contact {
+    string email = 5;
+    string phone_number = 6;
+  }
+  common.Time created_at = 7 [json_name = "created"];
+  message Address {
+    string city = 1;
+  }
+  reserved 8 to 10;
+  reserved "password";
+}
+
+enum Status {
+  STATUS_UNKNOWN = 0;
+  STATUS_ACTIVE = 1;
+}
+
+service Users {
+  rpc GetUser(User) returns (User);
+  rpc WatchUsers(User) returns (stream User);
+}
+"#;
+    let time = "syntax = \"proto3\";\npackage common;\nmessage Time {}\n";
+    let mut expected = read_sources(
+        vec![("common/time.proto", time), ("users.proto", source)],
+        &["."],
+    )
+    .file
+    .pop()
+    .unwrap();
+    expected.source_code_info = None;
+
+    let file = FileBuilder::new("users.proto")
+        .package("acme.users")
+        .import("common/time.proto")
+        .message("User", |m| {
+            m.field("id", FieldType::Int64, 1)
+                .repeated("tags", FieldType::String, 2)
+                .map(
+                    "postal_addresses",
+                    FieldType::String,
+                    FieldType::message("Address"),
+                    3,
+                )
+                .optional("status", FieldType::enumeration("Status"), 4)
+                .oneof("contact", |o| {
+                    o.field("email", FieldType::String, 5).field(
+                        "phone_number",
+                        FieldType::String,
+                        6,
+                    )
+                })
+                .field_with("created_at", FieldType::message("common.Time"), 7, |f| {
+                    f.json_name("created")
+                })
+                .message("Address", |m| m.field("city", FieldType::String, 1))
+                .reserved_range(8, 10)
+                .reserved_name("password")
+        })
+        .enumeration("Status", |e| {
+            e.value("STATUS_UNKNOWN", 0).value("STATUS_ACTIVE", 1)
+        })
+        .service("Users", |s| {
+            s.method("GetUser", "User", "User").streaming_method(
+                "WatchUsers",
+                "User",
+                false,
+                "User",
+                true,
+            )
+        })
+        .build();
+
+    assert_eq!(expected, file);
+    assert!(file
+        .to_protobuf(&file)
+        .contains("  map<string, .acme.users.User.Address> postal_addresses = 3;\n"));
+}
+
+#[cfg(feature = "from-rust")]
+#[test]
+fn it_reads_descriptors_from_prost_code() {
+    // `acme.users.rs` is what prost-build 0.13 generates for `users.proto`.
+    let mut expected = read_files(&["tests/prost/users.proto"], &["tests/prost"])
+        .file
+        .pop()
+        .unwrap();
+    expected.source_code_info = None;
+
+    let mut file = protobuf_gen::from_rust::read_file("tests/prost/acme.users.rs").unwrap();
+    assert_eq!(Some("acme/users.proto"), file.name.as_deref());
+    file.name = expected.name.clone();
+    pretty_assertions::assert_eq!(expected, file);
+
+    let err = protobuf_gen::from_rust::from_source(
+        "acme",
+        "#[derive(::prost::Message)]\npub struct A {\n    #[prost(group, tag = \"1\")]\n    pub g: i32,\n}\n",
+    )
+    .unwrap_err();
+    assert_eq!("A.g: groups are not supported", err.to_string());
+    let file = protobuf_gen::from_rust::from_source(
+        "acme",
+        "#[derive(::prost::Message)]\npub struct A {\n    #[prost(btree_map = \"string, int32\", tag = \"1\")]\n    pub sorted: ::prost::alloc::collections::BTreeMap<::prost::alloc::string::String, i32>,\n    #[prost(hash_map = \"int64, message\", tag = \"2\")]\n    pub by_id: ::std::collections::HashMap<i64, A>,\n}\n",
+    )
+    .unwrap();
+    let rendered = file.to_protobuf(&file);
+    assert!(rendered.contains("  map<string, int32> sorted = 1;\n"));
+    assert!(rendered.contains("  map<int64, .acme.A> by_id = 2;\n"));
+
+    // prost-build writes enum defaults as Rust variants and bytes ones as
+    // byte string literals.
+    let proto = "syntax = \"proto2\";\npackage acme;\nmessage Limits {\n  optional Level level = 1 [default = HIGH];\n  optional bytes magic = 2 [default = \"\\000\\377a\\\"\u{e9}\"];\n}\nenum Level {\n  LOW = 0;\n  HIGH = 1;\n}\n";
+    let expected = read_sources(vec![("limits.proto", proto)], &["tests/"]);
+    let source = r#"
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Limits {
+    #[prost(enumeration = "Level", optional, tag = "1", default = "High")]
+    pub level: ::core::option::Option<i32>,
+    #[prost(bytes = "vec", optional, tag = "2", default = "b\"\\x00\\xffa\\\"\\xc3\\xa9\"")]
+    pub magic: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Level {
+    Low = 0,
+    High = 1,
+}
+impl Level {
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Low => "LOW",
+            Self::High => "HIGH",
+        }
+    }
+}
+"#;
+    let file = protobuf_gen::from_rust::from_source("acme", source).unwrap();
+    let defaults = |file: &prost_types::FileDescriptorProto| {
+        file.message_type[0]
+            .field
+            .iter()
+            .map(|f| f.default_value().to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["HIGH", "\\000\\377a\\\"\\303\\251"], defaults(&file));
+    assert_eq!(defaults(&expected.file[0]), defaults(&file));
+}
+
+#[test]
+fn it_visits_every_element() {
+    use prost_types::{
+        EnumValueDescriptorProto, FieldDescriptorProto, FileDescriptorSet, OneofDescriptorProto,
+    };
+    use protobuf_gen::transform::Passes;
+    use protobuf_gen::visit::{Context, VisitMut};
+
+    let source = r#"syntax = "proto2";
+package acme;
+message User {
+  optional string name = 1;
+  oneof contact {
+    string email = 2;
+  }
+  message Name {
+    enum Kind {
+      KIND_UNKNOWN = 0;
+    }
+  }
+  extensions 100 to 200;
+  extend User {
+    optional string nickname = 100;
+  }
+}
+extend User {
+  optional int32 age = 101;
+}
+service Users {
+  rpc Get(User) returns (User);
+}
+"#;
+    let mut set = read_sources(vec![("user.proto", source)], &["tests/"]);
+
+    #[derive(Default)]
+    struct Record(Vec<String>);
+
+    impl Record {
+        fn push(&mut self, kind: &str, cx: &Context) {
+            self.0.push(format!(
+                "{} {} in {:?} at {} ({:?})",
+                kind,
+                cx.full_name(),
+                cx.scope(),
+                cx.path(),
+                cx.syntax()
+            ));
+        }
+    }
+
+    impl VisitMut for Record {
+        fn field(&mut self, _: &mut FieldDescriptorProto, cx: &Context) {
+            self.push("field", cx);
+        }
+        fn extension(&mut self, _: &mut FieldDescriptorProto, cx: &Context) {
+            self.push("extension", cx);
+        }
+        fn oneof(&mut self, _: &mut OneofDescriptorProto, cx: &Context) {
+            self.push("oneof", cx);
+        }
+        fn enum_value(&mut self, _: &mut EnumValueDescriptorProto, cx: &Context) {
+            self.push("value", cx);
+        }
+        fn method(&mut self, _: &mut prost_types::MethodDescriptorProto, cx: &Context) {
+            self.push("method", cx);
+        }
+    }
+
+    let mut record = Record::default();
+    protobuf_gen::visit::visit_set_mut(&mut record, &mut set);
+    assert_eq!(
+        vec![
+            "field .acme.User.name in \".acme.User\" at message[0].field[0] (Proto2)",
+            "field .acme.User.email in \".acme.User\" at message[0].field[1] (Proto2)",
+            "extension .acme.User.nickname in \".acme.User\" at message[0].extension[0] (Proto2)",
+            "oneof .acme.User.contact in \".acme.User\" at message[0].oneof[0] (Proto2)",
+            "value .acme.User.Name.KIND_UNKNOWN in \".acme.User.Name\" at message[0].message[0].enum[0].value[0] (Proto2)",
+            "method .acme.Users.Get in \".acme.Users\" at service[0].method[0] (Proto2)",
+            "extension .acme.age in \".acme\" at extension[0] (Proto2)",
+        ],
+        record.0
+    );
+
+    // Passes run in the order they were added.
+    struct Rename;
+
+    impl VisitMut for Rename {
+        fn field(&mut self, field: &mut FieldDescriptorProto, cx: &Context) {
+            if cx.full_name() == ".acme.User.name" {
+                field.name = Some("full_name".to_owned());
+            }
+        }
+    }
+
+    let mut passes = Passes::new()
+        .visit(Rename)
+        .then(|set: &mut FileDescriptorSet| {
+            let field = &mut set.file[0].message_type[0].field[0];
+            assert_eq!("full_name", field.name());
+            field.json_name = Some("fullName".to_owned());
+            Ok(())
+        });
+    assert_eq!(2, passes.len());
+    passes.run(&mut set).unwrap();
+    let file = &set.file[0];
+    assert!(file
+        .to_protobuf(file)
+        .contains("  optional string full_name = 1;\n"));
+}
+
+#[test]
+fn it_applies_rewrite_rules() {
+    use protobuf_gen::rules::Rules;
+
+    let source = r#"syntax = "proto3";
+package acme.users;
+message User {
+  string name = 1;
+  repeated string tags = 2;
+  map<string, string> labels = 3;
+  oneof contact {
+    string email = 4;
+  }
+  int32 legacy_age = 5;
+  Status status = 6;
+}
+enum Status {
+  UNKNOWN = 0;
+  STATUS_ACTIVE = 1;
+}
+"#;
+    let other = "syntax = \"proto3\";\npackage other;\nmessage Note {\n  string text = 1;\n}\n";
+    let mut set = read_sources(
+        vec![("user.proto", source), ("other.proto", other)],
+        &["tests/"],
+    );
+
+    let rules = Rules::from_toml(
+        r#"
+[[rule]]
+package = "acme.*"
+type = "string"
+optional = true
+
+[[rule]]
+field = "legacy_*"
+deprecated = true
+
+[[rule]]
+enum = "Status"
+value = "*"
+add_prefix = "STATUS_"
+"#,
+    )
+    .unwrap();
+    rules.apply(&mut set).unwrap();
+
+    let expected = r#"syntax = "proto3";
+package acme.users;
+message User {
+  optional string name = 1;
+  repeated string tags = 2;
+  map<string, string> labels = 3;
+  oneof contact {
+    string email = 4;
+  }
+  int32 legacy_age = 5 [deprecated = true];
+  Status status = 6;
+}
+enum Status {
+  STATUS_UNKNOWN = 0;
+  STATUS_ACTIVE = 1;
+}
+"#;
+    let mut expected = read_sources(vec![("user.proto", expected)], &["tests/"]);
+    for file in &mut set.file {
+        file.source_code_info = None;
+    }
+    expected.file[0].source_code_info = None;
+    assert_eq!(expected.file[0], set.file[0]);
+    assert_eq!(None, set.file[1].message_type[0].field[0].proto3_optional);
+
+    let yaml = Rules::from_yaml(
+        "rule:\n  - package: acme.*\n    type: string\n    optional: true\n  - field: legacy_*\n    deprecated: true\n  - enum: Status\n    value: '*'\n    add_prefix: STATUS_\n",
+    )
+    .unwrap();
+    assert_eq!(rules, yaml);
+
+    let err = Rules::from_toml("[[rule]]\nenum = \"Status\"\noptional = true\n").unwrap_err();
+    assert_eq!("rule 1: `optional` only applies to fields", err.to_string());
+}
+
+#[test]
+fn it_renames_enum_defaults_with_their_values() {
+    use protobuf_gen::rules::Rules;
+
+    let source = "syntax = \"proto2\";\npackage acme;\nenum Level {\n  LOW = 0;\n  HIGH = 1;\n}\nmessage Limits {\n  optional Level level = 1 [default = HIGH];\n  optional string name = 2 [default = \"HIGH\"];\n}\n";
+    let mut set = read_sources(vec![("limits.proto", source)], &["tests/"]);
+    let rules = Rules::from_toml(
+        "[[rule]]\nenum = \"Level\"\nadd_prefix = \"LEVEL_\"\n\n[[rule]]\nvalue = \"LEVEL_*\"\nadd_prefix = \"ACME_\"\n",
+    )
+    .unwrap();
+    rules.apply(&mut set).unwrap();
+
+    let fields = &set.file[0].message_type[0].field;
+    assert_eq!(Some("ACME_LEVEL_HIGH"), fields[0].default_value.as_deref());
+    assert_eq!(Some("HIGH"), fields[1].default_value.as_deref());
+}
+
+#[test]
+fn it_selects_fields_by_custom_option() {
+    use protobuf_gen::rules::Rules;
+
+    let source = "syntax = \"proto3\";\npackage acme;\nimport \"source.proto\";\nextend google.protobuf.FieldOptions {\n  bool sensitive = 50000;\n}\nmessage User {\n  string name = 1;\n  string password = 2 [(sensitive) = true];\n}\n";
+    let mut set = read_sources(vec![("user.proto", source)], &["tests/"]);
+    let rules =
+        Rules::from_toml("[[rule]]\noption = \"(acme.sensitive)\"\ndeprecated = true\n").unwrap();
+    rules.apply(&mut set).unwrap();
+
+    let file = set.file.iter().find(|f| f.name() == "user.proto").unwrap();
+    let fields = &file.message_type[0].field;
+    assert_eq!(None, fields[0].options.as_ref().and_then(|o| o.deprecated));
+    assert_eq!(
+        Some(true),
+        fields[1].options.as_ref().and_then(|o| o.deprecated)
+    );
+    assert!(file
+        .to_protobuf(file)
+        .contains("  string password = 2 [deprecated = true, (.acme.sensitive) = true];\n"));
+}
+
+#[cfg(feature = "script")]
+#[test]
+fn it_runs_scripts() {
+    use protobuf_gen::script::Script;
+
+    let source = r#"syntax = "proto3";
+package acme;
+message User {
+  // The display name.
+  string name = 1;
+  optional string nickname = 2;
+  repeated string tags = 3;
+  oneof contact {
+    string email = 4;
+  }
+}
+enum Status {
+  UNKNOWN = 0;
+  ACTIVE = 1;
+}
+"#;
+    let mut set = read_sources(vec![("user.proto", source)], &["tests/"]);
+
+    let script = Script::compile(
+        "fix.rhai",
+        r#"
+fn field(cx) {
+    if this.type == "string" && this.label == "optional" && !this.in_oneof {
+        this.optional = !this.optional;
+    }
+    if cx.full_name == ".acme.User.name" {
+        this.name = "full_name";
+        this.leading_comments += "\nRenamed from `name`.";
+    }
+}
+
+fn enum_value(cx) {
+    this.name = "STATUS_" + this.name;
+}
+"#,
+    )
+    .unwrap();
+    script.run(&mut set).unwrap();
+
+    let file = &set.file[0];
+    assert!(file.to_protobuf(file).contains(
+        "  // The display name.\n  // Renamed from `name`.\n  optional string full_name = 1;\n"
+    ));
+
+    let expected = r#"syntax = "proto3";
+package acme;
+message User {
+  optional string full_name = 1;
+  string nickname = 2;
+  repeated string tags = 3;
+  oneof contact {
+    string email = 4;
+  }
+}
+enum Status {
+  STATUS_UNKNOWN = 0;
+  STATUS_ACTIVE = 1;
+}
+"#;
+    let mut expected = read_sources(vec![("user.proto", expected)], &["tests/"]);
+    set.file[0].source_code_info = None;
+    expected.file[0].source_code_info = None;
+    assert_eq!(expected.file[0], set.file[0]);
+
+    let script = Script::compile("fix.rhai", "fn field(cx) { this.type = \"text\"; }").unwrap();
+    let err = script.run(&mut set).unwrap_err();
+    assert_eq!(
+        "fix.rhai: field .acme.User.full_name: Runtime error: unknown field type `text` (line 1, position 21)",
+        err.to_string()
+    );
+}
+
+#[test]
+fn it_indexes_symbols() {
+    use protobuf_gen::path::Path;
+    use protobuf_gen::pool::{DescriptorPool, ReferenceKind, Symbol};
+
+    let types = r#"syntax = "proto2";
+package acme;
+message Id {
+  optional int64 value = 1;
+  extensions 100 to 200;
+}
+enum Status {
+  UNKNOWN = 0;
+}
+"#;
+    let users = r#"syntax = "proto2";
+package acme.users;
+import "acme/types.proto";
+message User {
+  optional acme.Id id = 1;
+  optional Name name = 2;
+  map<string, Status> statuses = 3;
+  message Name {
+    optional string first = 1;
+  }
+  oneof contact {
+    string email = 4;
+  }
+}
+extend acme.Id {
+  optional string label = 100;
+}
+service Users {
+  rpc Get(acme.Id) returns (User);
+}
+"#;
+    let set = read_sources(
+        vec![
+            ("acme/types.proto", types),
+            ("acme/users/user.proto", users),
+        ],
+        &["tests/"],
+    );
+    let pool = DescriptorPool::new(&set).unwrap();
+
+    assert_eq!("acme/types.proto", pool.file_of(".acme.Id").unwrap().name());
+    assert_eq!(
+        "acme/users/user.proto",
+        pool.file_of("acme.users.User.Name").unwrap().name()
+    );
+    assert_eq!(
+        "Name",
+        pool.message(".acme.users.User.Name").unwrap().name()
+    );
+    assert_eq!(
+        "first",
+        pool.field(".acme.users.User.Name.first").unwrap().name()
+    );
+    assert_eq!(
+        "contact",
+        pool.oneof(".acme.users.User.contact").unwrap().name()
+    );
+    assert_eq!("label", pool.extension(".acme.users.label").unwrap().name());
+    assert_eq!("Status", pool.enumeration(".acme.Status").unwrap().name());
+    assert_eq!("UNKNOWN", pool.enum_value(".acme.UNKNOWN").unwrap().name());
+    assert_eq!("Users", pool.service(".acme.users.Users").unwrap().name());
+    assert_eq!("Get", pool.method(".acme.users.Users.Get").unwrap().name());
+    assert_eq!(Symbol::Package, pool.get(".acme").unwrap().symbol);
+    assert!(pool.message(".acme.users.User.id").is_none());
+    assert_eq!(
+        Path::file().message(0).message(1).field(0),
+        pool.get(".acme.users.User.Name.first").unwrap().path
+    );
+
+    let references: Vec<_> = pool
+        .references(".acme.Id")
+        .iter()
+        .map(|r| (r.name.as_str(), r.kind, r.path.to_string()))
+        .collect();
+    assert_eq!(
+        vec![
+            (
+                ".acme.users.User.id",
+                ReferenceKind::FieldType,
+                "message[0].field[0]".to_owned()
+            ),
+            (
+                ".acme.users.Users.Get",
+                ReferenceKind::MethodInput,
+                "service[0].method[0]".to_owned()
+            ),
+            (
+                ".acme.users.label",
+                ReferenceKind::Extendee,
+                "extension[0]".to_owned()
+            ),
+        ],
+        references
+    );
+    assert_eq!(
+        ".acme.users.User.StatusesEntry.value",
+        pool.references("acme.Status")[0].name
+    );
+
+    // Looked up in each enclosing scope, as protoc does.
+    let resolve = |scope, name| pool.resolve_type(scope, name).map(|d| d.name.as_str());
+    assert_eq!(
+        Some(".acme.users.User.Name"),
+        resolve(".acme.users.User", "Name")
+    );
+    assert_eq!(Some(".acme.Id"), resolve(".acme.users.User", "Id"));
+    assert_eq!(Some(".acme.users.User"), resolve(".acme", "users.User"));
+    assert_eq!(Some(".acme.Status"), resolve("acme.users", ".acme.Status"));
+    // The field `name` is not a type, so the search goes on outwards.
+    assert_eq!(None, resolve(".acme.users.User", "name"));
+    assert_eq!(
+        Some(".acme.users.User.name"),
+        pool.resolve(".acme.users.User", "name")
+            .map(|d| d.name.as_str())
+    );
+    // `users` is found in `acme`, so `users.Id` is not looked for further out.
+    assert_eq!(None, resolve(".acme.users", "users.Id"));
+
+    let mut duplicate = set.clone();
+    duplicate.file.push(set.file[0].clone());
+    let err = DescriptorPool::new(&duplicate).err().unwrap();
+    assert_eq!(
+        "acme/types.proto: \"acme.Id\" is already defined in file \"acme/types.proto\"",
+        err.to_string()
+    );
+}
+
+#[cfg(feature = "reflect")]
+#[test]
+fn it_renders_prost_reflect_descriptors() {
+    use prost::Message;
+    use protobuf_gen::reflect::ReflectProtobufString;
+
+    let source = r#"syntax = "proto3";
+package acme;
+// A user.
+message User {
+  // Where to reach them.
+  message Address {
+    string city = 1; // Or town.
+  }
+  Address address = 1;
+  Status status = 2;
+}
+// Account state.
+enum Status {
+  UNKNOWN = 0;
+}
+// Finds users.
+service Users {
+  rpc Get(User) returns (User);
+}
+"#;
+    let set = read_sources(vec![("user.proto", source)], &["tests/"]);
+    let pool = prost_reflect::DescriptorPool::decode(&*set.encode_to_vec()).unwrap();
+
+    let file = &set.file[0];
+    assert_eq!(
+        file.to_protobuf(file),
+        pool.get_file_by_name("user.proto").unwrap().to_protobuf()
+    );
+    assert_eq!(
+        "// Where to reach them.\nmessage Address {\n  string city = 1;  // Or town.\n}\n",
+        pool.get_message_by_name("acme.User.Address")
+            .unwrap()
+            .to_protobuf_with(&protobuf_gen::GeneratorConfig::new().align(true))
+    );
+    assert_eq!(
+        "// Account state.\nenum Status {\n  UNKNOWN = 0;\n}\n",
+        pool.get_enum_by_name("acme.Status").unwrap().to_protobuf()
+    );
+    let config = protobuf_gen::GeneratorConfig::new().indent(protobuf_gen::Indent::Spaces(4));
+    assert_eq!(
+        "// Finds users.\nservice Users {\n    rpc Get(.acme.User) returns (.acme.User);\n}\n",
+        pool.get_service_by_name("acme.Users")
+            .unwrap()
+            .to_protobuf_with(&config)
+    );
+
+    // Custom options are extensions, like `(google.api.http)`, which
+    // prost-reflect keeps as protoc encoded them.
+    let source = r#"syntax = "proto3";
+package acme;
+import "source.proto";
+message HttpRule {
+  string get = 1;
+}
+extend google.protobuf.MethodOptions {
+  HttpRule http = 72295728;
+}
+extend google.protobuf.FieldOptions {
+  bool sensitive = 50000;
+}
+message User {
+  string name = 1 [(sensitive) = true];
+}
+service Users {
+  rpc Get(User) returns (User) {
+    option (http) = {get: "/users/{name}"};
+  }
+}
+"#;
+    let tmp = tempfile::tempdir().unwrap();
+    std::fs::write(tmp.path().join("user.proto"), source).unwrap();
+    let output = std::process::Command::new("protoc")
+        .arg("--include_imports")
+        .arg("-o")
+        .arg(tmp.path().join("set.pb"))
+        .arg("-I")
+        .arg(tmp.path())
+        .arg("-I")
+        .arg("tests/")
+        .arg(tmp.path().join("user.proto"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let bytes = std::fs::read(tmp.path().join("set.pb")).unwrap();
+    let pool = prost_reflect::DescriptorPool::decode(&*bytes).unwrap();
+    assert_eq!(
+        "message User {\n  string name = 1 [(.acme.sensitive) = true];\n}\n",
+        pool.get_message_by_name("acme.User").unwrap().to_protobuf()
+    );
+    let config = protobuf_gen::GeneratorConfig::new().lossless(true);
+    assert_eq!(
+        "service Users {\n  rpc Get(.acme.User) returns (.acme.User) {\n    option (.acme.http) = {get: \"/users/{name}\"};\n  }\n}\n",
+        pool.get_service_by_name("acme.Users")
+            .unwrap()
+            .to_protobuf_with(&config)
+    );
+}
+
+#[test]
+fn it_renders_through_hooks() {
+    use protobuf_gen::{ast, Generator, GeneratorConfig, Print, RenderHooks};
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Hooks {
+        messages: RefCell<Vec<(String, Vec<i32>)>>,
+    }
+
+    impl RenderHooks for Hooks {
+        fn message(&self, gen: &mut Generator, message: &ast::Message) {
+            let path = gen.path().to_vec();
+            self.messages
+                .borrow_mut()
+                .push((message.name.to_string(), path));
+            message.print(gen);
+        }
+
+        fn service(&self, gen: &mut Generator, service: &ast::Service) {
+            gen.write_blank_lines(1);
+            gen.write_detached_comment(" Generated by acme.\n");
+            service.print(gen);
+        }
+    }
+
+    let source = "syntax = \"proto3\";\nmessage User {\n  message Profile {}\n  Profile profile = 1;\n}\n// Users.\nservice Users {\n  rpc GetUser(User) returns (User);\n}\n";
+    let file = read_sources(vec![("user.proto", source)], &["tests/"])
+        .file
+        .remove(0);
+    let hooks = Hooks::default();
+    let config = GeneratorConfig::default();
+    let mut gen = Generator::new(&config)
+        .source(&file)
+        .hooks(&hooks)
+        .record_source_info();
+    gen.print(&ast::File::from_descriptor(&file));
+    let (text, source_info) = gen.finish_with_source_info();
+
+    assert_eq!(text, file.to_protobuf_with_hooks(&file, &config, &hooks));
+    assert_eq!(
+        "syntax = \"proto3\";\n\n\nmessage User {\n  .User.Profile profile = 1;\n\n  message Profile {\n  }\n}\n\n// Generated by acme.\n\n// Users.\nservice Users {\n  rpc GetUser(.User) returns (.User);\n}\n",
+        text
+    );
+    assert_eq!(
+        vec![
+            ("User".to_owned(), vec![4, 0]),
+            ("Profile".to_owned(), vec![4, 0, 3, 0]),
+            ("User".to_owned(), vec![4, 0]),
+            ("Profile".to_owned(), vec![4, 0, 3, 0]),
+        ],
+        hooks.messages.into_inner()
+    );
+
+    let compiled = read_sources(vec![("user.proto", &text)], &["tests/"])
+        .file
+        .remove(0);
+    let service = |info: &prost_types::SourceCodeInfo| {
+        let location = info.location.iter().find(|l| l.path == [6, 0]).unwrap();
+        (
+            location.leading_detached_comments.clone(),
+            location.leading_comments.clone(),
+        )
+    };
+    assert_eq!(
+        service(compiled.source_code_info.as_ref().unwrap()),
+        service(&source_info)
+    );
+}
+
+#[test]
+fn it_streams_into_writers() {
+    use protobuf_gen::{GeneratorConfig, LineEnding};
+    use std::io;
+
+    let descriptor_set = read_files(&["tests/source.proto"], &["tests/"]);
+    let file = &descriptor_set.file[0];
+    for config in [
+        GeneratorConfig::new(),
+        GeneratorConfig::new()
+            .align(true)
+            .line_ending(LineEnding::CrLf)
+            .final_newline(false),
+    ] {
+        let expected = file.to_protobuf_with(file, &config);
+
+        let mut bytes = Vec::new();
+        file.write_protobuf(file, &config, &mut bytes).unwrap();
+        assert_eq!(expected, String::from_utf8(bytes).unwrap());
+
+        let mut text = String::new();
+        file.write_protobuf_fmt(file, &config, &mut text).unwrap();
+        assert_eq!(expected, text);
+    }
+
+    let tree = protobuf_gen::ast::File::from_descriptor(file);
+    assert_eq!(file.to_protobuf(file), tree.to_string());
+
+    // Writes until the sink fails, and reports its error.
+    struct Full(usize);
+    impl io::Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 < buf.len() {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"));
+            }
+            self.0 -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let err = tree
+        .write(&GeneratorConfig::new(), &mut Full(10_000))
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::WriteZero, err.kind());
+}